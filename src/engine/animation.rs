@@ -0,0 +1,71 @@
+use macroquad::math::{f32, Rect};
+
+use super::sprite::SpriteHandle;
+
+/// Sprite-sheet animation clip. Frames are rectangles of the sprite's texture, in texture pixels.
+pub struct AnimationClip {
+    pub frame: Vec<Rect>,
+    /// Duration of each frame in seconds.
+    pub frame_duration: Vec<f32>,
+    /// Looping clips start over after the last frame. One-shot clips stay on the last frame and
+    /// emit an `AnimationEvent::Finished`.
+    pub is_looping: bool,
+}
+
+impl AnimationClip {
+    pub fn new(frame: Vec<Rect>, frame_duration: Vec<f32>, is_looping: bool) -> Self {
+        assert!(
+            !frame.is_empty() && frame.len() == frame_duration.len(),
+            "Animation clips require at least one frame and a duration for each frame."
+        );
+        assert!(
+            frame_duration.iter().all(|duration| *duration > 0.0),
+            "Animation frame durations must be positive."
+        );
+
+        Self {
+            frame,
+            frame_duration,
+            is_looping,
+        }
+    }
+
+    /// Creates a clip from a row of a sprite-sheet divided in a grid of `columns` by `rows` equally
+    /// sized frames. Uses the first `frame_count` frames of the row, with the same duration each.
+    pub fn from_sheet_row(
+        sheet_size: f32::Vec2,
+        columns: u32,
+        rows: u32,
+        row: u32,
+        frame_count: u32,
+        frame_duration: f32,
+        is_looping: bool,
+    ) -> Self {
+        let frame_size = f32::Vec2::new(sheet_size.x / columns as f32, sheet_size.y / rows as f32);
+
+        let frame = (0..frame_count)
+            .map(|column| {
+                Rect::new(
+                    column as f32 * frame_size.x,
+                    row as f32 * frame_size.y,
+                    frame_size.x,
+                    frame_size.y,
+                )
+            })
+            .collect();
+        let frame_duration = vec![frame_duration; frame_count as usize];
+
+        Self::new(frame, frame_duration, is_looping)
+    }
+
+    pub fn len(&self) -> usize {
+        self.frame.len()
+    }
+}
+
+/// Animation playback events. Emitted by `SpriteMgr::update` and available until the next update.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AnimationEvent {
+    /// A one-shot clip reached the end of its last frame.
+    Finished { sprite: SpriteHandle, clip_i: usize },
+}
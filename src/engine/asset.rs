@@ -0,0 +1,86 @@
+use std::fmt;
+
+use macroquad::{color::Color, texture::Image};
+
+use super::logging::log;
+
+/// Size in pixels of the generated missing texture.
+const MISSING_TEXTURE_SIZE: u16 = 32;
+/// Size in pixels of each square of the missing texture checkerboard.
+const MISSING_TEXTURE_CHECKER_SIZE: u16 = 8;
+const MISSING_TEXTURE_COLORS: [Color; 2] = [
+    Color::new(1.0, 0.0, 1.0, 1.0),
+    Color::new(0.0, 0.0, 0.0, 1.0),
+];
+
+/// Asset that couldn't be loaded.
+#[derive(Clone, Debug)]
+pub struct AssetError {
+    pub kind: AssetKind,
+    /// Path of the asset, or name if it has no file of its own.
+    pub path: String,
+    pub message: String,
+}
+
+impl AssetError {
+    pub fn new<S: AsRef<str>>(kind: AssetKind, path: &str, message: S) -> Self {
+        Self {
+            kind,
+            path: String::from(path),
+            message: String::from(message.as_ref()),
+        }
+    }
+}
+
+impl fmt::Display for AssetError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?} \"{}\": {}", self.kind, self.path, self.message)
+    }
+}
+
+impl std::error::Error for AssetError {}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum AssetKind {
+    Texture,
+    Tileset,
+    Tile,
+    Map,
+    Bindings,
+}
+
+/// Generates the magenta and black checkerboard image used in place of missing textures.
+pub fn missing_texture_image() -> Image {
+    let mut image = Image::gen_image_color(
+        MISSING_TEXTURE_SIZE,
+        MISSING_TEXTURE_SIZE,
+        MISSING_TEXTURE_COLORS[0],
+    );
+
+    for y in 0..MISSING_TEXTURE_SIZE {
+        for x in 0..MISSING_TEXTURE_SIZE {
+            let checker = (x / MISSING_TEXTURE_CHECKER_SIZE + y / MISSING_TEXTURE_CHECKER_SIZE) % 2;
+            image.set_pixel(x as u32, y as u32, MISSING_TEXTURE_COLORS[checker as usize]);
+        }
+    }
+
+    image
+}
+
+/// Logs every asset that failed to load, or a single line if there are none.
+pub fn log_report<'a, I: IntoIterator<Item = &'a AssetError>>(errors: I) {
+    let errors: Vec<&AssetError> = errors.into_iter().collect();
+
+    if errors.is_empty() {
+        log::debug("Asset report: every asset loaded");
+        return;
+    }
+
+    log::warning(format!(
+        "Asset report: {} assets missing or failed to load",
+        errors.len()
+    ));
+    for error in errors {
+        log::warning(format!("  {error}"));
+    }
+}
@@ -0,0 +1,359 @@
+use macroquad::{color, texture::Image};
+
+/// Packs images into texture atlas pages using skyline bottom-left rectangle bin packing.
+///
+/// Packing only works with image sizes and CPU side `Image` data, so it doesn't require a GPU
+/// context. `Texture2dMgr` uploads the resulting pages.
+pub struct AtlasPacker {
+    /// Width and maximum height of every page.
+    page_size: u32,
+    /// Empty space left at the right and bottom of every packed image.
+    padding: u32,
+}
+
+impl AtlasPacker {
+    pub fn new(page_size: u32, padding: u32) -> Self {
+        Self { page_size, padding }
+    }
+
+    /// Finds a place for each of the `sizes` (width, height) in as few pages as possible.
+    ///
+    /// Returns a placement for each size in the same order. Sizes that don't fit in an empty page
+    /// get `None`.
+    pub fn pack(&self, sizes: &[(u32, u32)]) -> Vec<Option<AtlasPlacement>> {
+        let mut placements = vec![None; sizes.len()];
+        let mut pages: Vec<Skyline> = Vec::new();
+
+        // Packing taller rectangles first gives flatter skylines
+        let mut order: Vec<usize> = (0..sizes.len()).collect();
+        order.sort_by(|a, b| {
+            let (a_w, a_h) = sizes[*a];
+            let (b_w, b_h) = sizes[*b];
+            b_h.cmp(&a_h).then(b_w.cmp(&a_w))
+        });
+
+        for i in order {
+            let (width, height) = sizes[i];
+            let padded_width = width + self.padding;
+            let padded_height = height + self.padding;
+
+            if width == 0 || height == 0 {
+                continue;
+            }
+            if padded_width > self.page_size || padded_height > self.page_size {
+                continue;
+            }
+
+            let mut placement = None;
+            for (page, skyline) in pages.iter_mut().enumerate() {
+                if let Some((x, y)) = skyline.insert(padded_width, padded_height) {
+                    placement = Some((page, x, y));
+                    break;
+                }
+            }
+
+            let (page, x, y) = match placement {
+                Some(placement) => placement,
+                None => {
+                    let mut skyline = Skyline::new(self.page_size);
+                    let (x, y) = skyline.insert(padded_width, padded_height).unwrap();
+                    pages.push(skyline);
+                    (pages.len() - 1, x, y)
+                }
+            };
+
+            placements[i] = Some(AtlasPlacement {
+                page,
+                x,
+                y,
+                width,
+                height,
+            });
+        }
+
+        placements
+    }
+
+    /// Packs the images and copies them into newly created page images.
+    ///
+    /// Pages are `page_size` wide and as tall as their content. Returns the pages and the
+    /// placement of each image, in the same order as `images`.
+    pub fn build_pages(&self, images: &[&Image]) -> (Vec<Image>, Vec<Option<AtlasPlacement>>) {
+        let sizes: Vec<(u32, u32)> = images
+            .iter()
+            .map(|image| (image.width as u32, image.height as u32))
+            .collect();
+        let placements = self.pack(&sizes);
+
+        let mut page_height: Vec<u32> = Vec::new();
+        for placement in placements.iter().flatten() {
+            if placement.page >= page_height.len() {
+                page_height.resize(placement.page + 1, 0);
+            }
+            page_height[placement.page] =
+                u32::max(page_height[placement.page], placement.y + placement.height);
+        }
+
+        let mut pages: Vec<Image> = page_height
+            .iter()
+            .map(|height| {
+                Image::gen_image_color(self.page_size as u16, *height as u16, color::BLANK)
+            })
+            .collect();
+
+        for (image, placement) in images.iter().zip(placements.iter()) {
+            let placement = match placement {
+                Some(placement) => placement,
+                None => continue,
+            };
+
+            copy_image(image, &mut pages[placement.page], placement.x, placement.y);
+        }
+
+        (pages, placements)
+    }
+}
+
+/// Location of a packed image in the atlas.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct AtlasPlacement {
+    pub page: usize,
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+/// Top edge of the packed area of a page, as a list of horizontal segments sorted by `x`.
+struct Skyline {
+    page_size: u32,
+    segments: Vec<SkylineSegment>,
+}
+
+#[derive(Clone, Copy)]
+struct SkylineSegment {
+    x: u32,
+    y: u32,
+    width: u32,
+}
+
+impl Skyline {
+    fn new(page_size: u32) -> Self {
+        let segments = vec![SkylineSegment {
+            x: 0,
+            y: 0,
+            width: page_size,
+        }];
+
+        Self {
+            page_size,
+            segments,
+        }
+    }
+
+    /// Places a rectangle at the lowest (then leftmost) available position and returns it.
+    fn insert(&mut self, width: u32, height: u32) -> Option<(u32, u32)> {
+        let mut best: Option<(usize, u32, u32)> = None;
+
+        for i in 0..self.segments.len() {
+            let y = match self.fit(i, width, height) {
+                Some(y) => y,
+                None => continue,
+            };
+
+            let x = self.segments[i].x;
+            let is_better = match best {
+                Some((_, best_x, best_y)) => y < best_y || (y == best_y && x < best_x),
+                None => true,
+            };
+            if is_better {
+                best = Some((i, x, y));
+            }
+        }
+
+        let (segment_i, x, y) = best?;
+        self.add_segment(segment_i, x, y + height, width);
+
+        Some((x, y))
+    }
+
+    /// Returns the height a rectangle starting at segment `segment_i` would rest at.
+    fn fit(&self, segment_i: usize, width: u32, height: u32) -> Option<u32> {
+        let x = self.segments[segment_i].x;
+        if x + width > self.page_size {
+            return None;
+        }
+
+        let mut y = 0;
+        let mut remaining_width = width as i64;
+        let mut i = segment_i;
+        while remaining_width > 0 {
+            let segment = self.segments.get(i)?;
+            y = u32::max(y, segment.y);
+            if y + height > self.page_size {
+                return None;
+            }
+            remaining_width -= segment.width as i64;
+            i += 1;
+        }
+
+        Some(y)
+    }
+
+    /// Adds a new segment at `segment_i` and shrinks or removes the segments below it.
+    fn add_segment(&mut self, segment_i: usize, x: u32, y: u32, width: u32) {
+        self.segments
+            .insert(segment_i, SkylineSegment { x, y, width });
+
+        let end = x + width;
+        let i = segment_i + 1;
+        while i < self.segments.len() {
+            let segment = &mut self.segments[i];
+            if segment.x >= end {
+                break;
+            }
+
+            let segment_end = segment.x + segment.width;
+            if segment_end <= end {
+                self.segments.remove(i);
+                continue;
+            }
+
+            segment.width = segment_end - end;
+            segment.x = end;
+            break;
+        }
+
+        // Merge neighbouring segments at the same height
+        let mut i = 0;
+        while i + 1 < self.segments.len() {
+            if self.segments[i].y == self.segments[i + 1].y {
+                self.segments[i].width += self.segments[i + 1].width;
+                self.segments.remove(i + 1);
+            } else {
+                i += 1;
+            }
+        }
+    }
+}
+
+/// Copies the whole `source` image into `target` at (`x`, `y`), row by row.
+fn copy_image(source: &Image, target: &mut Image, x: u32, y: u32) {
+    let source_row_len = source.width as usize * 4;
+    let target_row_len = target.width as usize * 4;
+
+    for row in 0..source.height as usize {
+        let source_start = row * source_row_len;
+        let target_start = (y as usize + row) * target_row_len + x as usize * 4;
+
+        target.bytes[target_start..target_start + source_row_len]
+            .copy_from_slice(&source.bytes[source_start..source_start + source_row_len]);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PAGE_SIZE: u32 = 128;
+    const PADDING: u32 = 2;
+
+    fn assert_valid(sizes: &[(u32, u32)], placements: &[Option<AtlasPlacement>]) {
+        assert_eq!(sizes.len(), placements.len());
+
+        for (i, placement) in placements.iter().enumerate() {
+            let a = match placement {
+                Some(placement) => placement,
+                None => continue,
+            };
+            assert_eq!((a.width, a.height), sizes[i]);
+            assert!(a.x + a.width + PADDING <= PAGE_SIZE);
+            assert!(a.y + a.height + PADDING <= PAGE_SIZE);
+
+            for b in placements[i + 1..].iter().flatten() {
+                let is_apart = a.page != b.page
+                    || a.x + a.width + PADDING <= b.x
+                    || b.x + b.width + PADDING <= a.x
+                    || a.y + a.height + PADDING <= b.y
+                    || b.y + b.height + PADDING <= a.y;
+                assert!(is_apart, "{a:?} overlaps {b:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn pack_places_without_overlaps_inside_the_page() {
+        // Mixed sizes, from slivers to half a page
+        let sizes: Vec<(u32, u32)> = (0..64)
+            .map(|i| (4 + (i * 37) % 60, 4 + (i * 23) % 60))
+            .collect();
+
+        let placements = AtlasPacker::new(PAGE_SIZE, PADDING).pack(&sizes);
+
+        assert!(placements.iter().all(Option::is_some));
+        assert_valid(&sizes, &placements);
+    }
+
+    #[test]
+    fn pack_spills_onto_new_pages() {
+        // Only four padded 60x60 images fit in a page
+        let sizes = [(60, 60); 9];
+
+        let placements = AtlasPacker::new(PAGE_SIZE, PADDING).pack(&sizes);
+
+        assert_valid(&sizes, &placements);
+        let page_count = placements.iter().flatten().map(|p| p.page).max().unwrap() + 1;
+        assert_eq!(page_count, 3);
+        for page in 0..page_count {
+            let count = placements
+                .iter()
+                .flatten()
+                .filter(|p| p.page == page)
+                .count();
+            assert!(count <= 4);
+        }
+    }
+
+    #[test]
+    fn pack_skips_oversize_and_empty_images() {
+        let sizes = [
+            (PAGE_SIZE + 1, 8),
+            (16, 16),
+            (8, PAGE_SIZE),
+            (PAGE_SIZE - PADDING, PAGE_SIZE - PADDING),
+            (0, 16),
+        ];
+
+        let placements = AtlasPacker::new(PAGE_SIZE, PADDING).pack(&sizes);
+
+        assert_valid(&sizes, &placements);
+        assert!(placements[0].is_none());
+        assert!(placements[1].is_some());
+        // Doesn't fit with its padding
+        assert!(placements[2].is_none());
+        // A full page of its own
+        assert!(placements[3].is_some());
+        assert_ne!(placements[1].unwrap().page, placements[3].unwrap().page);
+        assert!(placements[4].is_none());
+    }
+
+    #[test]
+    fn build_pages_copies_images_to_their_placement() {
+        let red = Image::gen_image_color(8, 4, color::RED);
+        let blue = Image::gen_image_color(4, 8, color::BLUE);
+
+        let (pages, placements) = AtlasPacker::new(PAGE_SIZE, PADDING).build_pages(&[&red, &blue]);
+
+        assert_eq!(pages.len(), 1);
+        assert_eq!(pages[0].width as u32, PAGE_SIZE);
+        for (image, placement) in [&red, &blue].iter().zip(placements.iter()) {
+            let placement = placement.unwrap();
+            for y in 0..placement.height {
+                for x in 0..placement.width {
+                    let pixel = pages[0].get_pixel(placement.x + x, placement.y + y);
+                    assert_eq!(pixel, image.get_pixel(x, y));
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,78 @@
+use std::time::{Duration, Instant};
+
+use macroquad::{
+    math::{f32, Rect},
+    rand,
+};
+
+use crate::engine::logging::log;
+
+use super::collider::{ColliderHandle, ColliderMgr, CollisionLayer, CollisionMask, HitList};
+
+/// Collider counts to benchmark.
+const BENCHMARK_COLLIDER_COUNTS: [usize; 3] = [1000, 4000, 8000];
+const BENCHMARK_COLLIDER_SIZE: f32 = 16.0;
+/// World area per collider. Keeps the density, and the amount of overlaps, the same for every
+/// collider count.
+const BENCHMARK_AREA_PER_COLLIDER: f32 = 48.0 * 48.0;
+const BENCHMARK_SEED: u64 = 55;
+
+/// Compares bounding box queries through the broadphase against testing every collider.
+///
+/// Every collider of a randomly filled world is queried once, as `PlayerUnitMgr` does each frame
+/// for its moving units. Results are logged. Run the game with `--collider-benchmark` to run it.
+pub fn run_broadphase_benchmark() {
+    for collider_count in BENCHMARK_COLLIDER_COUNTS {
+        let mut collider_mgr = ColliderMgr::new();
+        let colliders = add_random_colliders(&mut collider_mgr, collider_count);
+
+        collider_mgr.set_broadphase_enabled(false);
+        let (brute_force_time, brute_force_hits) = query_every_collider(&collider_mgr, &colliders);
+
+        collider_mgr.set_broadphase_enabled(true);
+        let (broadphase_time, broadphase_hits) = query_every_collider(&collider_mgr, &colliders);
+
+        if brute_force_hits != broadphase_hits {
+            log::error(format!(
+                "Broadphase found {broadphase_hits} hits, brute force {brute_force_hits}"
+            ));
+        }
+
+        log::debug(format!(
+            "{collider_count} colliders, {broadphase_hits} hits: brute force {:.2}ms, broadphase {:.2}ms ({:.1}x)",
+            brute_force_time.as_secs_f64() * 1000.0,
+            broadphase_time.as_secs_f64() * 1000.0,
+            brute_force_time.as_secs_f64() / broadphase_time.as_secs_f64()
+        ));
+    }
+}
+
+fn add_random_colliders(collider_mgr: &mut ColliderMgr, count: usize) -> Vec<ColliderHandle> {
+    rand::srand(BENCHMARK_SEED);
+    let world_size = f32::sqrt(BENCHMARK_AREA_PER_COLLIDER * count as f32);
+
+    (0..count)
+        .map(|_| {
+            let x = rand::gen_range(0.0, world_size);
+            let y = rand::gen_range(0.0, world_size);
+            let bbox = Rect::new(x, y, BENCHMARK_COLLIDER_SIZE, BENCHMARK_COLLIDER_SIZE);
+            collider_mgr.add(bbox, CollisionLayer::Unit, CollisionMask::ALL)
+        })
+        .collect()
+}
+
+/// Returns the time taken and the total amount of hits.
+fn query_every_collider(
+    collider_mgr: &ColliderMgr,
+    colliders: &[ColliderHandle],
+) -> (Duration, usize) {
+    let mut hits: HitList = HitList::new();
+    let mut hit_count = 0;
+
+    let start = Instant::now();
+    for collider in colliders.iter().copied() {
+        hit_count += collider_mgr.intersect_bbox(collider, &f32::Vec2::ZERO, &mut hits);
+    }
+
+    (start.elapsed(), hit_count)
+}
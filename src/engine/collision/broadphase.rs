@@ -0,0 +1,100 @@
+use std::collections::HashMap;
+
+use macroquad::math::{f32, IVec2, Rect};
+
+/// Uniform grid broadphase. Colliders are registered in every grid cell their bounding box
+/// overlaps, so queries only have to test the colliders in the cells overlapped by the query.
+pub struct SpatialGrid {
+    cell_size: f32,
+    /// Collider indices in each non-empty cell.
+    cells: HashMap<IVec2, Vec<usize>>,
+    /// First and last cells covered by each collider, `None` if it's not in the grid.
+    collider_cells: Vec<Option<(IVec2, IVec2)>>,
+}
+
+impl SpatialGrid {
+    pub fn new(cell_size: f32, capacity: usize) -> Self {
+        Self {
+            cell_size,
+            cells: HashMap::with_capacity(capacity),
+            collider_cells: Vec::with_capacity(capacity),
+        }
+    }
+
+    /// Inserts or moves a collider.
+    pub fn update(&mut self, index: usize, bbox: &Rect) {
+        if index >= self.collider_cells.len() {
+            self.collider_cells.resize(index + 1, None);
+        }
+
+        let new_cells = self.cell_range(bbox);
+        if self.collider_cells[index] == Some(new_cells) {
+            return;
+        }
+
+        self.remove(index);
+
+        let (min, max) = new_cells;
+        for y in min.y..=max.y {
+            for x in min.x..=max.x {
+                self.cells.entry(IVec2::new(x, y)).or_default().push(index);
+            }
+        }
+        self.collider_cells[index] = Some(new_cells);
+    }
+
+    pub fn remove(&mut self, index: usize) {
+        let (min, max) = match self.collider_cells.get_mut(index).and_then(Option::take) {
+            Some(cells) => cells,
+            None => return,
+        };
+
+        for y in min.y..=max.y {
+            for x in min.x..=max.x {
+                let cell = IVec2::new(x, y);
+                let indices = self.cells.get_mut(&cell).unwrap();
+                let position = indices.iter().position(|i| *i == index).unwrap();
+                indices.swap_remove(position);
+                if indices.is_empty() {
+                    self.cells.remove(&cell);
+                }
+            }
+        }
+    }
+
+    /// Calls `f` once for each collider whose cells overlap the `rect` cells. Candidates may not
+    /// overlap `rect` themselves, so they still have to be tested.
+    pub fn query<F: FnMut(usize)>(&self, rect: &Rect, mut f: F) {
+        let (min, max) = self.cell_range(rect);
+
+        for y in min.y..=max.y {
+            for x in min.x..=max.x {
+                let cell = IVec2::new(x, y);
+                let indices = match self.cells.get(&cell) {
+                    Some(indices) => indices,
+                    None => continue,
+                };
+
+                for index in indices.iter().copied() {
+                    // Colliders spanning several cells are only reported from the first cell they
+                    // share with the query, so no duplicates are reported.
+                    let (collider_min, _) = self.collider_cells[index].unwrap();
+                    if collider_min.max(min) == cell {
+                        f(index);
+                    }
+                }
+            }
+        }
+    }
+
+    fn cell_range(&self, rect: &Rect) -> (IVec2, IVec2) {
+        // Selection boxes can have negative sizes while being dragged
+        let min = f32::Vec2::new(rect.x.min(rect.x + rect.w), rect.y.min(rect.y + rect.h));
+        let max = f32::Vec2::new(rect.x.max(rect.x + rect.w), rect.y.max(rect.y + rect.h));
+
+        (
+            (min / self.cell_size).floor().as_ivec2(),
+            (max / self.cell_size).floor().as_ivec2(),
+        )
+    }
+}
@@ -3,134 +3,391 @@ use macroquad::{
     math::{f32, Rect},
 };
 
-use crate::engine::sprite::SpriteMgr;
+use crate::engine::{
+    handle::{insert_at, Handle, HandleAllocator},
+    sprite::{SpriteHandle, SpriteMgr},
+};
+
+use super::{
+    broadphase::SpatialGrid,
+    shape::{self, ColliderShape, ConvexPolygon, WorldShape},
+};
 
 const MAX_COLLIDER_COUNT: usize = 256;
+/// Broadphase grid cell size. Should be around the size of the most common colliders.
+const BROADPHASE_CELL_SIZE: f32 = 64.0;
 /// Determines the maximum amount of collisions that will be returned from a collision test.
+/// Queries stop once a `HitList` is full.
 pub const MAX_COLLISION_COUNT: usize = 16;
+/// Expected maximum amount of colliders overlapping triggers at once.
+const MAX_TRIGGER_PAIR_COUNT: usize = 64;
+/// Overlap, in world units, still treated as touching by sweeps. Boxes stopped at contact can end
+/// up overlapping by float error, and must still block motion into each other.
+const CONTACT_SKIN: f32 = 0.01;
 
 /// Manages bounding boxes and collisions.
-/// TODO: allow passing in a list of colliders to test against.
-/// TODO: check `is_active` field in collision tests.
+///
+/// Colliders have a `ColliderShape` placed on their bounding box. Pairs of `Rect` shapes are
+/// tested as AABBs, every other pair uses the separating axis theorem. The bounding box is still
+/// used for the broadphase, sweeps and casts.
+///
+/// Each collider belongs to a `CollisionLayer` and has a `CollisionMask` with the layers it
+/// collides with. Queries made by a collider only report colliders in its mask.
+///
+/// Colliders are referenced with generational `ColliderHandle`s. Removed colliders free their slot
+/// for new ones.
+///
+/// Queries only test the colliders found by a uniform grid broadphase, which is updated whenever
+/// a bounding box changes.
+///
+/// Triggers are colliders that never block movement. Queries skip them, instead
+/// `update_triggers` tracks what overlaps them and emits `TriggerEvent`s.
 pub struct ColliderMgr {
-    pub bbox: Vec<Rect>,
+    handles: HandleAllocator<ColliderMgr>,
+
+    bbox: Vec<Rect>,
+    shape: Vec<ColliderShape>,
 
     /// Whether to render the bounding box or not.
-    pub render_bbox: Vec<bool>,
+    render_bbox: Vec<bool>,
     is_active: Vec<bool>,
+    layer: Vec<CollisionLayer>,
+    mask: Vec<CollisionMask>,
+    is_trigger: Vec<bool>,
+
+    /// Overlapping (trigger, other collider) pairs found by the last trigger update.
+    trigger_pairs: Vec<(ColliderHandle, ColliderHandle)>,
+    /// Pairs from the update before, kept to reuse its allocation.
+    previous_trigger_pairs: Vec<(ColliderHandle, ColliderHandle)>,
+    trigger_events: Vec<TriggerEvent>,
+
+    broadphase: SpatialGrid,
+    /// When disabled, queries test every collider. Used to benchmark the broadphase.
+    is_broadphase_enabled: bool,
+
+    /// Changes whenever a collider in `STATIC_MASK` is added, removed, moved, activated or
+    /// deactivated.
+    static_version: u32,
 }
 
 impl ColliderMgr {
     pub fn new() -> Self {
         Self {
+            handles: HandleAllocator::with_capacity(MAX_COLLIDER_COUNT),
+
             bbox: Vec::with_capacity(MAX_COLLIDER_COUNT),
+            shape: Vec::with_capacity(MAX_COLLIDER_COUNT),
 
             render_bbox: Vec::with_capacity(MAX_COLLIDER_COUNT),
             is_active: Vec::with_capacity(MAX_COLLIDER_COUNT),
+            layer: Vec::with_capacity(MAX_COLLIDER_COUNT),
+            mask: Vec::with_capacity(MAX_COLLIDER_COUNT),
+            is_trigger: Vec::with_capacity(MAX_COLLIDER_COUNT),
+
+            trigger_pairs: Vec::with_capacity(MAX_TRIGGER_PAIR_COUNT),
+            previous_trigger_pairs: Vec::with_capacity(MAX_TRIGGER_PAIR_COUNT),
+            trigger_events: Vec::with_capacity(MAX_TRIGGER_PAIR_COUNT),
+
+            broadphase: SpatialGrid::new(BROADPHASE_CELL_SIZE, MAX_COLLIDER_COUNT),
+            is_broadphase_enabled: true,
+
+            static_version: 0,
         }
     }
 
+    /// Amount of collider slots, including removed ones.
     pub fn len(&self) -> usize {
         self.bbox.len()
     }
 
-    pub fn is_active(&self, index: usize) -> bool {
-        self.is_active[index]
+    pub fn is_active(&self, collider: ColliderHandle) -> bool {
+        self.is_active[self.handles.index(collider)]
+    }
+
+    pub fn set_active(&mut self, collider: ColliderHandle, is_active: bool) {
+        let index = self.handles.index(collider);
+        if self.is_active[index] != is_active {
+            self.is_active[index] = is_active;
+            self.mark_static_changed(index);
+        }
+    }
+
+    pub fn add(
+        &mut self,
+        bbox: Rect,
+        layer: CollisionLayer,
+        mask: CollisionMask,
+    ) -> ColliderHandle {
+        let handle = self.handles.allocate();
+        let i = handle.index();
+
+        insert_at(&mut self.bbox, i, bbox);
+        insert_at(&mut self.shape, i, ColliderShape::Rect);
+        insert_at(&mut self.render_bbox, i, false);
+        insert_at(&mut self.is_active, i, true);
+        insert_at(&mut self.layer, i, layer);
+        insert_at(&mut self.mask, i, mask);
+        insert_at(&mut self.is_trigger, i, false);
+
+        self.broadphase.update(i, &bbox);
+        self.mark_static_changed(i);
+
+        handle
+    }
+
+    /// Adds a circle collider.
+    pub fn add_circle(
+        &mut self,
+        center: f32::Vec2,
+        radius: f32,
+        layer: CollisionLayer,
+        mask: CollisionMask,
+    ) -> ColliderHandle {
+        let bbox = Rect::new(
+            center.x - radius,
+            center.y - radius,
+            radius * 2.0,
+            radius * 2.0,
+        );
+        let collider = self.add(bbox, layer, mask);
+        self.set_shape(collider, ColliderShape::Circle);
+
+        collider
+    }
+
+    /// Adds a convex polygon collider, with vertices in world space. Its bounding box is the
+    /// polygon bounds.
+    pub fn add_polygon(
+        &mut self,
+        polygon: ConvexPolygon,
+        layer: CollisionLayer,
+        mask: CollisionMask,
+    ) -> ColliderHandle {
+        let bbox = polygon.bounds();
+        let collider = self.add(bbox, layer, mask);
+        self.set_shape(
+            collider,
+            ColliderShape::Polygon(polygon.offset(-bbox.point())),
+        );
+
+        collider
+    }
+
+    pub fn shape(&self, collider: ColliderHandle) -> ColliderShape {
+        self.shape[self.handles.index(collider)]
+    }
+
+    pub fn set_shape(&mut self, collider: ColliderHandle, shape: ColliderShape) {
+        let index = self.handles.index(collider);
+        self.shape[index] = shape;
+    }
+
+    /// Removes a collider. Its slot will be reused by new colliders.
+    pub fn remove(&mut self, collider: ColliderHandle) {
+        let index = self.handles.index(collider);
+        self.is_active[index] = false;
+        self.render_bbox[index] = false;
+        self.broadphase.remove(index);
+        self.mark_static_changed(index);
+
+        self.handles.free(collider);
+    }
+
+    pub fn is_valid(&self, collider: ColliderHandle) -> bool {
+        self.handles.is_valid(collider)
+    }
+
+    pub fn bbox(&self, collider: ColliderHandle) -> Rect {
+        self.bbox[self.handles.index(collider)]
+    }
+
+    pub fn set_bbox(&mut self, collider: ColliderHandle, bbox: Rect) {
+        let index = self.handles.index(collider);
+        self.bbox[index] = bbox;
+        self.broadphase.update(index, &bbox);
+        self.mark_static_changed(index);
+    }
+
+    pub fn set_broadphase_enabled(&mut self, is_enabled: bool) {
+        self.is_broadphase_enabled = is_enabled;
+    }
+
+    /// Calls `f` with the index of every collider that may overlap `rect`.
+    fn for_each_candidate<F: FnMut(usize)>(&self, rect: &Rect, f: F) {
+        if self.is_broadphase_enabled {
+            self.broadphase.query(rect, f);
+        } else {
+            (0..self.len()).for_each(f);
+        }
+    }
+
+    pub fn layer(&self, collider: ColliderHandle) -> CollisionLayer {
+        self.layer[self.handles.index(collider)]
     }
 
-    pub fn set_active(&mut self, index: usize, is_active: bool) {
-        self.is_active[index] = is_active;
+    pub fn set_layer(&mut self, collider: ColliderHandle, layer: CollisionLayer) {
+        let index = self.handles.index(collider);
+        self.mark_static_changed(index);
+        self.layer[index] = layer;
+        self.mark_static_changed(index);
     }
 
-    pub fn add(&mut self, bbox: Rect) -> usize {
-        self.bbox.push(bbox);
-        self.render_bbox.push(false);
-        self.is_active.push(true);
+    pub fn set_mask(&mut self, collider: ColliderHandle, mask: CollisionMask) {
+        let index = self.handles.index(collider);
+        self.mask[index] = mask;
+    }
+
+    /// Flags a collider as a trigger. Triggers detect the colliders in their mask through
+    /// `TriggerEvent`s.
+    pub fn set_trigger(&mut self, collider: ColliderHandle, is_trigger: bool) {
+        let index = self.handles.index(collider);
+        self.is_trigger[index] = is_trigger;
+    }
+
+    /// Finds the colliders overlapping each active trigger and emits the events of the pairs that
+    /// started, kept or stopped overlapping since the last call. Colliders in a trigger mask are
+    /// detected even if they're triggers themselves.
+    ///
+    /// Clears the events from the last update, so events must be read before the next one. Should
+    /// be called once per frame, after everything moved.
+    pub fn update_triggers(&mut self) {
+        self.trigger_events.clear();
+
+        // Taken out while filled, as queries borrow the manager
+        let mut trigger_pairs = std::mem::take(&mut self.previous_trigger_pairs);
+        trigger_pairs.clear();
+
+        for trigger_i in 0..self.len() {
+            if !self.is_trigger[trigger_i] || !self.is_active[trigger_i] {
+                continue;
+            }
+
+            let trigger = self.handles.handle(trigger_i);
+            let mask = self.mask[trigger_i];
+            let trigger_bbox = self.bbox[trigger_i];
+
+            self.for_each_candidate(&trigger_bbox, |i| {
+                if i == trigger_i || !self.is_active[i] || !mask.contains(self.layer[i]) {
+                    return;
+                }
+
+                if let Some(hit) = self.intersect_single(trigger_i, &trigger_bbox, i) {
+                    trigger_pairs.push((trigger, hit.collider));
+                }
+            });
+        }
+
+        self.previous_trigger_pairs = std::mem::replace(&mut self.trigger_pairs, trigger_pairs);
+
+        for (trigger, other) in self.trigger_pairs.iter().copied() {
+            let event = match self.previous_trigger_pairs.contains(&(trigger, other)) {
+                true => TriggerEvent::Stay { trigger, other },
+                false => TriggerEvent::Enter { trigger, other },
+            };
+            self.trigger_events.push(event);
+        }
+
+        // Includes pairs of removed and deactivated colliders
+        for (trigger, other) in self.previous_trigger_pairs.iter().copied() {
+            if !self.trigger_pairs.contains(&(trigger, other)) {
+                self.trigger_events
+                    .push(TriggerEvent::Exit { trigger, other });
+            }
+        }
+    }
 
-        let index = self.len() - 1;
+    pub fn trigger_events(&self) -> &[TriggerEvent] {
+        &self.trigger_events
+    }
 
-        index
+    pub fn set_render_bbox(&mut self, collider: ColliderHandle, render_bbox: bool) {
+        let index = self.handles.index(collider);
+        self.render_bbox[index] = render_bbox;
     }
 
     /// Adds a new collider with a sprite's position and size by default, or a predefined `bbox` if
     /// passed as argument.
     pub fn add_from_sprite(
         &mut self,
-        sprite_i: usize,
+        sprite: SpriteHandle,
         bbox: Option<Rect>,
+        layer: CollisionLayer,
+        mask: CollisionMask,
         sprite_mgr: &mut SpriteMgr,
-    ) -> usize {
-        let bbox = bbox.unwrap_or(Self::create_rect_for_sprite(sprite_i, sprite_mgr));
+    ) -> ColliderHandle {
+        let bbox = bbox.unwrap_or(Self::create_rect_for_sprite(sprite, sprite_mgr));
 
-        self.add(bbox)
+        self.add(bbox, layer, mask)
     }
 
-    /// Creates a new `Rect` with the sprite's position and size that can be used as a bounding box.
-    pub fn create_rect_for_sprite(sprite_i: usize, sprite_mgr: &mut SpriteMgr) -> Rect {
-        let position = &sprite_mgr.position(sprite_i);
-        let size = sprite_mgr.scaled_size(sprite_i);
-
-        Rect::new(position.x, position.y, size.x, size.y)
+    /// Creates a new `Rect` with the sprite's bounds that can be used as a bounding box.
+    pub fn create_rect_for_sprite(sprite: SpriteHandle, sprite_mgr: &mut SpriteMgr) -> Rect {
+        sprite_mgr.bounds(sprite)
     }
 
-    /// Tests a `point` for intersection with other colliders.
+    /// Tests a `point` for intersection with the colliders in the mask of `collider`.
+    ///
+    /// `hits` is cleared and filled with as many hits as it can hold.
     ///
     /// Returns the collision count.
-    pub fn intersect_point(
+    pub fn intersect_point<const N: usize>(
         &self,
-        index: usize,
+        collider: ColliderHandle,
         point: &f32::Vec2,
-        hit_buffer: &mut Vec<Hit>,
+        hits: &mut HitList<Hit, N>,
     ) -> usize {
-        let mut collision_count: usize = 0;
-
-        for i in 0..self.len() {
-            if i == index || !self.is_active(i) {
-                continue;
-            }
-
-            let mut hit = &mut hit_buffer[collision_count];
+        let index = self.handles.index(collider);
+        hits.clear();
 
-            let bbox = self.bbox[i];
-            let center = bbox.center();
+        let mask = self.mask[index];
 
-            if !Self::intersect_point_single(&point, &bbox, &center, i, &mut hit) {
-                continue;
+        let point_rect = Rect::new(point.x, point.y, 0.0, 0.0);
+        self.for_each_candidate(&point_rect, |i| {
+            if i == index || !self.is_active[i] || self.is_trigger[i] || hits.is_full() {
+                return;
+            }
+            if !mask.contains(self.layer[i]) {
+                return;
             }
 
-            collision_count += 1;
+            let bbox = self.bbox[i];
+            let test_collider = self.handles.handle(i);
 
-            if collision_count > MAX_COLLISION_COUNT {
-                break;
+            let hit = match self.shape[i] {
+                ColliderShape::Rect => {
+                    Self::intersect_point_single(point, &bbox, &bbox.center(), test_collider)
+                }
+                shape => Self::intersect_point_shape(point, &shape, &bbox, test_collider),
+            };
+            if let Some(hit) = hit {
+                hits.push(hit);
             }
-        }
+        });
 
-        collision_count
+        hits.len()
     }
 
+    /// Tests a `point` against a single bounding box. Returns `None` if they don't intersect.
     pub fn intersect_point_single(
         point: &f32::Vec2,
         test_bbox: &Rect,
         test_center: &f32::Vec2,
-        test_collider_i: usize,
-        hit: &mut Hit,
-    ) -> bool {
+        test_collider: ColliderHandle,
+    ) -> Option<Hit> {
         let dx = point.x - test_center.x;
         let px = (test_bbox.w / 2.0) - f32::abs(dx);
         if px <= 0.0 {
-            hit.is_colliding = false;
-            return false;
+            return None;
         }
 
         let dy = point.y - test_center.y;
         let py = (test_bbox.h / 2.0) - f32::abs(dy);
         if py <= 0.0 {
-            hit.is_colliding = false;
-            return false;
+            return None;
         }
 
-        hit.is_colliding = true;
-        hit.collider_i = test_collider_i;
+        let mut hit = Hit::EMPTY;
+        hit.collider = test_collider;
         if px < py {
             let sx = f32::signum(dx);
             hit.delta.x = px * sx;
@@ -149,61 +406,395 @@ impl ColliderMgr {
             hit.position.y = test_center.y + (test_bbox.h / 2.0) * sy;
         }
 
-        true
+        Some(hit)
     }
 
-    /// AABB intersection test.
+    /// Tests a `point` against a circle or polygon shape, giving the same hit data as
+    /// `intersect_point_single`.
+    fn intersect_point_shape(
+        point: &f32::Vec2,
+        test_shape: &ColliderShape,
+        test_bbox: &Rect,
+        test_collider: ColliderHandle,
+    ) -> Option<Hit> {
+        let point_shape = WorldShape::Circle {
+            center: *point,
+            radius: 0.0,
+        };
+        let overlap = shape::intersect(&point_shape, &WorldShape::new(test_shape, test_bbox))?;
+
+        // Point hits face away from the tested shape
+        let delta = -overlap.normal * overlap.depth;
+        Some(Hit {
+            collider: test_collider,
+            position: *point + delta,
+            delta,
+            normal: -overlap.normal,
+        })
+    }
+
+    /// Shape intersection test.
+    ///
+    /// Checks for overlaps between the shape of `collider` and the colliders in its mask.
+    /// Gives the axis of least overlap as the contact point in the `hit.delta` field. This delta
+    /// can be used to push the colliding shape out of the nearest edge.
     ///
-    /// Checks for overlaps between bounding boxes. Gives the axis of least overlap as the contact
-    /// point in the `hit.delta` field. This delta can be used to push the colliding box out of the
-    /// nearest edge.
+    /// `hits` is cleared and filled with as many hits as it can hold.
     ///
     /// Returns the collision count.
-    pub fn intersect_bbox(
+    pub fn intersect_bbox<const N: usize>(
         &self,
-        index: usize,
+        collider: ColliderHandle,
         translation: &f32::Vec2,
-        hit_buffer: &mut Vec<Hit>,
-        ignore_list: Option<&Vec<usize>>,
+        hits: &mut HitList<Hit, N>,
     ) -> usize {
-        let mut collision_count: usize = 0;
+        let index = self.handles.index(collider);
+        hits.clear();
+
+        let mask = self.mask[index];
 
         let mut self_bbox = self.bbox[index];
         self_bbox.x += translation.x;
         self_bbox.y += translation.y;
-        let self_center = self_bbox.center();
 
-        for i in 0..self.len() {
-            if i == index || !self.is_active(i) {
-                continue;
+        self.for_each_candidate(&self_bbox, |i| {
+            if i == index || !self.is_active[i] || self.is_trigger[i] || hits.is_full() {
+                return;
             }
 
-            match ignore_list {
-                Some(list) => {
-                    if list.contains(&i) {
-                        continue;
-                    }
-                }
-                None => {}
+            if !mask.contains(self.layer[i]) {
+                return;
+            }
+
+            if let Some(hit) = self.intersect_single(index, &self_bbox, i) {
+                hits.push(hit);
+            }
+        });
+
+        hits.len()
+    }
+
+    /// Tests the shape of collider `self_i`, placed on `self_bbox`, against collider `test_i`.
+    fn intersect_single(&self, self_i: usize, self_bbox: &Rect, test_i: usize) -> Option<Hit> {
+        let test_collider = self.handles.handle(test_i);
+        let test_bbox = self.bbox[test_i];
+
+        let (self_shape, test_shape) = (&self.shape[self_i], &self.shape[test_i]);
+        if *self_shape == ColliderShape::Rect && *test_shape == ColliderShape::Rect {
+            return Self::intersect_bbox_single(
+                self_bbox,
+                &self_bbox.center(),
+                &test_bbox,
+                &test_bbox.center(),
+                test_collider,
+            );
+        }
+
+        let overlap = shape::intersect(
+            &WorldShape::new(self_shape, self_bbox),
+            &WorldShape::new(test_shape, &test_bbox),
+        )?;
+
+        Some(Hit {
+            collider: test_collider,
+            position: overlap.position,
+            delta: overlap.normal * overlap.depth,
+            normal: overlap.normal,
+        })
+    }
+
+    /// Calls `f` with every active collider in `mask` whose bounding box overlaps `rect`. Triggers
+    /// are skipped, like in the other queries.
+    ///
+    /// Unlike `intersect_bbox`, the area is any rectangle and the amount of results isn't capped.
+    pub fn for_each_in_rect<F: FnMut(ColliderHandle)>(
+        &self,
+        rect: &Rect,
+        mask: CollisionMask,
+        mut f: F,
+    ) {
+        self.for_each_candidate(rect, |i| {
+            if !self.is_active[i] || self.is_trigger[i] || !mask.contains(self.layer[i]) {
+                return;
             }
 
             let bbox = self.bbox[i];
-            let center = bbox.center();
+            if bbox.left() < rect.right()
+                && rect.left() < bbox.right()
+                && bbox.top() < rect.bottom()
+                && rect.top() < bbox.bottom()
+            {
+                f(self.handles.handle(i));
+            }
+        });
+    }
+
+    /// Swept AABB test.
+    ///
+    /// Moves the bounding box of `collider` along `motion` and finds the first collider in its mask
+    /// that it would hit. Colliders already overlapping at the start are ignored, so overlapping
+    /// boxes can move apart. Boxes overlapping by less than `CONTACT_SKIN` are touching instead,
+    /// and moving further into them is a hit at time 0.
+    ///
+    /// Returns `None` if the whole motion is free.
+    pub fn sweep_bbox(&self, collider: ColliderHandle, motion: &f32::Vec2) -> Option<SweepHit> {
+        let index = self.handles.index(collider);
+        let mask = self.mask[index];
 
-            let mut hit = &mut hit_buffer[collision_count];
+        let self_bbox = self.bbox[index];
+        let swept_bbox = self_bbox.combine_with(self_bbox.offset(*motion));
 
-            if !Self::intersect_bbox_single(&self_bbox, &self_center, &bbox, &center, i, &mut hit) {
-                continue;
+        let mut first_hit: Option<SweepHit> = None;
+        self.for_each_candidate(&swept_bbox, |i| {
+            if i == index || !self.is_active[i] || self.is_trigger[i] {
+                return;
+            }
+            if !mask.contains(self.layer[i]) {
+                return;
+            }
+
+            let test_collider = self.handles.handle(i);
+            let hit =
+                match Self::sweep_bbox_single(&self_bbox, motion, &self.bbox[i], test_collider) {
+                    Some(hit) => hit,
+                    None => return,
+                };
+
+            if first_hit.is_none_or(|first_hit| hit.time < first_hit.time) {
+                first_hit = Some(hit);
+            }
+        });
+
+        first_hit
+    }
+
+    fn sweep_bbox_single(
+        self_bbox: &Rect,
+        motion: &f32::Vec2,
+        test_bbox: &Rect,
+        test_collider: ColliderHandle,
+    ) -> Option<SweepHit> {
+        let (entry_x, exit_x) = Self::sweep_axis(
+            self_bbox.left(),
+            self_bbox.right(),
+            test_bbox.left(),
+            test_bbox.right(),
+            motion.x,
+        )?;
+        let (entry_y, exit_y) = Self::sweep_axis(
+            self_bbox.top(),
+            self_bbox.bottom(),
+            test_bbox.top(),
+            test_bbox.bottom(),
+            motion.y,
+        )?;
+
+        let entry = f32::max(entry_x, entry_y);
+        let exit = f32::min(exit_x, exit_y);
+        if entry >= exit || entry >= 1.0 {
+            return None;
+        }
+
+        // The normal is on the axis that started touching last
+        let (normal, axis_motion) = if entry_x > entry_y {
+            (f32::Vec2::new(-f32::signum(motion.x), 0.0), motion.x)
+        } else {
+            (f32::Vec2::new(0.0, -f32::signum(motion.y)), motion.y)
+        };
+
+        // Starting inside the collider is only a hit when the boxes are touching, past that the
+        // motion is free so overlapping boxes can move apart
+        let start_overlap = -entry * f32::abs(axis_motion);
+        if entry < 0.0 && (axis_motion == 0.0 || start_overlap > CONTACT_SKIN) {
+            return None;
+        }
+
+        Some(SweepHit {
+            collider: test_collider,
+            time: f32::max(entry, 0.0),
+            normal,
+        })
+    }
+
+    /// Times, as fractions of `motion`, at which two intervals start and stop overlapping on one
+    /// axis. Returns `None` if they never overlap.
+    fn sweep_axis(
+        self_min: f32,
+        self_max: f32,
+        test_min: f32,
+        test_max: f32,
+        motion: f32,
+    ) -> Option<(f32, f32)> {
+        if motion > 0.0 {
+            Some((
+                (test_min - self_max) / motion,
+                (test_max - self_min) / motion,
+            ))
+        } else if motion < 0.0 {
+            Some((
+                (test_max - self_min) / motion,
+                (test_min - self_max) / motion,
+            ))
+        } else if self_max > test_min && self_min < test_max {
+            Some((f32::NEG_INFINITY, f32::INFINITY))
+        } else {
+            None
+        }
+    }
+
+    /// Casts a ray from `origin` along `direction`, up to `max_distance`.
+    ///
+    /// Returns the closest hit with the colliders in `mask`, skipping `ignored`, usually the
+    /// collider of the caster.
+    pub fn raycast(
+        &self,
+        origin: &f32::Vec2,
+        direction: &f32::Vec2,
+        max_distance: f32,
+        mask: CollisionMask,
+        ignored: Option<ColliderHandle>,
+    ) -> Option<RayHit> {
+        let end = *origin + direction.normalize_or_zero() * max_distance;
+        self.segment_cast(origin, &end, mask, ignored)
+    }
+
+    /// Casts a segment from `start` to `end`.
+    ///
+    /// Returns the closest hit with the colliders in `mask`, skipping `ignored`, usually the
+    /// collider of the caster.
+    pub fn segment_cast(
+        &self,
+        start: &f32::Vec2,
+        end: &f32::Vec2,
+        mask: CollisionMask,
+        ignored: Option<ColliderHandle>,
+    ) -> Option<RayHit> {
+        let mut closest_hit: Option<RayHit> = None;
+        self.for_each_segment_hit(start, end, mask, ignored, |hit| {
+            if closest_hit.is_none_or(|closest_hit| hit.distance < closest_hit.distance) {
+                closest_hit = Some(hit);
+            }
+        });
+
+        closest_hit
+    }
+
+    /// Casts a segment from `start` to `end`, like `segment_cast`, and gets every hit.
+    ///
+    /// `hits` is cleared and filled with the `MAX_COLLISION_COUNT` closest hits, sorted by
+    /// distance.
+    ///
+    /// Returns the hit count.
+    pub fn segment_cast_all(
+        &self,
+        start: &f32::Vec2,
+        end: &f32::Vec2,
+        mask: CollisionMask,
+        ignored: Option<ColliderHandle>,
+        hits: &mut RayHitList,
+    ) -> usize {
+        hits.clear();
+
+        self.for_each_segment_hit(start, end, mask, ignored, |hit| {
+            if hits.push(hit) {
+                return;
+            }
+
+            // Full, keep the closest hits
+            let farthest_hit = hits
+                .as_mut_slice()
+                .iter_mut()
+                .max_by(|a, b| a.distance.total_cmp(&b.distance))
+                .unwrap();
+            if hit.distance < farthest_hit.distance {
+                *farthest_hit = hit;
             }
+        });
+
+        hits.as_mut_slice()
+            .sort_by(|a, b| a.distance.total_cmp(&b.distance));
+
+        hits.len()
+    }
+
+    /// Whether the segment from `start` to `end` is free of walls and closed doors.
+    pub fn has_line_of_sight(&self, start: &f32::Vec2, end: &f32::Vec2) -> bool {
+        self.segment_cast(start, end, LINE_OF_SIGHT_MASK, None)
+            .is_none()
+    }
 
-            collision_count += 1;
+    fn for_each_segment_hit<F: FnMut(RayHit)>(
+        &self,
+        start: &f32::Vec2,
+        end: &f32::Vec2,
+        mask: CollisionMask,
+        ignored: Option<ColliderHandle>,
+        mut f: F,
+    ) {
+        let ignored_i = ignored.map(|ignored| self.handles.index(ignored));
 
-            if collision_count > MAX_COLLISION_COUNT {
-                break;
+        let segment = *end - *start;
+        let segment_bbox = Rect::new(start.x, start.y, segment.x, segment.y);
+        self.for_each_candidate(&segment_bbox, |i| {
+            if Some(i) == ignored_i || !self.is_active[i] || self.is_trigger[i] {
+                return;
+            }
+            if !mask.contains(self.layer[i]) {
+                return;
             }
+
+            let test_collider = self.handles.handle(i);
+            if let Some(hit) =
+                Self::segment_cast_single(start, &segment, &self.bbox[i], test_collider)
+            {
+                f(hit);
+            }
+        });
+    }
+
+    fn segment_cast_single(
+        start: &f32::Vec2,
+        segment: &f32::Vec2,
+        test_bbox: &Rect,
+        test_collider: ColliderHandle,
+    ) -> Option<RayHit> {
+        // A segment is a swept point
+        let (entry_x, exit_x) = Self::sweep_axis(
+            start.x,
+            start.x,
+            test_bbox.left(),
+            test_bbox.right(),
+            segment.x,
+        )?;
+        let (entry_y, exit_y) = Self::sweep_axis(
+            start.y,
+            start.y,
+            test_bbox.top(),
+            test_bbox.bottom(),
+            segment.y,
+        )?;
+
+        let entry = f32::max(entry_x, entry_y);
+        let exit = f32::min(exit_x, exit_y);
+        if entry >= exit || exit <= 0.0 || entry > 1.0 {
+            return None;
         }
 
-        collision_count
+        // Segments starting inside the collider hit it right away
+        let (time, normal) = if entry < 0.0 {
+            (0.0, f32::Vec2::ZERO)
+        } else if entry_x > entry_y {
+            (entry, f32::Vec2::new(-f32::signum(segment.x), 0.0))
+        } else {
+            (entry, f32::Vec2::new(0.0, -f32::signum(segment.y)))
+        };
+
+        Some(RayHit {
+            collider: test_collider,
+            distance: segment.length() * time,
+            point: *start + *segment * time,
+            normal,
+        })
     }
 
     fn intersect_bbox_single(
@@ -211,24 +802,21 @@ impl ColliderMgr {
         self_center: &f32::Vec2,
         test_bbox: &Rect,
         test_center: &f32::Vec2,
-        test_collider_i: usize,
-        hit: &mut Hit,
-    ) -> bool {
+        test_collider: ColliderHandle,
+    ) -> Option<Hit> {
         let dx = test_center.x - self_center.x;
         let px = (test_bbox.w / 2.0) + (self_bbox.w / 2.0) - f32::abs(dx);
         if px <= 0.0 {
-            hit.is_colliding = false;
-            return false;
+            return None;
         }
         let dy = test_center.y - self_center.y;
         let py = (test_bbox.h / 2.0) + (self_bbox.h / 2.0) - f32::abs(dy);
         if py <= 0.0 {
-            hit.is_colliding = false;
-            return false;
+            return None;
         }
 
-        hit.is_colliding = true;
-        hit.collider_i = test_collider_i;
+        let mut hit = Hit::EMPTY;
+        hit.collider = test_collider;
         if px < py {
             let sx = f32::signum(dx);
             hit.delta.x = px * sx;
@@ -247,54 +835,75 @@ impl ColliderMgr {
             hit.position.y = self_center.y + (self_bbox.y / 2.0) * sy;
         }
 
-        true
+        Some(hit)
     }
 
-    pub fn create_hit_buffer(size: usize) -> Vec<Hit> {
-        let mut buffer: Vec<Hit> = Vec::with_capacity(size);
+    pub fn set_position(&mut self, collider: ColliderHandle, x: f32, y: f32) {
+        let index = self.handles.index(collider);
+        self.bbox[index].x = x;
+        self.bbox[index].y = y;
+        self.broadphase.update(index, &self.bbox[index]);
+        self.mark_static_changed(index);
+    }
 
-        for _i in 0..size {
-            buffer.push(Hit {
-                is_colliding: false,
-                collider_i: usize::MAX,
-                position: f32::Vec2::ZERO,
-                delta: f32::Vec2::ZERO,
-                normal: f32::Vec2::ZERO,
-            })
-        }
+    /// Version of the colliders in `STATIC_MASK`. Systems caching data built from them, like
+    /// navigation grids, can compare it to know when to rebuild.
+    pub fn static_version(&self) -> u32 {
+        self.static_version
+    }
 
-        buffer
+    /// Calls `f` with the bounding box of every active collider in `STATIC_MASK`.
+    pub fn for_each_static_bbox<F: FnMut(&Rect)>(&self, mut f: F) {
+        for i in 0..self.len() {
+            if self.is_active[i] && STATIC_MASK.contains(self.layer[i]) {
+                f(&self.bbox[i]);
+            }
+        }
     }
 
-    pub fn set_position(&mut self, index: usize, x: f32, y: f32) {
-        self.bbox[index].x = x;
-        self.bbox[index].y = y;
+    fn mark_static_changed(&mut self, index: usize) {
+        if STATIC_MASK.contains(self.layer[index]) {
+            self.static_version = self.static_version.wrapping_add(1);
+        }
     }
 
     pub fn render(&self) {
         for i in 0..self.len() {
-            if !self.render_bbox[i] || !self.is_active(i) {
+            if !self.render_bbox[i] || !self.is_active[i] {
                 continue;
             }
 
             let bbox = self.bbox[i];
-            macroquad::shapes::draw_rectangle_lines(
-                bbox.x,
-                bbox.y,
-                bbox.w,
-                bbox.h,
-                4.0,
-                color::WHITE,
-            );
+            match WorldShape::new(&self.shape[i], &bbox) {
+                WorldShape::Circle { center, radius } => macroquad::shapes::draw_circle_lines(
+                    center.x,
+                    center.y,
+                    radius,
+                    4.0,
+                    color::WHITE,
+                ),
+                WorldShape::Polygon(polygon) => {
+                    let vertices = polygon.vertices();
+                    for (i, start) in vertices.iter().enumerate() {
+                        let end = vertices[(i + 1) % vertices.len()];
+                        macroquad::shapes::draw_line(
+                            start.x,
+                            start.y,
+                            end.x,
+                            end.y,
+                            4.0,
+                            color::WHITE,
+                        );
+                    }
+                }
+            }
         }
     }
 }
 
-#[derive(Debug)]
+#[derive(Clone, Copy, Debug)]
 pub struct Hit {
-    /// If `false`, assume every other field contains invalid data.
-    pub is_colliding: bool,
-    pub collider_i: usize,
+    pub collider: ColliderHandle,
     /// Contact point of the two objects.
     pub position: f32::Vec2,
     /// Overlap between the two objects.
@@ -304,3 +913,574 @@ pub struct Hit {
     /// Surface normal at the point of contact.
     pub normal: f32::Vec2,
 }
+
+/// First collision found by a swept test.
+#[derive(Clone, Copy, Debug)]
+pub struct SweepHit {
+    pub collider: ColliderHandle,
+    /// Time of impact, as a fraction of the motion vector in the `[0, 1)` range.
+    pub time: f32,
+    /// Surface normal of the hit collider at the point of contact.
+    pub normal: f32::Vec2,
+}
+
+impl Hit {
+    const EMPTY: Self = Self {
+        collider: ColliderHandle::INVALID,
+        position: f32::Vec2::ZERO,
+        delta: f32::Vec2::ZERO,
+        normal: f32::Vec2::ZERO,
+    };
+}
+
+impl Default for Hit {
+    fn default() -> Self {
+        Self::EMPTY
+    }
+}
+
+/// Hit found by a ray or segment cast.
+#[derive(Clone, Copy, Debug)]
+pub struct RayHit {
+    pub collider: ColliderHandle,
+    /// Distance from the start of the ray to `point`.
+    pub distance: f32,
+    /// Point where the ray enters the collider.
+    pub point: f32::Vec2,
+    /// Surface normal at `point`. Zero if the ray starts inside the collider.
+    pub normal: f32::Vec2,
+}
+
+impl Default for RayHit {
+    fn default() -> Self {
+        Self {
+            collider: ColliderHandle::INVALID,
+            distance: 0.0,
+            point: f32::Vec2::ZERO,
+            normal: f32::Vec2::ZERO,
+        }
+    }
+}
+
+/// Fixed capacity list of the hits found by a collision query. Holds at most `N` hits, by default
+/// `MAX_COLLISION_COUNT`, and never allocates, so it can be reused for every query.
+pub struct HitList<T: Copy + Default = Hit, const N: usize = MAX_COLLISION_COUNT> {
+    hits: [T; N],
+    len: usize,
+}
+
+impl<T: Copy + Default, const N: usize> HitList<T, N> {
+    pub fn new() -> Self {
+        Self {
+            hits: [T::default(); N],
+            len: 0,
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.len = 0;
+    }
+
+    /// Adds a hit. Returns `false` and drops it if the list is full.
+    pub fn push(&mut self, hit: T) -> bool {
+        if self.is_full() {
+            return false;
+        }
+
+        self.hits[self.len] = hit;
+        self.len += 1;
+
+        true
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Whether the list holds `N` hits. Queries stop once the list is full, so more colliders may
+    /// be overlapping.
+    pub fn is_full(&self) -> bool {
+        self.len == N
+    }
+
+    pub fn as_slice(&self) -> &[T] {
+        &self.hits[..self.len]
+    }
+
+    pub fn as_mut_slice(&mut self) -> &mut [T] {
+        &mut self.hits[..self.len]
+    }
+
+    pub fn iter(&self) -> std::slice::Iter<'_, T> {
+        self.as_slice().iter()
+    }
+}
+
+impl<'a, T: Copy + Default, const N: usize> IntoIterator for &'a HitList<T, N> {
+    type Item = &'a T;
+    type IntoIter = std::slice::Iter<'a, T>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+pub type RayHitList = HitList<RayHit>;
+pub type ColliderHandle = Handle<ColliderMgr>;
+
+/// Collision layer of a collider.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum CollisionLayer {
+    Unit,
+    Wall,
+    /// Areas that detect colliders without blocking them, like summoning circles.
+    Trigger,
+    Selector,
+    Projectile,
+    /// Closed doors. Open doors deactivate their collider.
+    Door,
+}
+
+/// Trigger overlap events. Emitted by `ColliderMgr::update_triggers` and available until the next
+/// update.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TriggerEvent {
+    /// `other` started overlapping `trigger`.
+    Enter {
+        trigger: ColliderHandle,
+        other: ColliderHandle,
+    },
+    /// `other` kept overlapping `trigger` since the last update.
+    Stay {
+        trigger: ColliderHandle,
+        other: ColliderHandle,
+    },
+    /// `other` stopped overlapping `trigger`, or either of them was deactivated or removed.
+    Exit {
+        trigger: ColliderHandle,
+        other: ColliderHandle,
+    },
+}
+
+/// Layers of colliders that don't move on their own, like walls and doors. They block navigation.
+pub const STATIC_MASK: CollisionMask =
+    CollisionMask::from_layers(&[CollisionLayer::Wall, CollisionLayer::Door]);
+
+/// Layers that block line of sight.
+pub const LINE_OF_SIGHT_MASK: CollisionMask =
+    CollisionMask::from_layers(&[CollisionLayer::Wall, CollisionLayer::Door]);
+
+/// Set of collision layers, as a bit for each `CollisionLayer`.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct CollisionMask(u32);
+
+impl CollisionMask {
+    pub const NONE: Self = Self(0);
+    pub const ALL: Self = Self(u32::MAX);
+
+    pub const fn from_layers(layers: &[CollisionLayer]) -> Self {
+        let mut bits = 0;
+        let mut i = 0;
+        while i < layers.len() {
+            bits |= 1 << layers[i] as u32;
+            i += 1;
+        }
+
+        Self(bits)
+    }
+
+    pub const fn contains(self, layer: CollisionLayer) -> bool {
+        self.0 & (1 << layer as u32) != 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Xorshift generator, so crowded scenes are random but reproducible.
+    struct Rng(u32);
+
+    impl Rng {
+        fn next_f32(&mut self) -> f32 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 17;
+            self.0 ^= self.0 << 5;
+            (self.0 >> 8) as f32 / (1 << 24) as f32
+        }
+
+        fn range(&mut self, min: f32, max: f32) -> f32 {
+            min + self.next_f32() * (max - min)
+        }
+    }
+
+    fn overlaps(a: &Rect, b: &Rect) -> bool {
+        a.x < b.x + b.w && b.x < a.x + a.w && a.y < b.y + b.h && b.y < a.y + a.h
+    }
+
+    fn contains(rect: &Rect, point: &f32::Vec2) -> bool {
+        rect.x < point.x
+            && point.x < rect.x + rect.w
+            && rect.y < point.y
+            && point.y < rect.y + rect.h
+    }
+
+    /// Whether `other` can be reported as a hit for `collider`.
+    fn is_candidate(collider_mgr: &ColliderMgr, collider: ColliderHandle, other: usize) -> bool {
+        let index = collider_mgr.handles.index(collider);
+        other != index
+            && collider_mgr.is_active[other]
+            && !collider_mgr.is_trigger[other]
+            && collider_mgr.mask[index].contains(collider_mgr.layer[other])
+    }
+
+    /// Fills a single broadphase cell with more overlapping boxes than a `HitList` holds, some of
+    /// them inactive, triggers or on layers outside the mask.
+    fn crowded_scene(rng: &mut Rng) -> (ColliderMgr, Vec<ColliderHandle>) {
+        let mut collider_mgr = ColliderMgr::new();
+        let mut colliders = Vec::new();
+
+        let count =
+            MAX_COLLISION_COUNT * 2 + (rng.next_f32() * MAX_COLLISION_COUNT as f32) as usize;
+        for _ in 0..count {
+            let bbox = Rect::new(
+                rng.range(0.0, 32.0),
+                rng.range(0.0, 32.0),
+                rng.range(8.0, 32.0),
+                rng.range(8.0, 32.0),
+            );
+            let layer = if rng.next_f32() < 0.8 {
+                CollisionLayer::Unit
+            } else {
+                CollisionLayer::Projectile
+            };
+            let mask = CollisionMask::from_layers(&[CollisionLayer::Unit, CollisionLayer::Wall]);
+            let collider = collider_mgr.add(bbox, layer, mask);
+
+            let roll = rng.next_f32();
+            if roll < 0.1 {
+                collider_mgr.set_active(collider, false);
+            } else if roll < 0.2 {
+                collider_mgr.set_trigger(collider, true);
+            }
+            colliders.push(collider);
+        }
+
+        (collider_mgr, colliders)
+    }
+
+    #[test]
+    fn intersect_bbox_in_crowded_cell_stays_in_capacity_and_returns_real_hits() {
+        let mut rng = Rng(0x9e37_79b9);
+        let mut hits: HitList = HitList::new();
+
+        for _ in 0..64 {
+            let (mut collider_mgr, colliders) = crowded_scene(&mut rng);
+            for is_broadphase_enabled in [true, false] {
+                collider_mgr.set_broadphase_enabled(is_broadphase_enabled);
+
+                for collider in &colliders {
+                    let translation = f32::Vec2::new(rng.range(-4.0, 4.0), rng.range(-4.0, 4.0));
+                    let count = collider_mgr.intersect_bbox(*collider, &translation, &mut hits);
+
+                    assert_eq!(count, hits.len());
+                    assert!(count <= MAX_COLLISION_COUNT);
+
+                    let mut bbox = collider_mgr.bbox(*collider);
+                    bbox.x += translation.x;
+                    bbox.y += translation.y;
+                    let expected = (0..collider_mgr.len())
+                        .filter(|i| is_candidate(&collider_mgr, *collider, *i))
+                        .filter(|i| overlaps(&bbox, &collider_mgr.bbox[*i]))
+                        .count();
+                    assert_eq!(count, expected.min(MAX_COLLISION_COUNT));
+
+                    for (i, hit) in hits.iter().enumerate() {
+                        assert!(collider_mgr.is_valid(hit.collider));
+                        let hit_i = collider_mgr.handles.index(hit.collider);
+                        assert!(is_candidate(&collider_mgr, *collider, hit_i));
+                        assert!(overlaps(&bbox, &collider_mgr.bbox(hit.collider)));
+                        assert!(hits.as_slice()[..i]
+                            .iter()
+                            .all(|other| other.collider != hit.collider));
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn intersect_point_in_crowded_cell_stays_in_capacity_and_returns_real_hits() {
+        let mut rng = Rng(0x85eb_ca6b);
+        let mut hits: HitList = HitList::new();
+
+        for _ in 0..64 {
+            let (mut collider_mgr, colliders) = crowded_scene(&mut rng);
+            for is_broadphase_enabled in [true, false] {
+                collider_mgr.set_broadphase_enabled(is_broadphase_enabled);
+
+                for collider in &colliders {
+                    let point = f32::Vec2::new(rng.range(8.0, 40.0), rng.range(8.0, 40.0));
+                    let count = collider_mgr.intersect_point(*collider, &point, &mut hits);
+
+                    assert_eq!(count, hits.len());
+                    assert!(count <= MAX_COLLISION_COUNT);
+
+                    let expected = (0..collider_mgr.len())
+                        .filter(|i| is_candidate(&collider_mgr, *collider, *i))
+                        .filter(|i| contains(&collider_mgr.bbox[*i], &point))
+                        .count();
+                    assert_eq!(count, expected.min(MAX_COLLISION_COUNT));
+
+                    for (i, hit) in hits.iter().enumerate() {
+                        assert!(collider_mgr.is_valid(hit.collider));
+                        let hit_i = collider_mgr.handles.index(hit.collider);
+                        assert!(is_candidate(&collider_mgr, *collider, hit_i));
+                        assert!(contains(&collider_mgr.bbox(hit.collider), &point));
+                        assert!(hits.as_slice()[..i]
+                            .iter()
+                            .all(|other| other.collider != hit.collider));
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn for_each_in_rect_finds_every_overlapping_collider() {
+        let mut rng = Rng(0x27d4_eb2f);
+        let (collider_mgr, _) = crowded_scene(&mut rng);
+        let mask = CollisionMask::from_layers(&[CollisionLayer::Unit]);
+
+        for _ in 0..64 {
+            let rect = Rect::new(
+                rng.range(-16.0, 48.0),
+                rng.range(-16.0, 48.0),
+                rng.range(1.0, 32.0),
+                rng.range(1.0, 32.0),
+            );
+
+            let mut found = Vec::new();
+            collider_mgr.for_each_in_rect(&rect, mask, |collider| found.push(collider.index()));
+            found.sort_unstable();
+
+            let expected: Vec<usize> = (0..collider_mgr.len())
+                .filter(|i| {
+                    collider_mgr.is_active[*i]
+                        && !collider_mgr.is_trigger[*i]
+                        && mask.contains(collider_mgr.layer[*i])
+                        && overlaps(&rect, &collider_mgr.bbox[*i])
+                })
+                .collect();
+            assert_eq!(found, expected);
+        }
+    }
+
+    #[test]
+    fn sweep_bbox_blocks_motion_into_touching_collider() {
+        let mut collider_mgr = ColliderMgr::new();
+        let mask = CollisionMask::from_layers(&[CollisionLayer::Wall]);
+        let wall = collider_mgr.add(
+            Rect::new(32.0, 0.0, 16.0, 64.0),
+            CollisionLayer::Wall,
+            CollisionMask::NONE,
+        );
+
+        // Stopped at contact, overlapping by float error
+        let unit = collider_mgr.add(
+            Rect::new(16.001, 8.0, 16.0, 16.0),
+            CollisionLayer::Unit,
+            mask,
+        );
+        let hit = collider_mgr
+            .sweep_bbox(unit, &f32::Vec2::new(4.0, 4.0))
+            .unwrap();
+        assert_eq!(hit.collider, wall);
+        assert_eq!(hit.time, 0.0);
+        assert_eq!(hit.normal, f32::Vec2::new(-1.0, 0.0));
+
+        // Sliding along and moving away are free
+        assert!(collider_mgr
+            .sweep_bbox(unit, &f32::Vec2::new(0.0, 4.0))
+            .is_none());
+        assert!(collider_mgr
+            .sweep_bbox(unit, &f32::Vec2::new(-4.0, 0.0))
+            .is_none());
+
+        // Deep overlaps are ignored so the unit can get out
+        collider_mgr.set_bbox(unit, Rect::new(24.0, 8.0, 16.0, 16.0));
+        assert!(collider_mgr
+            .sweep_bbox(unit, &f32::Vec2::new(4.0, 0.0))
+            .is_none());
+    }
+
+    #[test]
+    fn intersect_bbox_with_crowded_circles_only_returns_real_hits() {
+        let mut rng = Rng(0xc2b2_ae35);
+        let mut hits: HitList = HitList::new();
+        let mut collider_mgr = ColliderMgr::new();
+
+        let colliders: Vec<_> = (0..MAX_COLLISION_COUNT * 3)
+            .map(|_| {
+                let center = f32::Vec2::new(rng.range(16.0, 48.0), rng.range(16.0, 48.0));
+                collider_mgr.add_circle(
+                    center,
+                    rng.range(4.0, 16.0),
+                    CollisionLayer::Unit,
+                    CollisionMask::ALL,
+                )
+            })
+            .collect();
+
+        for collider in &colliders {
+            let count = collider_mgr.intersect_bbox(*collider, &f32::Vec2::ZERO, &mut hits);
+            assert_eq!(count, hits.len());
+            assert!(count <= MAX_COLLISION_COUNT);
+
+            let bbox = collider_mgr.bbox(*collider);
+            let center = bbox.center();
+            for hit in &hits {
+                assert_ne!(hit.collider, *collider);
+                let other_bbox = collider_mgr.bbox(hit.collider);
+                let distance = center.distance(other_bbox.center());
+                assert!(distance < (bbox.w + other_bbox.w) / 2.0);
+                assert!(hit.delta.length() > 0.0);
+            }
+        }
+    }
+
+    fn add_wall(collider_mgr: &mut ColliderMgr, bbox: Rect) -> ColliderHandle {
+        collider_mgr.add(bbox, CollisionLayer::Wall, CollisionMask::NONE)
+    }
+
+    #[test]
+    fn raycast_returns_closest_hit() {
+        let mut collider_mgr = ColliderMgr::new();
+        let mask = CollisionMask::from_layers(&[CollisionLayer::Wall]);
+        let far_wall = add_wall(&mut collider_mgr, Rect::new(64.0, 0.0, 16.0, 64.0));
+        let near_wall = add_wall(&mut collider_mgr, Rect::new(32.0, 0.0, 16.0, 64.0));
+        collider_mgr.add(
+            Rect::new(16.0, 0.0, 8.0, 64.0),
+            CollisionLayer::Unit,
+            CollisionMask::NONE,
+        );
+
+        let origin = f32::Vec2::new(0.0, 16.0);
+        let direction = f32::Vec2::new(2.0, 0.0);
+        let hit = collider_mgr
+            .raycast(&origin, &direction, 100.0, mask, None)
+            .unwrap();
+        assert_eq!(hit.collider, near_wall);
+        assert_eq!(hit.distance, 32.0);
+        assert_eq!(hit.point, f32::Vec2::new(32.0, 16.0));
+        assert_eq!(hit.normal, f32::Vec2::new(-1.0, 0.0));
+
+        // Ignored colliders are passed through
+        let hit = collider_mgr
+            .raycast(&origin, &direction, 100.0, mask, Some(near_wall))
+            .unwrap();
+        assert_eq!(hit.collider, far_wall);
+        assert_eq!(hit.distance, 64.0);
+
+        // Out of range
+        assert!(collider_mgr
+            .raycast(&origin, &direction, 30.0, mask, None)
+            .is_none());
+
+        // Hit from below
+        let hit = collider_mgr
+            .segment_cast(
+                &f32::Vec2::new(40.0, 80.0),
+                &f32::Vec2::new(40.0, 0.0),
+                mask,
+                None,
+            )
+            .unwrap();
+        assert_eq!(hit.collider, near_wall);
+        assert_eq!(hit.distance, 16.0);
+        assert_eq!(hit.point, f32::Vec2::new(40.0, 64.0));
+        assert_eq!(hit.normal, f32::Vec2::new(0.0, 1.0));
+    }
+
+    #[test]
+    fn segment_cast_starting_inside_hits_at_start() {
+        let mut collider_mgr = ColliderMgr::new();
+        let mask = CollisionMask::from_layers(&[CollisionLayer::Wall]);
+        let wall = add_wall(&mut collider_mgr, Rect::new(32.0, 0.0, 16.0, 64.0));
+
+        let start = f32::Vec2::new(40.0, 16.0);
+        let hit = collider_mgr
+            .segment_cast(&start, &f32::Vec2::new(100.0, 16.0), mask, None)
+            .unwrap();
+        assert_eq!(hit.collider, wall);
+        assert_eq!(hit.distance, 0.0);
+        assert_eq!(hit.point, start);
+        assert_eq!(hit.normal, f32::Vec2::ZERO);
+    }
+
+    #[test]
+    fn segment_cast_all_keeps_closest_hits_sorted() {
+        let mut collider_mgr = ColliderMgr::new();
+        let mask = CollisionMask::from_layers(&[CollisionLayer::Wall]);
+        let mut hits = RayHitList::new();
+
+        // Farthest walls first, so the list fills up before the closest ones are found
+        let mut walls = Vec::new();
+        for i in (0..MAX_COLLISION_COUNT * 2).rev() {
+            let bbox = Rect::new(i as f32 * 16.0 + 8.0, 0.0, 8.0, 16.0);
+            walls.push(add_wall(&mut collider_mgr, bbox));
+        }
+        walls.reverse();
+
+        let start = f32::Vec2::new(0.0, 8.0);
+        let end = f32::Vec2::new(MAX_COLLISION_COUNT as f32 * 40.0, 8.0);
+        let count = collider_mgr.segment_cast_all(&start, &end, mask, None, &mut hits);
+        assert_eq!(count, MAX_COLLISION_COUNT);
+        assert_eq!(hits.len(), count);
+
+        for (i, hit) in hits.iter().enumerate() {
+            assert_eq!(hit.collider, walls[i]);
+            assert_eq!(hit.distance, i as f32 * 16.0 + 8.0);
+        }
+
+        // Fewer hits than the capacity
+        let end = f32::Vec2::new(44.0, 8.0);
+        assert_eq!(
+            collider_mgr.segment_cast_all(&start, &end, mask, None, &mut hits),
+            3
+        );
+        let distances: Vec<f32> = hits.iter().map(|hit| hit.distance).collect();
+        assert_eq!(distances, [8.0, 24.0, 40.0]);
+    }
+
+    #[test]
+    fn closed_doors_block_line_of_sight() {
+        let mut collider_mgr = ColliderMgr::new();
+        let door = collider_mgr.add(
+            Rect::new(32.0, 0.0, 16.0, 64.0),
+            CollisionLayer::Door,
+            CollisionMask::NONE,
+        );
+        collider_mgr.add(
+            Rect::new(16.0, 0.0, 8.0, 64.0),
+            CollisionLayer::Unit,
+            CollisionMask::NONE,
+        );
+
+        let start = f32::Vec2::new(0.0, 16.0);
+        let end = f32::Vec2::new(64.0, 16.0);
+        assert!(!collider_mgr.has_line_of_sight(&start, &end));
+        assert!(!collider_mgr.has_line_of_sight(&end, &start));
+
+        // Units don't block the view
+        assert!(collider_mgr.has_line_of_sight(&start, &f32::Vec2::new(28.0, 16.0)));
+
+        // Open doors deactivate their collider
+        collider_mgr.set_active(door, false);
+        assert!(collider_mgr.has_line_of_sight(&start, &end));
+    }
+}
@@ -1 +1,4 @@
+pub mod benchmark;
+pub mod broadphase;
 pub mod collider;
+pub mod shape;
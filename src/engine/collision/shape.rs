@@ -0,0 +1,355 @@
+use macroquad::math::{f32, Rect};
+
+/// Maximum amount of vertices of a `ConvexPolygon`.
+pub const MAX_POLYGON_VERTEX_COUNT: usize = 8;
+
+/// Shape of a collider. Shapes are placed relative to the collider bounding box, so moving or
+/// resizing the box moves or resizes the shape too.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ColliderShape {
+    /// The bounding box itself.
+    Rect,
+    /// Circle inscribed in the bounding box, centered in it.
+    Circle,
+    /// Convex polygon, with vertices relative to the bounding box top left corner.
+    Polygon(ConvexPolygon),
+}
+
+/// Convex polygon with up to `MAX_POLYGON_VERTEX_COUNT` vertices, in any winding order.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ConvexPolygon {
+    vertices: [f32::Vec2; MAX_POLYGON_VERTEX_COUNT],
+    len: usize,
+}
+
+impl ConvexPolygon {
+    /// Panics if there are less than 3 or more than `MAX_POLYGON_VERTEX_COUNT` vertices. The
+    /// polygon is assumed to be convex.
+    pub fn new(vertices: &[f32::Vec2]) -> Self {
+        assert!(
+            vertices.len() >= 3 && vertices.len() <= MAX_POLYGON_VERTEX_COUNT,
+            "Convex polygons need 3 to {MAX_POLYGON_VERTEX_COUNT} vertices, got {}",
+            vertices.len()
+        );
+
+        let mut polygon = Self {
+            vertices: [f32::Vec2::ZERO; MAX_POLYGON_VERTEX_COUNT],
+            len: vertices.len(),
+        };
+        polygon.vertices[..vertices.len()].copy_from_slice(vertices);
+
+        polygon
+    }
+
+    pub fn vertices(&self) -> &[f32::Vec2] {
+        &self.vertices[..self.len]
+    }
+
+    /// Smallest rectangle containing every vertex.
+    pub fn bounds(&self) -> Rect {
+        let vertices = self.vertices();
+        let min = vertices.iter().copied().reduce(f32::Vec2::min).unwrap();
+        let max = vertices.iter().copied().reduce(f32::Vec2::max).unwrap();
+
+        Rect::new(min.x, min.y, max.x - min.x, max.y - min.y)
+    }
+
+    /// Copy of the polygon moved by `offset`.
+    pub fn offset(&self, offset: f32::Vec2) -> Self {
+        let mut polygon = *self;
+        for vertex in polygon.vertices[..polygon.len].iter_mut() {
+            *vertex += offset;
+        }
+
+        polygon
+    }
+}
+
+/// Shape placed in world space, ready for intersection tests.
+#[derive(Clone, Copy, Debug)]
+pub enum WorldShape {
+    Polygon(ConvexPolygon),
+    Circle { center: f32::Vec2, radius: f32 },
+}
+
+impl WorldShape {
+    /// Places `shape` on its collider bounding box.
+    pub fn new(shape: &ColliderShape, bbox: &Rect) -> Self {
+        // Selection boxes can have negative sizes while being dragged
+        let min = f32::Vec2::new(bbox.x.min(bbox.x + bbox.w), bbox.y.min(bbox.y + bbox.h));
+        let size = f32::Vec2::new(bbox.w.abs(), bbox.h.abs());
+
+        match shape {
+            ColliderShape::Rect => Self::Polygon(ConvexPolygon::new(&[
+                min,
+                min + f32::Vec2::new(size.x, 0.0),
+                min + size,
+                min + f32::Vec2::new(0.0, size.y),
+            ])),
+            ColliderShape::Circle => Self::Circle {
+                center: min + size / 2.0,
+                radius: size.min_element() / 2.0,
+            },
+            ColliderShape::Polygon(polygon) => Self::Polygon(polygon.offset(bbox.point())),
+        }
+    }
+
+    /// Min and max projections of the shape on `axis`.
+    fn project(&self, axis: f32::Vec2) -> (f32, f32) {
+        match self {
+            Self::Polygon(polygon) => polygon.vertices().iter().fold(
+                (f32::INFINITY, f32::NEG_INFINITY),
+                |(min, max), vertex| {
+                    let projection = vertex.dot(axis);
+                    (min.min(projection), max.max(projection))
+                },
+            ),
+            Self::Circle { center, radius } => {
+                let projection = center.dot(axis);
+                (projection - radius, projection + radius)
+            }
+        }
+    }
+
+    /// Point of the shape furthest along `direction`.
+    fn support(&self, direction: f32::Vec2) -> f32::Vec2 {
+        match self {
+            Self::Polygon(polygon) => polygon
+                .vertices()
+                .iter()
+                .copied()
+                .max_by(|a, b| a.dot(direction).total_cmp(&b.dot(direction)))
+                .unwrap(),
+            Self::Circle { center, radius } => *center + direction * *radius,
+        }
+    }
+
+    /// Separating axis candidates of the shape against `other`.
+    fn for_each_axis<F: FnMut(f32::Vec2)>(&self, other: &WorldShape, mut f: F) {
+        match self {
+            Self::Polygon(polygon) => {
+                let vertices = polygon.vertices();
+                for i in 0..vertices.len() {
+                    let edge = vertices[(i + 1) % vertices.len()] - vertices[i];
+                    f(edge.perp());
+                }
+            }
+            // Circles only have the axis towards the closest feature of the other shape
+            Self::Circle { center, .. } => match other {
+                Self::Polygon(polygon) => {
+                    let closest_vertex = polygon
+                        .vertices()
+                        .iter()
+                        .copied()
+                        .min_by(|a, b| {
+                            a.distance_squared(*center)
+                                .total_cmp(&b.distance_squared(*center))
+                        })
+                        .unwrap();
+                    f(closest_vertex - *center);
+                }
+                Self::Circle {
+                    center: other_center,
+                    ..
+                } => f(*other_center - *center),
+            },
+        }
+    }
+}
+
+/// Overlap found by a separating axis test.
+#[derive(Clone, Copy, Debug)]
+pub struct ShapeOverlap {
+    /// Unit axis of least overlap, pointing from the first shape towards the second one.
+    pub normal: f32::Vec2,
+    pub depth: f32,
+    /// Point of the second shape deepest into the first one.
+    pub position: f32::Vec2,
+}
+
+/// Separating axis theorem test between two convex shapes. Returns `None` if they don't overlap,
+/// touching shapes don't overlap.
+pub fn intersect(a: &WorldShape, b: &WorldShape) -> Option<ShapeOverlap> {
+    let mut normal = f32::Vec2::ZERO;
+    let mut depth = f32::INFINITY;
+    let mut is_separated = false;
+
+    let mut test_axis = |axis: f32::Vec2| {
+        let axis = axis.normalize_or_zero();
+        if is_separated || axis == f32::Vec2::ZERO {
+            return;
+        }
+
+        // Distances to push `a` out of `b` towards each side of the axis. They also work when
+        // one shape contains the other.
+        let (a_min, a_max) = a.project(axis);
+        let (b_min, b_max) = b.project(axis);
+        let (overlap, axis) = match a_max - b_min < b_max - a_min {
+            true => (a_max - b_min, axis),
+            false => (b_max - a_min, -axis),
+        };
+
+        if overlap <= 0.0 {
+            is_separated = true;
+        } else if overlap < depth {
+            normal = axis;
+            depth = overlap;
+        }
+    };
+    a.for_each_axis(b, &mut test_axis);
+    b.for_each_axis(a, &mut test_axis);
+
+    // Concentric circles have no axis of their own. Extra axes never give a smaller overlap
+    // than the least one, so testing it on any pair is safe.
+    test_axis(f32::Vec2::X);
+
+    if is_separated {
+        return None;
+    }
+
+    Some(ShapeOverlap {
+        normal,
+        depth,
+        position: b.support(-normal),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::collision::collider::{ColliderMgr, CollisionLayer, CollisionMask, HitList};
+
+    const EPSILON: f32 = 1e-4;
+
+    fn circle(x: f32, y: f32, radius: f32) -> WorldShape {
+        WorldShape::Circle {
+            center: f32::Vec2::new(x, y),
+            radius,
+        }
+    }
+
+    fn rect(x: f32, y: f32, w: f32, h: f32) -> WorldShape {
+        WorldShape::new(&ColliderShape::Rect, &Rect::new(x, y, w, h))
+    }
+
+    fn assert_overlap(overlap: Option<ShapeOverlap>, normal: f32::Vec2, depth: f32) {
+        let overlap = overlap.expect("shapes should overlap");
+        assert!(
+            overlap.normal.abs_diff_eq(normal, EPSILON),
+            "normal {} != {normal}",
+            overlap.normal
+        );
+        assert!(
+            (overlap.depth - depth).abs() < EPSILON,
+            "depth {} != {depth}",
+            overlap.depth
+        );
+    }
+
+    #[test]
+    fn intersect_circles() {
+        let a = circle(0.0, 0.0, 10.0);
+
+        assert_overlap(intersect(&a, &circle(15.0, 0.0, 10.0)), f32::Vec2::X, 5.0);
+        assert_overlap(
+            intersect(&a, &circle(0.0, -12.0, 4.0)),
+            f32::Vec2::NEG_Y,
+            2.0,
+        );
+
+        // Concentric circles still give a finite push along some axis
+        let overlap = intersect(&a, &circle(0.0, 0.0, 4.0)).unwrap();
+        assert!((overlap.normal.length() - 1.0).abs() < EPSILON);
+        assert!((overlap.depth - 14.0).abs() < EPSILON);
+    }
+
+    #[test]
+    fn intersect_circle_and_rect() {
+        let a = circle(0.0, 0.0, 10.0);
+
+        // Edge
+        assert_overlap(
+            intersect(&a, &rect(8.0, -5.0, 10.0, 10.0)),
+            f32::Vec2::X,
+            2.0,
+        );
+        assert_overlap(
+            intersect(&rect(8.0, -5.0, 10.0, 10.0), &a),
+            f32::Vec2::NEG_X,
+            2.0,
+        );
+
+        // Corner, pushed along the diagonal
+        let corner_distance = f32::Vec2::splat(6.0).length();
+        assert_overlap(
+            intersect(&a, &rect(6.0, 6.0, 10.0, 10.0)),
+            f32::Vec2::ONE.normalize(),
+            10.0 - corner_distance,
+        );
+
+        // Near the corner but outside the circle
+        assert!(intersect(&a, &rect(8.0, 8.0, 10.0, 10.0)).is_none());
+    }
+
+    #[test]
+    fn intersect_polygon_and_rect() {
+        let triangle = WorldShape::Polygon(ConvexPolygon::new(&[
+            f32::Vec2::new(0.0, 0.0),
+            f32::Vec2::new(10.0, 0.0),
+            f32::Vec2::new(0.0, 10.0),
+        ]));
+
+        // The rect corner crosses the hypotenuse
+        assert_overlap(
+            intersect(&triangle, &rect(4.0, 4.0, 10.0, 10.0)),
+            f32::Vec2::ONE.normalize(),
+            2.0 / 2.0_f32.sqrt(),
+        );
+
+        assert!(intersect(&triangle, &rect(6.0, 6.0, 10.0, 10.0)).is_none());
+    }
+
+    #[test]
+    fn touching_shapes_do_not_intersect() {
+        let a = circle(0.0, 0.0, 10.0);
+        assert!(intersect(&a, &circle(20.0, 0.0, 10.0)).is_none());
+        assert!(intersect(&a, &rect(10.0, -5.0, 10.0, 10.0)).is_none());
+        assert!(intersect(&rect(0.0, 0.0, 10.0, 10.0), &rect(10.0, 0.0, 10.0, 10.0)).is_none());
+        assert!(intersect(&rect(0.0, 0.0, 10.0, 10.0), &rect(10.0, 10.0, 10.0, 10.0)).is_none());
+    }
+
+    #[test]
+    fn intersect_sign_matches_bbox_test() {
+        let mut collider_mgr = ColliderMgr::new();
+        let mut hits: HitList = HitList::new();
+        let self_bbox = Rect::new(0.0, 0.0, 16.0, 16.0);
+        let collider = collider_mgr.add(self_bbox, CollisionLayer::Unit, CollisionMask::ALL);
+        let other = collider_mgr.add(Rect::default(), CollisionLayer::Unit, CollisionMask::ALL);
+
+        for (x, y) in [
+            (10.0, 2.0),
+            (-10.0, 2.0),
+            (2.0, 12.0),
+            (3.0, -13.0),
+            (-6.0, 9.0),
+        ] {
+            let test_bbox = Rect::new(x, y, 16.0, 16.0);
+            collider_mgr.set_bbox(other, test_bbox);
+
+            // Rect pairs use the AABB test
+            assert_eq!(
+                collider_mgr.intersect_bbox(collider, &f32::Vec2::ZERO, &mut hits),
+                1
+            );
+            let hit = hits.as_slice()[0];
+
+            let overlap = intersect(
+                &WorldShape::new(&ColliderShape::Rect, &self_bbox),
+                &WorldShape::new(&ColliderShape::Rect, &test_bbox),
+            )
+            .unwrap();
+            assert!(overlap.normal.abs_diff_eq(hit.normal, EPSILON));
+            assert!((overlap.normal * overlap.depth).abs_diff_eq(hit.delta, EPSILON));
+        }
+    }
+}
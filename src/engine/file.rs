@@ -60,3 +60,32 @@ pub fn set_pc_assets_folder(path: &str) -> Option<String> {
     macroquad::file::set_pc_assets_folder(path);
     Some(path.to_string())
 }
+
+/// Normalises a relative asset path so that different spellings of the same file are equal.
+///
+/// Uses `/` as separator and resolves `.` and `..` components, so for example
+/// `tilesets/../tiles/grass01.png` becomes `tiles/grass01.png`.
+pub fn normalize_path(path: &str) -> String {
+    let mut components: Vec<&str> = Vec::new();
+
+    for component in path.split(['/', '\\']) {
+        match component {
+            "" | "." => continue,
+            ".." => match components.last() {
+                Some(&last) if last != ".." => {
+                    components.pop();
+                }
+                _ => components.push(component),
+            },
+            _ => components.push(component),
+        }
+    }
+
+    let normalized = components.join("/");
+
+    if path.starts_with('/') {
+        format!("/{normalized}")
+    } else {
+        normalized
+    }
+}
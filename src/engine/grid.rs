@@ -0,0 +1,329 @@
+use macroquad::math::{f32, IVec2};
+
+use super::logging::log;
+
+/// Tile grid of a scene. Converts between tile coordinates and world positions for the supported
+/// Tiled map orientations.
+///
+/// World positions follow the Tiled editor pixel coordinates, so a map is rendered exactly as in
+/// the editor. Tile positions returned by `tile_to_world` are the top left corner of the tile's
+/// bounding box.
+#[derive(Clone, Copy)]
+pub struct MapGrid {
+    pub orientation: MapOrientation,
+    /// Map size in tiles.
+    pub size: IVec2,
+    /// Size of a grid cell, which may be different from the size of the tileset images.
+    pub tile_size: f32::Vec2,
+}
+
+impl MapGrid {
+    pub fn from_tiled_map(map: &tiled::Map) -> Self {
+        let orientation = match map.orientation {
+            tiled::Orientation::Orthogonal => MapOrientation::Orthogonal,
+            tiled::Orientation::Isometric => MapOrientation::Isometric,
+            tiled::Orientation::Staggered => MapOrientation::Staggered {
+                stagger_x: map.stagger_axis == tiled::StaggerAxis::X,
+                stagger_even: map.stagger_index == tiled::StaggerIndex::Even,
+            },
+            tiled::Orientation::Hexagonal => {
+                log::error("Hexagonal maps are not supported. Using orthogonal orientation");
+                MapOrientation::Orthogonal
+            }
+        };
+
+        let size = IVec2::new(map.width as i32, map.height as i32);
+        let tile_size = f32::Vec2::new(map.tile_width as f32, map.tile_height as f32);
+
+        Self {
+            orientation,
+            size,
+            tile_size,
+        }
+    }
+
+    /// Returns the world position of the top left corner of the tile's bounding box.
+    pub fn tile_to_world(&self, tile: IVec2) -> f32::Vec2 {
+        let half_size = self.tile_size / 2.0;
+
+        match self.orientation {
+            MapOrientation::Orthogonal => tile.as_vec2() * self.tile_size,
+            MapOrientation::Isometric => {
+                let origin_x = self.size.y as f32 * half_size.x;
+                f32::Vec2::new(
+                    (tile.x - tile.y) as f32 * half_size.x + origin_x - half_size.x,
+                    (tile.x + tile.y) as f32 * half_size.y,
+                )
+            }
+            MapOrientation::Staggered {
+                stagger_x,
+                stagger_even,
+            } => {
+                if stagger_x {
+                    let mut position = f32::Vec2::new(
+                        tile.x as f32 * half_size.x,
+                        tile.y as f32 * self.tile_size.y,
+                    );
+                    if is_staggered(tile.x, stagger_even) {
+                        position.y += half_size.y;
+                    }
+                    position
+                } else {
+                    let mut position = f32::Vec2::new(
+                        tile.x as f32 * self.tile_size.x,
+                        tile.y as f32 * half_size.y,
+                    );
+                    if is_staggered(tile.y, stagger_even) {
+                        position.x += half_size.x;
+                    }
+                    position
+                }
+            }
+        }
+    }
+
+    /// Returns the tile containing the world `position`. The tile may be outside of the map, see
+    /// `contains_tile`.
+    pub fn world_to_tile(&self, position: f32::Vec2) -> IVec2 {
+        match self.orientation {
+            MapOrientation::Orthogonal => (position / self.tile_size).floor().as_ivec2(),
+            MapOrientation::Isometric => {
+                let origin_x = self.size.y as f32 * self.tile_size.x / 2.0;
+                let x = position.x - origin_x;
+                let tile_y = position.y / self.tile_size.y;
+                let tile_x = x / self.tile_size.x;
+                IVec2::new(
+                    (tile_y + tile_x).floor() as i32,
+                    (tile_y - tile_x).floor() as i32,
+                )
+            }
+            MapOrientation::Staggered {
+                stagger_x,
+                stagger_even,
+            } => self.staggered_world_to_tile(position, stagger_x, stagger_even),
+        }
+    }
+
+    /// Staggered maps are laid out as a grid of diamonds. The position is first located in a grid
+    /// aligned cell and then moved to the neighbouring tile when it falls in one of the corners.
+    fn staggered_world_to_tile(
+        &self,
+        position: f32::Vec2,
+        stagger_x: bool,
+        stagger_even: bool,
+    ) -> IVec2 {
+        let half_size = self.tile_size / 2.0;
+
+        let mut position = position;
+        if stagger_even {
+            if stagger_x {
+                position.x -= half_size.x;
+            } else {
+                position.y -= half_size.y;
+            }
+        }
+
+        let mut tile = (position / self.tile_size).floor().as_ivec2();
+        let relative = position - tile.as_vec2() * self.tile_size;
+
+        if stagger_x {
+            tile.x = tile.x * 2 + stagger_even as i32;
+        } else {
+            tile.y = tile.y * 2 + stagger_even as i32;
+        }
+
+        let y_pos = relative.x * (self.tile_size.y / self.tile_size.x);
+
+        if half_size.y - y_pos > relative.y {
+            tile = self.staggered_neighbour(tile, -1, -1, stagger_x, stagger_even);
+        }
+        if -half_size.y + y_pos > relative.y {
+            tile = self.staggered_neighbour(tile, 1, -1, stagger_x, stagger_even);
+        }
+        if half_size.y + y_pos < relative.y {
+            tile = self.staggered_neighbour(tile, -1, 1, stagger_x, stagger_even);
+        }
+        if half_size.y * 3.0 - y_pos < relative.y {
+            tile = self.staggered_neighbour(tile, 1, 1, stagger_x, stagger_even);
+        }
+
+        tile
+    }
+
+    /// Returns the diagonal neighbour of a staggered tile in the (`dx`, `dy`) screen direction.
+    fn staggered_neighbour(
+        &self,
+        tile: IVec2,
+        dx: i32,
+        dy: i32,
+        stagger_x: bool,
+        stagger_even: bool,
+    ) -> IVec2 {
+        if stagger_x {
+            let is_shifted = is_staggered(tile.x, stagger_even);
+            let y = match (dy < 0, is_shifted) {
+                (true, true) => tile.y,
+                (true, false) => tile.y - 1,
+                (false, true) => tile.y + 1,
+                (false, false) => tile.y,
+            };
+            IVec2::new(tile.x + dx, y)
+        } else {
+            let is_shifted = is_staggered(tile.y, stagger_even);
+            let x = match (dx < 0, is_shifted) {
+                (true, true) => tile.x,
+                (true, false) => tile.x - 1,
+                (false, true) => tile.x + 1,
+                (false, false) => tile.x,
+            };
+            IVec2::new(x, tile.y + dy)
+        }
+    }
+
+    /// Converts a position in Tiled object coordinates to a world position.
+    ///
+    /// Isometric maps store object positions in an unprojected space where both axes are measured
+    /// in tile heights. Every other orientation already uses world positions.
+    pub fn object_to_world(&self, position: f32::Vec2) -> f32::Vec2 {
+        match self.orientation {
+            MapOrientation::Isometric => {
+                let tile = position / self.tile_size.y;
+                let half_size = self.tile_size / 2.0;
+                let origin_x = self.size.y as f32 * half_size.x;
+                f32::Vec2::new(
+                    (tile.x - tile.y) * half_size.x + origin_x,
+                    (tile.x + tile.y) * half_size.y,
+                )
+            }
+            _ => position,
+        }
+    }
+
+    /// Returns the world position of the center of the tile.
+    pub fn tile_center(&self, tile: IVec2) -> f32::Vec2 {
+        self.tile_to_world(tile) + self.tile_size / 2.0
+    }
+
+    /// Returns the world corners of a tile in clockwise order, starting at the top. Can be used to
+    /// draw a tile outline.
+    pub fn tile_corners(&self, tile: IVec2) -> [f32::Vec2; 4] {
+        let position = self.tile_to_world(tile);
+        let size = self.tile_size;
+        let half_size = size / 2.0;
+
+        match self.orientation {
+            MapOrientation::Orthogonal => [
+                position,
+                position + f32::Vec2::new(size.x, 0.0),
+                position + size,
+                position + f32::Vec2::new(0.0, size.y),
+            ],
+            MapOrientation::Isometric | MapOrientation::Staggered { .. } => [
+                position + f32::Vec2::new(half_size.x, 0.0),
+                position + f32::Vec2::new(size.x, half_size.y),
+                position + f32::Vec2::new(half_size.x, size.y),
+                position + f32::Vec2::new(0.0, half_size.y),
+            ],
+        }
+    }
+
+    pub fn contains_tile(&self, tile: IVec2) -> bool {
+        tile.x >= 0 && tile.y >= 0 && tile.x < self.size.x && tile.y < self.size.y
+    }
+
+    /// Key that sorts tiles of the same layer in the order they should be rendered at.
+    ///
+    /// Row by row works for every orientation except staggered maps along the X axis, where the
+    /// shifted columns of a row have to be rendered after the other ones.
+    pub fn render_order(&self, tile: IVec2) -> (i32, bool, i32) {
+        match self.orientation {
+            MapOrientation::Staggered {
+                stagger_x: true,
+                stagger_even,
+            } => (tile.y, is_staggered(tile.x, stagger_even), tile.x),
+            _ => (tile.y, false, tile.x),
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum MapOrientation {
+    Orthogonal,
+    Isometric,
+    /// Staggered isometric. Every other row (or column when `stagger_x` is set) is shifted by half
+    /// a tile. `stagger_even` selects whether even or odd ones are shifted.
+    Staggered {
+        stagger_x: bool,
+        stagger_even: bool,
+    },
+}
+
+/// Whether the row or column at `index` is shifted in a staggered map.
+fn is_staggered(index: i32, stagger_even: bool) -> bool {
+    (index & 1 == 1) != stagger_even
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn map_grid(orientation: MapOrientation) -> MapGrid {
+        MapGrid {
+            orientation,
+            size: IVec2::new(7, 6),
+            tile_size: f32::Vec2::new(64.0, 32.0),
+        }
+    }
+
+    /// Checks the center of every tile of the map, and points around it inside the tile, map back
+    /// to the tile.
+    fn assert_round_trip(map_grid: &MapGrid) {
+        // Small enough to stay inside the diamond of isometric tiles
+        let quarter_size = map_grid.tile_size / 4.0 - 1.0;
+        let offsets = [
+            f32::Vec2::ZERO,
+            f32::Vec2::new(quarter_size.x, 0.0),
+            f32::Vec2::new(-quarter_size.x, 0.0),
+            f32::Vec2::new(0.0, quarter_size.y),
+            f32::Vec2::new(0.0, -quarter_size.y),
+        ];
+
+        for y in -1..=map_grid.size.y {
+            for x in -1..=map_grid.size.x {
+                let tile = IVec2::new(x, y);
+                let center = map_grid.tile_center(tile);
+                for offset in offsets {
+                    assert_eq!(
+                        map_grid.world_to_tile(center + offset),
+                        tile,
+                        "{:?} at {}",
+                        map_grid.orientation,
+                        center + offset
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn orthogonal_tile_center_round_trips() {
+        assert_round_trip(&map_grid(MapOrientation::Orthogonal));
+    }
+
+    #[test]
+    fn isometric_tile_center_round_trips() {
+        assert_round_trip(&map_grid(MapOrientation::Isometric));
+    }
+
+    #[test]
+    fn staggered_tile_center_round_trips() {
+        for stagger_x in [false, true] {
+            for stagger_even in [false, true] {
+                assert_round_trip(&map_grid(MapOrientation::Staggered {
+                    stagger_x,
+                    stagger_even,
+                }));
+            }
+        }
+    }
+}
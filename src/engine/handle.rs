@@ -0,0 +1,158 @@
+use std::{fmt, hash, marker::PhantomData};
+
+/// Generational handle to an entry of a manager. `T` is the manager type, so handles from
+/// different managers can't be mixed up.
+///
+/// When an entry is removed its slot can be reused by a new one, which bumps the slot generation.
+/// Handles to the removed entry are then stale and are detected instead of pointing at the new
+/// entry.
+pub struct Handle<T> {
+    index: usize,
+    generation: u32,
+    _manager: PhantomData<fn() -> T>,
+}
+
+impl<T> Handle<T> {
+    /// Handle that never points to an entry.
+    pub const INVALID: Self = Self {
+        index: usize::MAX,
+        generation: u32::MAX,
+        _manager: PhantomData,
+    };
+
+    /// Slot index in the manager arrays. Only meaningful while the handle is valid.
+    pub fn index(&self) -> usize {
+        self.index
+    }
+}
+
+// Manual implementations, deriving them would require `T` to implement the traits as well.
+impl<T> Clone for Handle<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T> Copy for Handle<T> {}
+
+impl<T> PartialEq for Handle<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.index == other.index && self.generation == other.generation
+    }
+}
+
+impl<T> Eq for Handle<T> {}
+
+impl<T> hash::Hash for Handle<T> {
+    fn hash<H: hash::Hasher>(&self, state: &mut H) {
+        self.index.hash(state);
+        self.generation.hash(state);
+    }
+}
+
+impl<T> fmt::Debug for Handle<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Handle({}v{})", self.index, self.generation)
+    }
+}
+
+/// Keeps track of the used slots of a manager and their generations.
+///
+/// Managers keep their data in arrays indexed by slot. `allocate` returns either a freed slot, whose
+/// data must be overwritten, or the next slot at the end of the arrays, whose data must be pushed
+/// (see `insert_at`).
+pub struct HandleAllocator<T> {
+    generation: Vec<u32>,
+    is_alive: Vec<bool>,
+    free_i: Vec<usize>,
+    _manager: PhantomData<fn() -> T>,
+}
+
+impl<T> HandleAllocator<T> {
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self {
+            generation: Vec::with_capacity(capacity),
+            is_alive: Vec::with_capacity(capacity),
+            free_i: Vec::with_capacity(capacity),
+            _manager: PhantomData,
+        }
+    }
+
+    /// Amount of slots, alive or not.
+    pub fn len(&self) -> usize {
+        self.generation.len()
+    }
+
+    pub fn allocate(&mut self) -> Handle<T> {
+        let index = match self.free_i.pop() {
+            Some(index) => {
+                self.generation[index] = self.generation[index].wrapping_add(1);
+                self.is_alive[index] = true;
+                index
+            }
+            None => {
+                self.generation.push(0);
+                self.is_alive.push(true);
+                self.len() - 1
+            }
+        };
+
+        Handle {
+            index,
+            generation: self.generation[index],
+            _manager: PhantomData,
+        }
+    }
+
+    /// Frees the slot of a handle so it can be reused. Returns `false` if the handle was stale.
+    pub fn free(&mut self, handle: Handle<T>) -> bool {
+        if !self.is_valid(handle) {
+            return false;
+        }
+
+        self.is_alive[handle.index] = false;
+        self.free_i.push(handle.index);
+
+        true
+    }
+
+    pub fn is_valid(&self, handle: Handle<T>) -> bool {
+        handle.index < self.len()
+            && self.is_alive[handle.index]
+            && self.generation[handle.index] == handle.generation
+    }
+
+    pub fn is_alive(&self, index: usize) -> bool {
+        self.is_alive[index]
+    }
+
+    /// Returns the slot index of a handle. Panics if the handle is stale.
+    pub fn index(&self, handle: Handle<T>) -> usize {
+        assert!(
+            self.is_valid(handle),
+            "Stale or invalid handle {:?} used in {}",
+            handle,
+            std::any::type_name::<T>()
+        );
+
+        handle.index
+    }
+
+    /// Returns the current handle of an alive slot.
+    pub fn handle(&self, index: usize) -> Handle<T> {
+        Handle {
+            index,
+            generation: self.generation[index],
+            _manager: PhantomData,
+        }
+    }
+}
+
+/// Writes `value` at a slot returned by `HandleAllocator::allocate`, pushing it if it's a new one.
+pub fn insert_at<V>(values: &mut Vec<V>, index: usize, value: V) {
+    if index == values.len() {
+        values.push(value);
+    } else {
+        values[index] = value;
+    }
+}
@@ -0,0 +1,599 @@
+use macroquad::input::{
+    is_key_down, is_key_pressed, is_key_released, is_mouse_button_down, is_mouse_button_pressed,
+    is_mouse_button_released, KeyCode, MouseButton,
+};
+
+use super::{
+    asset::{AssetError, AssetKind},
+    file,
+    logging::log,
+};
+
+/// Separates the bindings of an action in the bindings file.
+const BINDING_SEPARATOR: char = ',';
+/// Separates the buttons of a chord in the bindings file.
+const CHORD_SEPARATOR: char = '+';
+/// Starts a comment line in the bindings file.
+const COMMENT_PREFIX: char = '#';
+
+/// Keys that can be bound, by their `KeyCode` name.
+const BINDABLE_KEYS: [KeyCode; 67] = [
+    KeyCode::A,
+    KeyCode::B,
+    KeyCode::C,
+    KeyCode::D,
+    KeyCode::E,
+    KeyCode::F,
+    KeyCode::G,
+    KeyCode::H,
+    KeyCode::I,
+    KeyCode::J,
+    KeyCode::K,
+    KeyCode::L,
+    KeyCode::M,
+    KeyCode::N,
+    KeyCode::O,
+    KeyCode::P,
+    KeyCode::Q,
+    KeyCode::R,
+    KeyCode::S,
+    KeyCode::T,
+    KeyCode::U,
+    KeyCode::V,
+    KeyCode::W,
+    KeyCode::X,
+    KeyCode::Y,
+    KeyCode::Z,
+    KeyCode::Key0,
+    KeyCode::Key1,
+    KeyCode::Key2,
+    KeyCode::Key3,
+    KeyCode::Key4,
+    KeyCode::Key5,
+    KeyCode::Key6,
+    KeyCode::Key7,
+    KeyCode::Key8,
+    KeyCode::Key9,
+    KeyCode::F1,
+    KeyCode::F2,
+    KeyCode::F3,
+    KeyCode::F4,
+    KeyCode::F5,
+    KeyCode::F6,
+    KeyCode::F7,
+    KeyCode::F8,
+    KeyCode::F9,
+    KeyCode::F10,
+    KeyCode::F11,
+    KeyCode::F12,
+    KeyCode::Space,
+    KeyCode::Escape,
+    KeyCode::Enter,
+    KeyCode::Tab,
+    KeyCode::Backspace,
+    KeyCode::Delete,
+    KeyCode::Insert,
+    KeyCode::Home,
+    KeyCode::End,
+    KeyCode::Up,
+    KeyCode::Down,
+    KeyCode::Left,
+    KeyCode::Right,
+    KeyCode::LeftShift,
+    KeyCode::RightShift,
+    KeyCode::LeftControl,
+    KeyCode::RightControl,
+    KeyCode::LeftAlt,
+    KeyCode::RightAlt,
+];
+
+/// Named game action that input buttons are bound to. Systems ask the `InputMgr` for actions
+/// instead of reading keys and mouse buttons directly, so the bindings can be remapped.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum InputAction {
+    Quit,
+    LoadLevel1,
+    LoadLevel2,
+    /// Select units, by clicking or dragging a selection box.
+    Select,
+    /// Order the selected units to move.
+    Command,
+    /// Held to add to the current selection instead of replacing it.
+    AddToSelection,
+    /// Held to queue orders after the current ones instead of replacing them.
+    QueueCommand,
+    CycleFormation,
+    StopCommands,
+    /// Remove the last queued order.
+    PopCommand,
+    /// Order the selected units to attack.
+    Attack,
+}
+
+impl InputAction {
+    pub const COUNT: usize = 11;
+    pub const ALL: [InputAction; InputAction::COUNT] = [
+        Self::Quit,
+        Self::LoadLevel1,
+        Self::LoadLevel2,
+        Self::Select,
+        Self::Command,
+        Self::AddToSelection,
+        Self::QueueCommand,
+        Self::CycleFormation,
+        Self::StopCommands,
+        Self::PopCommand,
+        Self::Attack,
+    ];
+
+    /// Name used in the bindings file.
+    pub fn name(self) -> String {
+        format!("{:?}", self)
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|action| action.name() == name)
+    }
+
+    /// Bindings used when the bindings file doesn't set the action.
+    fn default_bindings(self) -> Vec<InputBinding> {
+        let key = |key_code| InputBinding::new(&[InputButton::Key(key_code)]);
+        let mouse = |mouse_button| InputBinding::new(&[InputButton::Mouse(mouse_button)]);
+
+        match self {
+            Self::Quit => vec![key(KeyCode::Q)],
+            Self::LoadLevel1 => vec![key(KeyCode::Key1)],
+            Self::LoadLevel2 => vec![key(KeyCode::Key2)],
+            Self::Select => vec![mouse(MouseButton::Left)],
+            Self::Command => vec![mouse(MouseButton::Right)],
+            Self::AddToSelection => vec![key(KeyCode::LeftShift)],
+            Self::QueueCommand => vec![key(KeyCode::LeftShift)],
+            Self::CycleFormation => vec![key(KeyCode::F)],
+            Self::StopCommands => vec![key(KeyCode::S)],
+            Self::PopCommand => vec![
+                key(KeyCode::Backspace),
+                InputBinding::new(&[
+                    InputButton::Key(KeyCode::LeftControl),
+                    InputButton::Key(KeyCode::Z),
+                ]),
+            ],
+            Self::Attack => vec![key(KeyCode::A)],
+        }
+    }
+}
+
+/// Key or mouse button.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum InputButton {
+    Key(KeyCode),
+    Mouse(MouseButton),
+}
+
+impl InputButton {
+    /// Parses a button name from the bindings file: a `KeyCode` name like `A`, `Key1` or
+    /// `LeftShift`, or one of `MouseLeft`, `MouseRight` and `MouseMiddle`.
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "MouseLeft" => Some(Self::Mouse(MouseButton::Left)),
+            "MouseRight" => Some(Self::Mouse(MouseButton::Right)),
+            "MouseMiddle" => Some(Self::Mouse(MouseButton::Middle)),
+            _ => BINDABLE_KEYS
+                .into_iter()
+                .find(|key_code| format!("{:?}", key_code) == name)
+                .map(Self::Key),
+        }
+    }
+}
+
+/// Buttons bound to an action. Single buttons trigger it on their own. Chords trigger it with
+/// their last button, while every other one is held.
+#[derive(Clone, PartialEq, Debug)]
+pub struct InputBinding {
+    buttons: Vec<InputButton>,
+}
+
+impl InputBinding {
+    /// Panics if `buttons` is empty.
+    pub fn new(buttons: &[InputButton]) -> Self {
+        assert!(
+            !buttons.is_empty(),
+            "Input bindings need at least one button"
+        );
+
+        Self {
+            buttons: buttons.to_vec(),
+        }
+    }
+
+    /// Parses a binding from the bindings file, like `Q` or `LeftControl+Z`.
+    pub fn from_name(name: &str) -> Option<Self> {
+        let buttons = name
+            .split(CHORD_SEPARATOR)
+            .map(|button| InputButton::from_name(button.trim()))
+            .collect::<Option<Vec<_>>>()?;
+
+        Some(Self::new(&buttons))
+    }
+
+    pub fn buttons(&self) -> &[InputButton] {
+        &self.buttons
+    }
+
+    fn trigger(&self) -> InputButton {
+        *self.buttons.last().unwrap()
+    }
+
+    fn modifiers(&self) -> &[InputButton] {
+        &self.buttons[..self.buttons.len() - 1]
+    }
+}
+
+/// Source of the button states of the current frame.
+pub trait InputSource {
+    fn is_down(&self, button: InputButton) -> bool;
+    /// Whether the button went down this frame.
+    fn is_pressed(&self, button: InputButton) -> bool;
+    /// Whether the button went up this frame.
+    fn is_released(&self, button: InputButton) -> bool;
+}
+
+/// Reads the keyboard and mouse through macroquad.
+pub struct MacroquadInput;
+
+impl InputSource for MacroquadInput {
+    fn is_down(&self, button: InputButton) -> bool {
+        match button {
+            InputButton::Key(key_code) => is_key_down(key_code),
+            InputButton::Mouse(mouse_button) => is_mouse_button_down(mouse_button),
+        }
+    }
+
+    fn is_pressed(&self, button: InputButton) -> bool {
+        match button {
+            InputButton::Key(key_code) => is_key_pressed(key_code),
+            InputButton::Mouse(mouse_button) => is_mouse_button_pressed(mouse_button),
+        }
+    }
+
+    fn is_released(&self, button: InputButton) -> bool {
+        match button {
+            InputButton::Key(key_code) => is_key_released(key_code),
+            InputButton::Mouse(mouse_button) => is_mouse_button_released(mouse_button),
+        }
+    }
+}
+
+/// Maps input buttons to `InputAction`s.
+///
+/// Bindings are loaded from a text file with one action per line, like
+/// `PopCommand = Backspace, LeftControl+Z`. Actions missing from the file keep their default
+/// bindings.
+pub struct InputMgr {
+    /// Bindings of each action, indexed by `InputAction`.
+    bindings: Vec<Vec<InputBinding>>,
+    source: Box<dyn InputSource>,
+    /// Bindings files that failed to load.
+    failed_assets: Vec<AssetError>,
+}
+
+impl InputMgr {
+    /// Creates a manager reading the keyboard and mouse, with the default bindings.
+    pub fn new() -> Self {
+        Self::with_source(Box::new(MacroquadInput))
+    }
+
+    /// Creates a manager reading from `source`, with the default bindings.
+    pub fn with_source(source: Box<dyn InputSource>) -> Self {
+        let bindings = InputAction::ALL
+            .into_iter()
+            .map(InputAction::default_bindings)
+            .collect();
+
+        Self {
+            bindings,
+            source,
+            failed_assets: Vec::new(),
+        }
+    }
+
+    /// Loads the bindings file. Keeps the current bindings if it can't be loaded.
+    pub async fn load_bindings(&mut self, path: &str) {
+        let text = match file::load_file_async(path).await {
+            Ok(bytes) => String::from_utf8(bytes).map_err(|error| error.to_string()),
+            Err(error) => Err(error.to_string()),
+        };
+
+        match text {
+            Ok(text) => self.parse_bindings(&text),
+            Err(message) => {
+                let error = AssetError::new(AssetKind::Bindings, path, message);
+                log::error(format!("Can't load {error}"));
+                self.failed_assets.push(error);
+            }
+        }
+    }
+
+    /// Sets the bindings of the actions in `text`, in the bindings file format. Invalid lines are
+    /// logged and skipped.
+    pub fn parse_bindings(&mut self, text: &str) {
+        for (line_i, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with(COMMENT_PREFIX) {
+                continue;
+            }
+
+            let (action_name, bindings_str) = match line.split_once('=') {
+                Some(pair) => pair,
+                None => {
+                    log::warning(format!(
+                        "Invalid input binding line {}: `{line}`",
+                        line_i + 1
+                    ));
+                    continue;
+                }
+            };
+
+            let action = match InputAction::from_name(action_name.trim()) {
+                Some(action) => action,
+                None => {
+                    log::warning(format!(
+                        "Unknown input action `{}` in line {}",
+                        action_name.trim(),
+                        line_i + 1
+                    ));
+                    continue;
+                }
+            };
+
+            let mut bindings = Vec::new();
+            for binding_str in bindings_str.split(BINDING_SEPARATOR) {
+                let binding_str = binding_str.trim();
+                if binding_str.is_empty() {
+                    continue;
+                }
+
+                match InputBinding::from_name(binding_str) {
+                    Some(binding) => bindings.push(binding),
+                    None => log::warning(format!(
+                        "Unknown input binding `{binding_str}` in line {}",
+                        line_i + 1
+                    )),
+                }
+            }
+
+            self.set_bindings(action, bindings);
+        }
+    }
+
+    pub fn bindings(&self, action: InputAction) -> &[InputBinding] {
+        &self.bindings[action as usize]
+    }
+
+    /// Replaces the bindings of the action. Actions without bindings can't be triggered.
+    pub fn set_bindings(&mut self, action: InputAction, bindings: Vec<InputBinding>) {
+        self.bindings[action as usize] = bindings;
+    }
+
+    pub fn failed_assets(&self) -> &[AssetError] {
+        &self.failed_assets
+    }
+
+    /// Whether every button of any binding of the action is held.
+    pub fn is_action_down(&self, action: InputAction) -> bool {
+        self.bindings(action).iter().any(|binding| {
+            binding
+                .buttons()
+                .iter()
+                .all(|button| self.source.is_down(*button))
+        })
+    }
+
+    /// Whether the action was triggered this frame.
+    pub fn is_action_pressed(&self, action: InputAction) -> bool {
+        self.bindings(action).iter().any(|binding| {
+            self.source.is_pressed(binding.trigger()) && self.are_modifiers_down(binding)
+        })
+    }
+
+    /// Whether the trigger of a binding of the action was let go this frame. Modifiers are
+    /// ignored, so letting them go first still ends the action.
+    pub fn is_action_released(&self, action: InputAction) -> bool {
+        self.bindings(action)
+            .iter()
+            .any(|binding| self.source.is_released(binding.trigger()))
+    }
+
+    fn are_modifiers_down(&self, binding: &InputBinding) -> bool {
+        binding
+            .modifiers()
+            .iter()
+            .all(|button| self.source.is_down(*button))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{cell::RefCell, collections::HashSet, rc::Rc};
+
+    const CONTROL: InputButton = InputButton::Key(KeyCode::LeftControl);
+    const Z: InputButton = InputButton::Key(KeyCode::Z);
+    const BACKSPACE: InputButton = InputButton::Key(KeyCode::Backspace);
+
+    /// Input source driven by code instead of the keyboard and mouse, to run systems without a
+    /// window. Clones share the same state, so a clone can be kept to drive the one given to the
+    /// `InputMgr`.
+    #[derive(Clone, Default)]
+    struct FakeInput {
+        state: Rc<RefCell<FakeInputState>>,
+    }
+
+    #[derive(Default)]
+    struct FakeInputState {
+        down: HashSet<InputButton>,
+        pressed: HashSet<InputButton>,
+        released: HashSet<InputButton>,
+    }
+
+    impl FakeInput {
+        fn new() -> Self {
+            Self::default()
+        }
+
+        fn press(&self, button: InputButton) {
+            let mut state = self.state.borrow_mut();
+            if state.down.insert(button) {
+                state.pressed.insert(button);
+            }
+        }
+
+        fn release(&self, button: InputButton) {
+            let mut state = self.state.borrow_mut();
+            if state.down.remove(&button) {
+                state.released.insert(button);
+            }
+        }
+
+        /// Ends the frame. Held buttons stay down, but are no longer pressed or released.
+        fn next_frame(&self) {
+            let mut state = self.state.borrow_mut();
+            state.pressed.clear();
+            state.released.clear();
+        }
+    }
+
+    impl InputSource for FakeInput {
+        fn is_down(&self, button: InputButton) -> bool {
+            self.state.borrow().down.contains(&button)
+        }
+
+        fn is_pressed(&self, button: InputButton) -> bool {
+            self.state.borrow().pressed.contains(&button)
+        }
+
+        fn is_released(&self, button: InputButton) -> bool {
+            self.state.borrow().released.contains(&button)
+        }
+    }
+
+    fn input_mgr() -> (InputMgr, FakeInput) {
+        let input = FakeInput::new();
+        (InputMgr::with_source(Box::new(input.clone())), input)
+    }
+
+    #[test]
+    fn single_button_is_pressed_down_and_released() {
+        let (input_mgr, input) = input_mgr();
+        let action = InputAction::PopCommand;
+
+        input.press(BACKSPACE);
+        assert!(input_mgr.is_action_pressed(action));
+        assert!(input_mgr.is_action_down(action));
+        assert!(!input_mgr.is_action_released(action));
+
+        input.next_frame();
+        assert!(!input_mgr.is_action_pressed(action));
+        assert!(input_mgr.is_action_down(action));
+
+        input.release(BACKSPACE);
+        assert!(!input_mgr.is_action_down(action));
+        assert!(input_mgr.is_action_released(action));
+
+        input.next_frame();
+        assert!(!input_mgr.is_action_released(action));
+    }
+
+    #[test]
+    fn chord_triggers_with_last_button_while_modifiers_are_held() {
+        let (input_mgr, input) = input_mgr();
+        let action = InputAction::PopCommand;
+
+        // The trigger alone does nothing
+        input.press(Z);
+        assert!(!input_mgr.is_action_pressed(action));
+        assert!(!input_mgr.is_action_down(action));
+        input.release(Z);
+        input.next_frame();
+
+        // Modifier first, then trigger
+        input.press(CONTROL);
+        assert!(!input_mgr.is_action_pressed(action));
+        input.next_frame();
+        input.press(Z);
+        assert!(input_mgr.is_action_pressed(action));
+        assert!(input_mgr.is_action_down(action));
+        input.next_frame();
+
+        // Letting the modifier go first still releases with the trigger
+        input.release(CONTROL);
+        assert!(!input_mgr.is_action_down(action));
+        assert!(!input_mgr.is_action_released(action));
+        input.next_frame();
+        input.release(Z);
+        assert!(input_mgr.is_action_released(action));
+    }
+
+    #[test]
+    fn parse_bindings_replaces_listed_actions() {
+        let (mut input_mgr, _) = input_mgr();
+
+        input_mgr.parse_bindings(
+            "# Comment\n\
+             \n\
+             Quit = Escape, LeftControl + Q\n\
+             Select = MouseRight\n",
+        );
+
+        assert_eq!(
+            input_mgr.bindings(InputAction::Quit),
+            [
+                InputBinding::new(&[InputButton::Key(KeyCode::Escape)]),
+                InputBinding::new(&[CONTROL, InputButton::Key(KeyCode::Q)]),
+            ]
+        );
+        assert_eq!(
+            input_mgr.bindings(InputAction::Select),
+            [InputBinding::new(&[InputButton::Mouse(MouseButton::Right)])]
+        );
+        // Missing from the text
+        assert_eq!(
+            input_mgr.bindings(InputAction::Command),
+            InputAction::Command.default_bindings()
+        );
+    }
+
+    #[test]
+    fn parse_bindings_skips_unknown_actions_and_buttons() {
+        let (mut input_mgr, _) = input_mgr();
+
+        input_mgr.parse_bindings(
+            "Jump = Space\n\
+             Quit = Escape, Hyper, LeftControl+Nope\n\
+             Not a binding\n",
+        );
+
+        assert_eq!(
+            input_mgr.bindings(InputAction::Quit),
+            [InputBinding::new(&[InputButton::Key(KeyCode::Escape)])]
+        );
+        for action in InputAction::ALL {
+            if action != InputAction::Quit {
+                assert_eq!(input_mgr.bindings(action), action.default_bindings());
+            }
+        }
+    }
+
+    #[test]
+    fn parse_bindings_with_empty_list_unbinds_action() {
+        let (mut input_mgr, input) = input_mgr();
+
+        input_mgr.parse_bindings("Quit =\nStopCommands = ,");
+
+        assert!(input_mgr.bindings(InputAction::Quit).is_empty());
+        assert!(input_mgr.bindings(InputAction::StopCommands).is_empty());
+
+        input.press(InputButton::Key(KeyCode::Q));
+        assert!(!input_mgr.is_action_pressed(InputAction::Quit));
+        assert!(!input_mgr.is_action_down(InputAction::Quit));
+    }
+}
@@ -1,10 +1,18 @@
 //! Common engine related systems and functions.
 
+pub mod animation;
+pub mod asset;
+pub mod atlas;
 pub mod camera;
 pub mod collision;
 pub mod diagnostics;
 pub mod file;
+pub mod grid;
+pub mod handle;
+pub mod input;
 pub mod logging;
+pub mod navigation;
+pub mod particle;
 pub mod scene;
 pub mod sprite;
 pub mod tile;
@@ -0,0 +1,521 @@
+use std::{cmp::Reverse, collections::BinaryHeap};
+
+use macroquad::math::{f32, IVec2, Rect};
+
+use super::{collision::collider::ColliderMgr, grid::MapGrid, logging::log, scene::SceneMgr};
+
+/// A* cost of a straight step. Diagonal steps cost `DIAGONAL_COST`, roughly `STRAIGHT_COST * √2`.
+const STRAIGHT_COST: u32 = 10;
+const DIAGONAL_COST: u32 = 14;
+/// Fraction of the cell size between samples when testing if a segment is walkable.
+const SEGMENT_SAMPLE_STEP: f32 = 0.25;
+
+/// Walkability grid used for pathfinding. Cells are axis aligned and sized like the scene tiles,
+/// starting at `origin` in world space.
+///
+/// Grids can be built by hand with `new` and `set_blocked`, so paths can be computed without a
+/// scene.
+pub struct NavGrid {
+    origin: f32::Vec2,
+    cell_size: f32::Vec2,
+    /// Grid size in cells.
+    size: IVec2,
+    is_blocked: Vec<bool>,
+}
+
+impl NavGrid {
+    /// Creates a grid where every cell is walkable.
+    pub fn new(origin: f32::Vec2, cell_size: f32::Vec2, size: IVec2) -> Self {
+        Self {
+            origin,
+            cell_size,
+            size,
+            is_blocked: vec![false; (size.x * size.y) as usize],
+        }
+    }
+
+    /// Creates a grid covering a scene map, blocking its solid tiles and the static colliders,
+    /// like walls and closed doors.
+    pub fn from_scene(
+        map_grid: &MapGrid,
+        solid_tiles: &[IVec2],
+        collider_mgr: &ColliderMgr,
+    ) -> Self {
+        // Bounds of the whole map, which don't start at zero for every orientation
+        let last_tile = map_grid.size - IVec2::ONE;
+        let mut min = f32::Vec2::splat(f32::INFINITY);
+        let mut max = f32::Vec2::splat(f32::NEG_INFINITY);
+        for tile in [
+            IVec2::ZERO,
+            last_tile,
+            IVec2::new(last_tile.x, 0),
+            IVec2::new(0, last_tile.y),
+        ] {
+            for corner in map_grid.tile_corners(tile) {
+                min = min.min(corner);
+                max = max.max(corner);
+            }
+        }
+
+        let cell_size = map_grid.tile_size;
+        let size = ((max - min) / cell_size).ceil().as_ivec2();
+        let mut grid = Self::new(min, cell_size, size);
+
+        for tile in solid_tiles {
+            let center = map_grid.tile_center(*tile);
+            grid.set_blocked(grid.world_to_cell(center), true);
+        }
+
+        collider_mgr.for_each_static_bbox(|bbox| grid.block_rect(bbox));
+
+        grid
+    }
+
+    pub fn contains(&self, cell: IVec2) -> bool {
+        cell.x >= 0 && cell.y >= 0 && cell.x < self.size.x && cell.y < self.size.y
+    }
+
+    /// Cells outside the grid are blocked.
+    pub fn is_blocked(&self, cell: IVec2) -> bool {
+        match self.contains(cell) {
+            true => self.is_blocked[self.cell_index(cell)],
+            false => true,
+        }
+    }
+
+    /// Does nothing for cells outside the grid.
+    pub fn set_blocked(&mut self, cell: IVec2, is_blocked: bool) {
+        if self.contains(cell) {
+            let index = self.cell_index(cell);
+            self.is_blocked[index] = is_blocked;
+        }
+    }
+
+    /// Blocks every cell overlapped by `rect`. Cells it only touches are left walkable.
+    pub fn block_rect(&mut self, rect: &Rect) {
+        let min = ((rect.point() - self.origin) / self.cell_size)
+            .floor()
+            .as_ivec2();
+        let max = ((rect.point() + rect.size() - self.origin) / self.cell_size)
+            .ceil()
+            .as_ivec2();
+
+        for y in min.y..max.y {
+            for x in min.x..max.x {
+                self.set_blocked(IVec2::new(x, y), true);
+            }
+        }
+    }
+
+    pub fn world_to_cell(&self, position: f32::Vec2) -> IVec2 {
+        ((position - self.origin) / self.cell_size)
+            .floor()
+            .as_ivec2()
+    }
+
+    pub fn cell_center(&self, cell: IVec2) -> f32::Vec2 {
+        self.origin + (cell.as_vec2() + 0.5) * self.cell_size
+    }
+
+    /// Walkable cell closest to `position` that is also accepted by `is_free`, searching up to
+    /// `max_distance` cells away. Returns `None` if there is none in range.
+    pub fn nearest_walkable_cell<F: Fn(IVec2) -> bool>(
+        &self,
+        position: f32::Vec2,
+        max_distance: i32,
+        is_free: F,
+    ) -> Option<IVec2> {
+        let center = self.world_to_cell(position);
+
+        // Search square rings of growing size, the closest free cell of the first ring with any
+        for distance in 0..=max_distance {
+            let mut closest: Option<(f32, IVec2)> = None;
+            for y in -distance..=distance {
+                for x in -distance..=distance {
+                    if x.abs() != distance && y.abs() != distance {
+                        continue;
+                    }
+
+                    let cell = center + IVec2::new(x, y);
+                    if self.is_blocked(cell) || !is_free(cell) {
+                        continue;
+                    }
+
+                    let cell_distance = self.cell_center(cell).distance_squared(position);
+                    if closest.is_none_or(|(distance, _)| cell_distance < distance) {
+                        closest = Some((cell_distance, cell));
+                    }
+                }
+            }
+
+            if let Some((_, cell)) = closest {
+                return Some(cell);
+            }
+        }
+
+        None
+    }
+
+    fn cell_index(&self, cell: IVec2) -> usize {
+        (cell.y * self.size.x + cell.x) as usize
+    }
+
+    /// A* search between two cells, with diagonal moves that don't cut blocked corners.
+    ///
+    /// `path` is cleared and filled with the cells to walk through, from the one after `start` to
+    /// `goal`. The start cell may be blocked, so units standing next to walls can still leave.
+    ///
+    /// Returns `false` if the goal can't be reached.
+    pub fn find_path(&self, start: IVec2, goal: IVec2, path: &mut Vec<IVec2>) -> bool {
+        path.clear();
+
+        if !self.contains(start) || self.is_blocked(goal) {
+            return false;
+        }
+        if start == goal {
+            return true;
+        }
+
+        let cell_count = self.is_blocked.len();
+        let mut cost = vec![u32::MAX; cell_count];
+        let mut came_from = vec![usize::MAX; cell_count];
+        let mut open = BinaryHeap::new();
+
+        let start_i = self.cell_index(start);
+        let goal_i = self.cell_index(goal);
+        cost[start_i] = 0;
+        open.push(Reverse((Self::heuristic(start, goal), start_i)));
+
+        while let Some(Reverse((_, current_i))) = open.pop() {
+            if current_i == goal_i {
+                break;
+            }
+
+            let current = IVec2::new(
+                current_i as i32 % self.size.x,
+                current_i as i32 / self.size.x,
+            );
+
+            for (offset, step_cost) in NEIGHBOURS {
+                let neighbour = current + offset;
+                if self.is_blocked(neighbour) {
+                    continue;
+                }
+
+                // Diagonal moves need both adjacent cells free
+                if offset.x != 0 && offset.y != 0 {
+                    let is_corner_blocked = self.is_blocked(current + IVec2::new(offset.x, 0))
+                        || self.is_blocked(current + IVec2::new(0, offset.y));
+                    if is_corner_blocked {
+                        continue;
+                    }
+                }
+
+                let neighbour_i = self.cell_index(neighbour);
+                let neighbour_cost = cost[current_i] + step_cost;
+                if neighbour_cost >= cost[neighbour_i] {
+                    continue;
+                }
+
+                cost[neighbour_i] = neighbour_cost;
+                came_from[neighbour_i] = current_i;
+                let priority = neighbour_cost + Self::heuristic(neighbour, goal);
+                open.push(Reverse((priority, neighbour_i)));
+            }
+        }
+
+        if came_from[goal_i] == usize::MAX {
+            return false;
+        }
+
+        let mut cell_i = goal_i;
+        while cell_i != start_i {
+            path.push(IVec2::new(
+                cell_i as i32 % self.size.x,
+                cell_i as i32 / self.size.x,
+            ));
+            cell_i = came_from[cell_i];
+        }
+        path.reverse();
+
+        true
+    }
+
+    /// Finds a path between two world positions, like `find_path`.
+    ///
+    /// `waypoints` is cleared and filled with the positions to walk through, ending at `goal`.
+    /// Waypoints that can be skipped walking in a straight line are removed.
+    ///
+    /// Returns `false` if the goal can't be reached.
+    pub fn find_world_path(
+        &self,
+        start: f32::Vec2,
+        goal: f32::Vec2,
+        waypoints: &mut Vec<f32::Vec2>,
+    ) -> bool {
+        waypoints.clear();
+
+        let mut path = Vec::new();
+        if !self.find_path(
+            self.world_to_cell(start),
+            self.world_to_cell(goal),
+            &mut path,
+        ) {
+            return false;
+        }
+
+        // Keep only the cells that can't be seen from the last kept waypoint
+        let mut from = start;
+        for (i, cell) in path.iter().enumerate() {
+            let next = match path.get(i + 1) {
+                Some(next_cell) => self.cell_center(*next_cell),
+                None => goal,
+            };
+            if !self.is_segment_walkable(from, next) {
+                from = self.cell_center(*cell);
+                waypoints.push(from);
+            }
+        }
+        waypoints.push(goal);
+
+        true
+    }
+
+    /// Whether every cell crossed by the segment from `start` to `end` is walkable. The cell of
+    /// `start` is not tested.
+    pub fn is_segment_walkable(&self, start: f32::Vec2, end: f32::Vec2) -> bool {
+        let start_cell = self.world_to_cell(start);
+        let step = self.cell_size.min_element() * SEGMENT_SAMPLE_STEP;
+        let sample_count = (start.distance(end) / step).ceil() as usize;
+
+        (1..=sample_count).all(|i| {
+            let position = start.lerp(end, i as f32 / sample_count as f32);
+            let cell = self.world_to_cell(position);
+            cell == start_cell || !self.is_blocked(cell)
+        })
+    }
+
+    /// Octile distance, admissible with diagonal moves.
+    fn heuristic(cell: IVec2, goal: IVec2) -> u32 {
+        let distance = (goal - cell).abs();
+        let diagonal = distance.x.min(distance.y) as u32;
+        let straight = distance.x.max(distance.y) as u32 - diagonal;
+
+        diagonal * DIAGONAL_COST + straight * STRAIGHT_COST
+    }
+}
+
+const NEIGHBOURS: [(IVec2, u32); 8] = [
+    (IVec2::new(1, 0), STRAIGHT_COST),
+    (IVec2::new(-1, 0), STRAIGHT_COST),
+    (IVec2::new(0, 1), STRAIGHT_COST),
+    (IVec2::new(0, -1), STRAIGHT_COST),
+    (IVec2::new(1, 1), DIAGONAL_COST),
+    (IVec2::new(1, -1), DIAGONAL_COST),
+    (IVec2::new(-1, 1), DIAGONAL_COST),
+    (IVec2::new(-1, -1), DIAGONAL_COST),
+];
+
+/// Keeps the navigation grid of the active scene up to date. The grid is rebuilt when the scene
+/// changes and when static colliders change, like doors opening or closing.
+pub struct NavMgr {
+    grid: Option<NavGrid>,
+    /// Scene and `ColliderMgr::static_version` the grid was built for.
+    built_for: Option<(usize, u32)>,
+    /// Incremented each time the grid is rebuilt or removed.
+    version: u32,
+}
+
+impl NavMgr {
+    pub fn new() -> Self {
+        Self {
+            grid: None,
+            built_for: None,
+            version: 0,
+        }
+    }
+
+    /// Rebuilds the grid if it's outdated. Should run after the systems that spawn or move static
+    /// colliders.
+    pub fn update(&mut self, scene_mgr: &SceneMgr, collider_mgr: &ColliderMgr) {
+        let (scene_id, map_grid) = match (scene_mgr.active_scene_id, scene_mgr.active_grid()) {
+            (Some(scene_id), Some(map_grid)) => (scene_id, map_grid),
+            _ => {
+                if self.grid.take().is_some() {
+                    self.version = self.version.wrapping_add(1);
+                }
+                self.built_for = None;
+                return;
+            }
+        };
+
+        let built_for = Some((scene_id, collider_mgr.static_version()));
+        if self.built_for == built_for {
+            return;
+        }
+
+        log::debug("Building navigation grid");
+        self.grid = Some(NavGrid::from_scene(
+            map_grid,
+            scene_mgr.active_solid_tiles(),
+            collider_mgr,
+        ));
+        self.built_for = built_for;
+        self.version = self.version.wrapping_add(1);
+    }
+
+    pub fn grid(&self) -> Option<&NavGrid> {
+        self.grid.as_ref()
+    }
+
+    /// Changes when the grid does. Paths found on an older version may cross closed doors.
+    pub fn version(&self) -> u32 {
+        self.version
+    }
+
+    /// Finds a path in the active scene, see `NavGrid::find_world_path`. Returns `false` if there
+    /// is no grid or the goal can't be reached.
+    pub fn find_path(
+        &self,
+        start: f32::Vec2,
+        goal: f32::Vec2,
+        waypoints: &mut Vec<f32::Vec2>,
+    ) -> bool {
+        match &self.grid {
+            Some(grid) => grid.find_world_path(start, goal, waypoints),
+            None => {
+                waypoints.clear();
+                false
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CELL_SIZE: f32 = 16.0;
+
+    fn grid(width: i32, height: i32) -> NavGrid {
+        NavGrid::new(
+            f32::Vec2::ZERO,
+            f32::Vec2::splat(CELL_SIZE),
+            IVec2::new(width, height),
+        )
+    }
+
+    /// Checks each step of `path` moves to a free neighbour without cutting blocked corners.
+    fn assert_walkable(nav_grid: &NavGrid, start: IVec2, path: &[IVec2]) {
+        let mut previous = start;
+        for cell in path {
+            let offset = *cell - previous;
+            assert!(
+                offset.abs().max_element() == 1,
+                "{previous} -> {cell} is not a neighbour"
+            );
+            assert!(!nav_grid.is_blocked(*cell), "{cell} is blocked");
+            if offset.x != 0 && offset.y != 0 {
+                assert!(!nav_grid.is_blocked(previous + IVec2::new(offset.x, 0)));
+                assert!(!nav_grid.is_blocked(previous + IVec2::new(0, offset.y)));
+            }
+            previous = *cell;
+        }
+    }
+
+    #[test]
+    fn find_path_goes_around_wall() {
+        let mut nav_grid = grid(10, 10);
+        for y in 0..8 {
+            nav_grid.set_blocked(IVec2::new(5, y), true);
+        }
+
+        let start = IVec2::new(2, 2);
+        let goal = IVec2::new(8, 2);
+        let mut path = Vec::new();
+        assert!(nav_grid.find_path(start, goal, &mut path));
+
+        assert_eq!(path.last(), Some(&goal));
+        assert_walkable(&nav_grid, start, &path);
+        assert!(path.iter().any(|cell| cell.y >= 8));
+    }
+
+    #[test]
+    fn find_path_fails_for_unreachable_goal() {
+        let mut nav_grid = grid(10, 10);
+        for y in 0..10 {
+            nav_grid.set_blocked(IVec2::new(5, y), true);
+        }
+
+        let mut path = vec![IVec2::ZERO];
+        assert!(!nav_grid.find_path(IVec2::new(2, 2), IVec2::new(8, 2), &mut path));
+        assert!(path.is_empty());
+
+        // Blocked and out of bounds goals
+        assert!(!nav_grid.find_path(IVec2::new(2, 2), IVec2::new(5, 2), &mut path));
+        assert!(!nav_grid.find_path(IVec2::new(2, 2), IVec2::new(2, 12), &mut path));
+    }
+
+    #[test]
+    fn find_path_does_not_cut_corners() {
+        let mut nav_grid = grid(3, 3);
+        nav_grid.set_blocked(IVec2::new(1, 0), true);
+
+        let start = IVec2::new(0, 0);
+        let mut path = Vec::new();
+        assert!(nav_grid.find_path(start, IVec2::new(1, 1), &mut path));
+        assert_eq!(path, [IVec2::new(0, 1), IVec2::new(1, 1)]);
+
+        // Both corners blocked seal the diagonal
+        nav_grid.set_blocked(IVec2::new(0, 1), true);
+        assert!(!nav_grid.find_path(start, IVec2::new(1, 1), &mut path));
+    }
+
+    #[test]
+    fn find_path_leaves_blocked_start() {
+        let mut nav_grid = grid(5, 5);
+        let start = IVec2::new(2, 2);
+        nav_grid.set_blocked(start, true);
+
+        let goal = IVec2::new(4, 4);
+        let mut path = Vec::new();
+        assert!(nav_grid.find_path(start, goal, &mut path));
+
+        assert!(!path.contains(&start));
+        assert_eq!(path.last(), Some(&goal));
+        assert_walkable(&nav_grid, start, &path);
+    }
+
+    #[test]
+    fn find_world_path_prunes_visible_waypoints() {
+        let mut nav_grid = grid(10, 10);
+        let start = nav_grid.cell_center(IVec2::new(1, 1));
+        let goal = nav_grid.cell_center(IVec2::new(8, 6));
+
+        // Nothing in the way: straight to the goal
+        let mut waypoints = Vec::new();
+        assert!(nav_grid.find_world_path(start, goal, &mut waypoints));
+        assert_eq!(waypoints, [goal]);
+
+        // Around a wall: fewer waypoints than cells, each one visible from the previous one
+        for y in 0..8 {
+            nav_grid.set_blocked(IVec2::new(5, y), true);
+        }
+        let mut path = Vec::new();
+        assert!(nav_grid.find_path(
+            nav_grid.world_to_cell(start),
+            nav_grid.world_to_cell(goal),
+            &mut path,
+        ));
+        assert!(nav_grid.find_world_path(start, goal, &mut waypoints));
+
+        assert!(waypoints.len() > 1);
+        assert!(waypoints.len() < path.len());
+        assert_eq!(waypoints.last(), Some(&goal));
+        let mut from = start;
+        for waypoint in &waypoints {
+            assert!(nav_grid.is_segment_walkable(from, *waypoint));
+            from = *waypoint;
+        }
+    }
+}
@@ -0,0 +1,399 @@
+use macroquad::{
+    color::Color,
+    math::{f32, Vec4},
+    rand,
+    texture::DrawTextureParams,
+};
+
+use super::{
+    handle::{insert_at, Handle, HandleAllocator},
+    sprite::{SpriteHandle, SpriteMgr, Texture2dMgr},
+};
+
+const MAX_EMITTER_COUNT: usize = 128;
+const MAX_PARTICLE_COUNT: usize = 4096;
+
+/// Particle emitter parameters. Ranges are (min, max) tuples, each particle picks a random value
+/// in them. Values over lifetime are (start, end) tuples, interpolated over each particle's life.
+#[derive(Clone, Debug)]
+pub struct EmitterConfig {
+    /// Particles emitted at once when the emitter is added.
+    pub burst_count: u32,
+    /// Particles emitted per second after the burst. Zero for one-shot bursts.
+    pub spawn_rate: f32,
+    /// Time the emitter keeps emitting, in seconds. `None` emits until stopped or removed.
+    pub duration: Option<f32>,
+    /// Particle lifetime range in seconds.
+    pub lifetime: (f32, f32),
+    /// Initial velocity range, per axis, in pixels per second.
+    pub velocity: (f32::Vec2, f32::Vec2),
+    /// Constant acceleration of every particle, like gravity.
+    pub acceleration: f32::Vec2,
+    pub color: (Color, Color),
+    /// Particle size in pixels.
+    pub size: (f32, f32),
+    /// Texture index in `Texture2dMgr`. Particles without texture are drawn as squares.
+    pub texture_i: Option<usize>,
+}
+
+/// Simulates and renders particles from emitters.
+///
+/// Emitters are placed at world positions or attached to sprites, which they follow. Emitters
+/// are referenced with generational `EmitterHandle`s, and are removed automatically once they
+/// stop emitting and their last particle dies.
+///
+/// Simulation (`update`) doesn't depend on rendering, so it can run without a window.
+pub struct ParticleMgr {
+    // Emitters
+    handles: HandleAllocator<ParticleMgr>,
+    config: Vec<EmitterConfig>,
+    emitter_position: Vec<f32::Vec2>,
+    /// Sprite the emitter follows and its offset from the sprite position.
+    attached_sprite: Vec<Option<(SpriteHandle, f32::Vec2)>>,
+    emitter_age: Vec<f32>,
+    /// Fractional particles left from the previous updates.
+    spawn_accumulator: Vec<f32>,
+    is_emitting: Vec<bool>,
+    /// Alive particles of each emitter.
+    particle_count: Vec<usize>,
+
+    // Particles
+    position: Vec<f32::Vec2>,
+    velocity: Vec<f32::Vec2>,
+    age: Vec<f32>,
+    lifetime: Vec<f32>,
+    /// Emitter slot of each particle.
+    emitter_i: Vec<usize>,
+}
+
+impl ParticleMgr {
+    pub fn new() -> Self {
+        let handles = HandleAllocator::with_capacity(MAX_EMITTER_COUNT);
+        let config = Vec::with_capacity(MAX_EMITTER_COUNT);
+        let emitter_position = Vec::with_capacity(MAX_EMITTER_COUNT);
+        let attached_sprite = Vec::with_capacity(MAX_EMITTER_COUNT);
+        let emitter_age = Vec::with_capacity(MAX_EMITTER_COUNT);
+        let spawn_accumulator = Vec::with_capacity(MAX_EMITTER_COUNT);
+        let is_emitting = Vec::with_capacity(MAX_EMITTER_COUNT);
+        let particle_count = Vec::with_capacity(MAX_EMITTER_COUNT);
+
+        let position = Vec::with_capacity(MAX_PARTICLE_COUNT);
+        let velocity = Vec::with_capacity(MAX_PARTICLE_COUNT);
+        let age = Vec::with_capacity(MAX_PARTICLE_COUNT);
+        let lifetime = Vec::with_capacity(MAX_PARTICLE_COUNT);
+        let emitter_i = Vec::with_capacity(MAX_PARTICLE_COUNT);
+
+        Self {
+            handles,
+            config,
+            emitter_position,
+            attached_sprite,
+            emitter_age,
+            spawn_accumulator,
+            is_emitting,
+            particle_count,
+
+            position,
+            velocity,
+            age,
+            lifetime,
+            emitter_i,
+        }
+    }
+
+    /// Adds an emitter at a world position. Its burst is emitted on the next `update`.
+    pub fn add_emitter(&mut self, config: EmitterConfig, position: f32::Vec2) -> EmitterHandle {
+        let handle = self.handles.allocate();
+        let i = handle.index();
+
+        // The burst is emitted by the first update
+        let spawn_accumulator = config.burst_count as f32;
+
+        insert_at(&mut self.config, i, config);
+        insert_at(&mut self.emitter_position, i, position);
+        insert_at(&mut self.attached_sprite, i, None);
+        insert_at(&mut self.emitter_age, i, 0.0);
+        insert_at(&mut self.spawn_accumulator, i, spawn_accumulator);
+        insert_at(&mut self.is_emitting, i, true);
+        insert_at(&mut self.particle_count, i, 0);
+
+        handle
+    }
+
+    /// Adds an emitter that follows a sprite, at an `offset` from the sprite position.
+    pub fn add_emitter_attached(
+        &mut self,
+        config: EmitterConfig,
+        sprite: SpriteHandle,
+        offset: f32::Vec2,
+        sprite_mgr: &SpriteMgr,
+    ) -> EmitterHandle {
+        let emitter = self.add_emitter(config, sprite_mgr.position(sprite) + offset);
+        self.attached_sprite[emitter.index()] = Some((sprite, offset));
+
+        emitter
+    }
+
+    /// Stops emitting new particles. The emitter is removed once its particles die. Does nothing if
+    /// it was already removed.
+    pub fn stop(&mut self, emitter: EmitterHandle) {
+        if !self.handles.is_valid(emitter) {
+            return;
+        }
+
+        self.is_emitting[emitter.index()] = false;
+    }
+
+    /// Removes an emitter and its particles right away.
+    pub fn remove(&mut self, emitter: EmitterHandle) {
+        let index = self.handles.index(emitter);
+
+        let mut i = 0;
+        while i < self.particle_len() {
+            if self.emitter_i[i] == index {
+                self.remove_particle(i);
+            } else {
+                i += 1;
+            }
+        }
+
+        self.is_emitting[index] = false;
+        self.handles.free(emitter);
+    }
+
+    /// Whether the emitter hasn't been removed yet.
+    pub fn is_valid(&self, emitter: EmitterHandle) -> bool {
+        self.handles.is_valid(emitter)
+    }
+
+    /// Amount of emitter slots, including removed ones.
+    pub fn len(&self) -> usize {
+        self.config.len()
+    }
+
+    /// Amount of alive particles.
+    pub fn particle_len(&self) -> usize {
+        self.position.len()
+    }
+
+    /// Emits new particles, moves the alive ones and removes the dead ones.
+    pub fn update(&mut self, dt: f32, sprite_mgr: &SpriteMgr) {
+        // Age and move particles
+        let mut i = 0;
+        while i < self.particle_len() {
+            self.age[i] += dt;
+            if self.age[i] >= self.lifetime[i] {
+                self.remove_particle(i);
+                continue;
+            }
+
+            let acceleration = self.config[self.emitter_i[i]].acceleration;
+            self.velocity[i] += acceleration * dt;
+            self.position[i] += self.velocity[i] * dt;
+
+            i += 1;
+        }
+
+        // Emit
+        for emitter_i in 0..self.len() {
+            if !self.is_emitting[emitter_i] {
+                continue;
+            }
+
+            if let Some((sprite, offset)) = self.attached_sprite[emitter_i] {
+                // Emitters of removed sprites stop emitting
+                if sprite_mgr.is_valid(sprite) {
+                    self.emitter_position[emitter_i] = sprite_mgr.position(sprite) + offset;
+                } else {
+                    self.is_emitting[emitter_i] = false;
+                    continue;
+                }
+            }
+
+            let config = &self.config[emitter_i];
+            self.spawn_accumulator[emitter_i] += config.spawn_rate * dt;
+            self.emitter_age[emitter_i] += dt;
+
+            while self.spawn_accumulator[emitter_i] >= 1.0 {
+                self.spawn_accumulator[emitter_i] -= 1.0;
+                self.spawn_particle(emitter_i);
+            }
+
+            if let Some(duration) = self.config[emitter_i].duration {
+                if self.emitter_age[emitter_i] >= duration {
+                    self.is_emitting[emitter_i] = false;
+                }
+            }
+        }
+
+        // Remove finished emitters
+        for emitter_i in 0..self.len() {
+            if self.handles.is_alive(emitter_i)
+                && !self.is_emitting[emitter_i]
+                && self.particle_count[emitter_i] == 0
+            {
+                self.handles.free(self.handles.handle(emitter_i));
+            }
+        }
+    }
+
+    fn spawn_particle(&mut self, emitter_i: usize) {
+        // New particles are dropped when the pool is full
+        if self.particle_len() >= MAX_PARTICLE_COUNT {
+            return;
+        }
+
+        let config = &self.config[emitter_i];
+        let (velocity_min, velocity_max) = config.velocity;
+        let velocity = f32::Vec2::new(
+            rand::gen_range(velocity_min.x, velocity_max.x),
+            rand::gen_range(velocity_min.y, velocity_max.y),
+        );
+        let lifetime = rand::gen_range(config.lifetime.0, config.lifetime.1);
+
+        self.position.push(self.emitter_position[emitter_i]);
+        self.velocity.push(velocity);
+        self.age.push(0.0);
+        self.lifetime.push(lifetime);
+        self.emitter_i.push(emitter_i);
+
+        self.particle_count[emitter_i] += 1;
+    }
+
+    /// Particle order doesn't matter, so they are swap removed.
+    fn remove_particle(&mut self, index: usize) {
+        self.particle_count[self.emitter_i[index]] -= 1;
+
+        self.position.swap_remove(index);
+        self.velocity.swap_remove(index);
+        self.age.swap_remove(index);
+        self.lifetime.swap_remove(index);
+        self.emitter_i.swap_remove(index);
+    }
+
+    /// Renders every alive particle, centered on its position.
+    pub fn render(&self, texture_mgr: &Texture2dMgr) {
+        for i in 0..self.particle_len() {
+            let config = &self.config[self.emitter_i[i]];
+            let t = self.age[i] / self.lifetime[i];
+
+            let color = Color::from_vec(Vec4::lerp(
+                config.color.0.to_vec(),
+                config.color.1.to_vec(),
+                t,
+            ));
+            let size = config.size.0 + (config.size.1 - config.size.0) * t;
+            let top_left = self.position[i] - size / 2.0;
+
+            match config.texture_i {
+                Some(texture_i) => {
+                    let params = DrawTextureParams {
+                        dest_size: Some(f32::Vec2::splat(size)),
+                        ..Default::default()
+                    };
+                    texture_mgr.render_texture_ex(texture_i, top_left, color, params);
+                }
+                None => {
+                    macroquad::shapes::draw_rectangle(top_left.x, top_left.y, size, size, color)
+                }
+            }
+        }
+    }
+}
+
+pub type EmitterHandle = Handle<ParticleMgr>;
+
+#[cfg(test)]
+mod tests {
+    use macroquad::color;
+
+    use super::*;
+
+    /// Power of two frame time, so spawn rates accumulate without rounding.
+    const DT: f32 = 1.0 / 64.0;
+
+    fn config(burst_count: u32, spawn_rate: f32, duration: Option<f32>) -> EmitterConfig {
+        EmitterConfig {
+            burst_count,
+            spawn_rate,
+            duration,
+            lifetime: (1.0, 1.0),
+            velocity: (f32::Vec2::ZERO, f32::Vec2::ZERO),
+            acceleration: f32::Vec2::ZERO,
+            color: (color::WHITE, color::WHITE),
+            size: (1.0, 1.0),
+            texture_i: None,
+        }
+    }
+
+    #[test]
+    fn burst_is_emitted_by_first_update() {
+        let sprite_mgr = SpriteMgr::new();
+        let mut particle_mgr = ParticleMgr::new();
+        particle_mgr.add_emitter(config(12, 0.0, Some(0.0)), f32::Vec2::ZERO);
+        assert_eq!(particle_mgr.particle_len(), 0);
+
+        particle_mgr.update(DT, &sprite_mgr);
+        assert_eq!(particle_mgr.particle_len(), 12);
+
+        // Bursts are only emitted once
+        particle_mgr.update(DT, &sprite_mgr);
+        assert_eq!(particle_mgr.particle_len(), 12);
+    }
+
+    #[test]
+    fn particles_and_finished_emitters_expire() {
+        let sprite_mgr = SpriteMgr::new();
+        let mut particle_mgr = ParticleMgr::new();
+        let emitter = particle_mgr.add_emitter(config(8, 0.0, Some(0.0)), f32::Vec2::ZERO);
+
+        particle_mgr.update(DT, &sprite_mgr);
+        for _ in 0..63 {
+            particle_mgr.update(DT, &sprite_mgr);
+            assert_eq!(particle_mgr.particle_len(), 8);
+            assert!(particle_mgr.is_valid(emitter));
+        }
+
+        // One second after the burst
+        particle_mgr.update(DT, &sprite_mgr);
+        assert_eq!(particle_mgr.particle_len(), 0);
+        assert!(!particle_mgr.is_valid(emitter));
+    }
+
+    #[test]
+    fn spawn_rate_emits_over_time() {
+        let sprite_mgr = SpriteMgr::new();
+        let mut particle_mgr = ParticleMgr::new();
+        let mut config = config(0, 32.0, None);
+        config.lifetime = (10.0, 10.0);
+        let emitter = particle_mgr.add_emitter(config, f32::Vec2::ZERO);
+
+        // Half a particle per update
+        for i in 1..=64 {
+            particle_mgr.update(DT, &sprite_mgr);
+            assert_eq!(particle_mgr.particle_len(), i / 2);
+        }
+
+        // Stopped emitters keep their particles
+        particle_mgr.stop(emitter);
+        particle_mgr.update(DT, &sprite_mgr);
+        assert_eq!(particle_mgr.particle_len(), 32);
+        assert!(particle_mgr.is_valid(emitter));
+    }
+
+    #[test]
+    fn duration_stops_emitting() {
+        let sprite_mgr = SpriteMgr::new();
+        let mut particle_mgr = ParticleMgr::new();
+        let mut config = config(4, 64.0, Some(0.25));
+        config.lifetime = (10.0, 10.0);
+        particle_mgr.add_emitter(config, f32::Vec2::ZERO);
+
+        for _ in 0..64 {
+            particle_mgr.update(DT, &sprite_mgr);
+        }
+
+        // The burst, then one particle per update for a quarter second
+        assert_eq!(particle_mgr.particle_len(), 4 + 16);
+    }
+}
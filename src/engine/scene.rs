@@ -1,15 +1,29 @@
-use std::collections::HashMap;
+use std::{
+    collections::{HashMap, VecDeque},
+    ops::Range,
+    sync::Arc,
+};
 
-use macroquad::math::{f32, IVec2};
+use macroquad::math::{f32, IVec2, Rect};
 use tiled;
 
 use crate::{engine::logging::log, file};
 
-use super::{sprite::Texture2dMgr, tile::TileMgr};
+use super::{
+    asset::{AssetError, AssetKind},
+    grid::MapGrid,
+    sprite::Texture2dMgr,
+    tile::TileMgr,
+};
 
 const MAX_SCENE_COUNT: usize = 32;
 const MAX_TILE_COUNT: usize = 32768;
 const TILE_RENDERER_CACHE_SIZE: usize = 8192;
+const MAX_OBJECT_CLASS_COUNT: usize = 64;
+/// Maximum amount of tile layer cells processed in a single loading step.
+const TILE_LOAD_BATCH_SIZE: usize = 256;
+/// Boolean tile property, set in the tileset, of tiles that block movement.
+const SOLID_TILE_PROPERTY: &str = "solid";
 
 /// Loads the game scenes using Tiled.
 /// The scene format is comprised of tile layers and object layers.
@@ -23,6 +37,9 @@ const TILE_RENDERER_CACHE_SIZE: usize = 8192;
 /// Object layers contains `object_name` and `object_class` alongside the corresponding `position`.
 /// Specific game logic code (for example the `Player` manager) should read from here and
 /// use the data as required.
+///
+/// The Tiled `object_id` is also kept so that object properties can reference other objects in
+/// the same scene (see `get_object_property_object`).
 pub struct SceneMgr {
     pub scene_id: Vec<usize>,
     /// Maps scene names to scene ids.
@@ -33,31 +50,65 @@ pub struct SceneMgr {
     pub layer_tag: Vec<LayerTag>,
 
     // Object layer fields
+    /// Object id as assigned by Tiled. Unique only within its scene.
+    pub object_id: Vec<Option<u32>>,
     pub object_name: Vec<Option<String>>,
     pub object_class: Vec<Option<String>>,
     pub object_position: Vec<Option<f32::Vec2>>,
     pub object_size: Vec<Option<f32::Vec2>>,
     pub object_properties: Vec<Option<tiled::Properties>>,
+    /// Maps (`scene_id`, `object_id`) tuples to scene manager indices.
+    object_id_map: HashMap<(usize, u32), usize>,
 
     // Tile layer fields
     pub tile_id: Vec<Option<u32>>,
     pub tileset_id: Vec<Option<usize>>,
     pub tile_position: Vec<Option<IVec2>>,
     pub tile_size: Vec<Option<f32::Vec2>>,
+    pub tile_is_solid: Vec<Option<bool>>,
 
     // Manager properties
     pub loader: Option<tiled::Loader<tiled::DefaultResourceCache, TiledCursorReader>>,
     pc_assets_folder: Option<String>,
 
+    // Scene loading
+    /// Whether each scene (indexed by `scene_id`) has finished loading.
+    is_scene_loaded: Vec<bool>,
+    /// Whether each scene (indexed by `scene_id`) failed to load. Failed scenes are never loaded.
+    is_scene_failed: Vec<bool>,
+    /// Tile grid of each scene (indexed by `scene_id`). Available once the map file is parsed.
+    scene_grid: Vec<Option<MapGrid>>,
+    /// Custom map properties of each scene (indexed by `scene_id`). Available once the map file
+    /// is parsed.
+    scene_properties: Vec<Option<tiled::Properties>>,
+    /// Id and name of the tile layers of each scene (indexed by `scene_id`), from the bottom one.
+    scene_tile_layer: Vec<Vec<(u32, String)>>,
+    /// Scenes waiting to be loaded, as (`scene_id`, path) tuples.
+    load_queue: VecDeque<(usize, String)>,
+    /// Scene currently being loaded.
+    load_job: Option<SceneLoadJob>,
+    /// Maps and tilesets that failed to load. Scenes whose map fails are never loaded.
+    failed_assets: Vec<AssetError>,
+
     // Active scene
     has_pending_spawn: bool,
     has_pending_despawn: bool,
     pub objects_to_despawn: Vec<usize>,
+    /// Maps object classes to the indices in `objects_to_despawn` that have them.
+    despawn_class_map: HashMap<String, Vec<usize>>,
     pub active_scene_id: Option<usize>,
     /// Keeps active object indices
     pub active_objects: Vec<usize>,
+    /// Maps object classes to active object indices. Built at scene activation.
+    active_class_map: HashMap<String, Vec<usize>>,
+    /// Maps object names to active object indices. Built at scene activation.
+    active_name_map: HashMap<String, Vec<usize>>,
     /// Keeps tiles to render
     tile_renderer_cache: Vec<CachedTile>,
+    /// Range of `tile_renderer_cache` of each tile layer of the active scene.
+    tile_layer_range: Vec<Range<usize>>,
+    /// Positions of the solid tiles of the active scene, in any tile layer.
+    active_solid_tiles: Vec<IVec2>,
 }
 
 impl SceneMgr {
@@ -68,25 +119,41 @@ impl SceneMgr {
         let layer_id = Vec::with_capacity(MAX_TILE_COUNT);
         let layer_tag = Vec::with_capacity(MAX_TILE_COUNT);
 
+        let object_id = Vec::with_capacity(MAX_TILE_COUNT);
         let object_name = Vec::with_capacity(MAX_TILE_COUNT);
         let object_class = Vec::with_capacity(MAX_TILE_COUNT);
         let object_position = Vec::with_capacity(MAX_TILE_COUNT);
         let object_size = Vec::with_capacity(MAX_TILE_COUNT);
         let object_properties = Vec::with_capacity(MAX_TILE_COUNT);
+        let object_id_map = HashMap::with_capacity(MAX_TILE_COUNT);
 
         let tile_id = Vec::with_capacity(MAX_TILE_COUNT);
         let tileset_id = Vec::with_capacity(MAX_TILE_COUNT);
         let tile_position = Vec::with_capacity(MAX_TILE_COUNT);
         let tile_size = Vec::with_capacity(MAX_TILE_COUNT);
+        let tile_is_solid = Vec::with_capacity(MAX_TILE_COUNT);
 
         let pc_assets_folder = None;
 
+        let is_scene_loaded = Vec::with_capacity(MAX_SCENE_COUNT);
+        let is_scene_failed = Vec::with_capacity(MAX_SCENE_COUNT);
+        let scene_grid = Vec::with_capacity(MAX_SCENE_COUNT);
+        let scene_properties = Vec::with_capacity(MAX_SCENE_COUNT);
+        let scene_tile_layer = Vec::with_capacity(MAX_SCENE_COUNT);
+        let load_queue = VecDeque::with_capacity(MAX_SCENE_COUNT);
+        let load_job = None;
+
         let has_pending_spawn = false;
         let has_pending_despawn = false;
         let objects_to_despawn = Vec::with_capacity(TILE_RENDERER_CACHE_SIZE);
+        let despawn_class_map = HashMap::with_capacity(MAX_OBJECT_CLASS_COUNT);
         let active_scene_id = None;
         let active_objects = Vec::with_capacity(TILE_RENDERER_CACHE_SIZE);
+        let active_class_map = HashMap::with_capacity(MAX_OBJECT_CLASS_COUNT);
+        let active_name_map = HashMap::with_capacity(TILE_RENDERER_CACHE_SIZE);
         let tile_renderer_cache = Vec::with_capacity(TILE_RENDERER_CACHE_SIZE);
+        let tile_layer_range = Vec::new();
+        let active_solid_tiles = Vec::with_capacity(TILE_RENDERER_CACHE_SIZE);
 
         let loader = None;
 
@@ -97,26 +164,43 @@ impl SceneMgr {
             layer_id,
             layer_tag,
 
+            object_id,
             object_name,
             object_class,
             object_position,
             object_size,
             object_properties,
+            object_id_map,
 
             tile_id,
             tileset_id,
             tile_position,
             tile_size,
+            tile_is_solid,
 
             loader,
             pc_assets_folder,
 
+            is_scene_loaded,
+            is_scene_failed,
+            scene_grid,
+            scene_properties,
+            scene_tile_layer,
+            load_queue,
+            load_job,
+            failed_assets: Vec::new(),
+
             has_pending_spawn,
             has_pending_despawn,
             objects_to_despawn,
+            despawn_class_map,
             active_scene_id,
             tile_renderer_cache,
             active_objects,
+            active_class_map,
+            active_name_map,
+            tile_layer_range,
+            active_solid_tiles,
         }
     }
 
@@ -138,12 +222,14 @@ impl SceneMgr {
         tile_size: f32::Vec2,
         tile_id: u32,
         tileset_id: usize,
+        tile_is_solid: bool,
     ) -> usize {
         self.scene_id.push(scene_id);
 
         self.layer_id.push(layer_id);
         self.layer_tag.push(LayerTag::Tiles);
 
+        self.object_id.push(None);
         self.object_name.push(None);
         self.object_class.push(None);
         self.object_position.push(None);
@@ -154,37 +240,35 @@ impl SceneMgr {
         self.tileset_id.push(Some(tileset_id));
         self.tile_position.push(Some(tile_position));
         self.tile_size.push(Some(tile_size));
+        self.tile_is_solid.push(Some(tile_is_solid));
 
         self.len() - 1
     }
 
-    fn add_object(
-        &mut self,
-        scene_id: usize,
-        layer_id: u32,
-        object_name: String,
-        object_class: String,
-        object_position: f32::Vec2,
-        object_size: Option<f32::Vec2>,
-        object_properties: tiled::Properties,
-    ) -> usize {
+    fn add_object(&mut self, scene_id: usize, layer_id: u32, object: SceneObject) -> usize {
         self.scene_id.push(scene_id);
 
         self.layer_id.push(layer_id);
         self.layer_tag.push(LayerTag::Objects);
 
-        self.object_name.push(Some(object_name));
-        self.object_class.push(Some(object_class));
-        self.object_position.push(Some(object_position));
-        self.object_size.push(object_size);
-        self.object_properties.push(Some(object_properties));
+        self.object_id.push(Some(object.id));
+        self.object_name.push(Some(object.name));
+        self.object_class.push(Some(object.class));
+        self.object_position.push(Some(object.position));
+        self.object_size.push(object.size);
+        self.object_properties.push(Some(object.properties));
 
         self.tile_id.push(None);
         self.tileset_id.push(None);
         self.tile_position.push(None);
         self.tile_size.push(None);
+        self.tile_is_solid.push(None);
 
-        self.len() - 1
+        let index = self.len() - 1;
+
+        self.object_id_map.insert((scene_id, object.id), index);
+
+        index
     }
 
     /// Adds a new scene to the `scene_map` and returns the `scene_id`.
@@ -196,41 +280,168 @@ impl SceneMgr {
         new_id
     }
 
-    /// Loads a Tile map file as a scene and retuns the `scene_id`.
-    pub async fn load_scene(
-        &mut self,
-        path: &str,
-        tile_mgr: &mut TileMgr,
-        texture_mgr: &mut Texture2dMgr,
-    ) -> usize {
-        log::debug(format!("Loading scene: {path}"));
+    /// Queues a Tiled map file to be loaded as a scene and returns the `scene_id`.
+    ///
+    /// The scene is loaded progressively by `load`, a step at a time, and can't be activated until
+    /// `is_scene_loaded` returns `true`.
+    pub fn queue_scene(&mut self, path: &str) -> usize {
+        // Using map file path as scene name
+        let scene_id = self.register_scene(path);
+        self.is_scene_loaded.push(false);
+        self.is_scene_failed.push(false);
+        self.scene_grid.push(None);
+        self.scene_properties.push(None);
+        self.scene_tile_layer.push(Vec::new());
+        self.load_queue.push_back((scene_id, String::from(path)));
+
+        scene_id
+    }
+
+    pub fn is_scene_loaded(&self, scene_id: usize) -> bool {
+        self.is_scene_loaded[scene_id]
+    }
+
+    /// Whether the scene failed to load, because its map file couldn't be parsed. It will never be
+    /// loaded.
+    pub fn is_scene_failed(&self, scene_id: usize) -> bool {
+        self.is_scene_failed[scene_id]
+    }
+
+    /// Whether there are scenes queued or being loaded.
+    pub fn is_loading(&self) -> bool {
+        self.load_job.is_some() || !self.load_queue.is_empty()
+    }
+
+    /// Maps and tilesets that failed to load since the game started.
+    pub fn failed_assets(&self) -> &[AssetError] {
+        &self.failed_assets
+    }
+
+    /// Progress of the scene currently being loaded, if any.
+    pub fn load_progress(&self) -> Option<&SceneLoadProgress> {
+        self.load_job.as_ref().map(|job| &job.progress)
+    }
+
+    /// Runs a single loading step of the queued scenes. Should be called once per frame.
+    ///
+    /// A step is either parsing a map file (and its tilesets), loading a single tile texture,
+    /// loading a batch of up to `TILE_LOAD_BATCH_SIZE` tiles from a tile layer or loading a whole
+    /// object layer.
+    pub async fn load(&mut self, tile_mgr: &mut TileMgr, texture_mgr: &mut Texture2dMgr) {
+        let mut job = match self.load_job.take() {
+            Some(job) => job,
+            None => match self.load_queue.pop_front() {
+                Some((scene_id, path)) => {
+                    log::debug(format!("Loading scene: {path}"));
+                    SceneLoadJob::new(scene_id, path)
+                }
+                None => return,
+            },
+        };
+
+        match job.steps.pop_front() {
+            Some(SceneLoadStep::Map) => self.load_map(&mut job, tile_mgr),
+            Some(SceneLoadStep::Texture {
+                tileset,
+                tileset_id,
+                tile_id,
+            }) => {
+                job.progress.current_asset = tile_mgr
+                    .tile_image_path(&tileset, tile_id)
+                    .unwrap_or_else(|error| error.path);
+                tile_mgr
+                    .load_tile(&tileset, tileset_id, tile_id, texture_mgr)
+                    .await;
+                job.progress.loaded_textures += 1;
+            }
+            Some(SceneLoadStep::TileLayer { layer_index, start }) => {
+                self.load_map_tile_layer(&mut job, layer_index, start, tile_mgr)
+            }
+            Some(SceneLoadStep::ObjectLayer { layer_index }) => {
+                self.load_map_object_layer(&mut job, layer_index)
+            }
+            None => {}
+        }
+
+        if job.steps.is_empty() && job.map.is_none() {
+            log::error(format!("Scene failed to load: {}", job.path));
+            self.is_scene_failed[job.scene_id] = true;
+            return;
+        }
+
+        if job.steps.is_empty() {
+            log::debug(format!("Scene loaded: {}", job.path));
+            self.is_scene_loaded[job.scene_id] = true;
+            return;
+        }
+
+        self.load_job = Some(job);
+    }
+
+    /// Parses the map file and queues the loading steps for its tilesets and layers.
+    fn load_map(&mut self, job: &mut SceneLoadJob, tile_mgr: &mut TileMgr) {
+        job.progress.current_asset = job.path.clone();
 
         let loader = self.loader.as_mut().unwrap();
-        let map = loader.load_tmx_map(path).unwrap();
+        let map = match loader.load_tmx_map(&job.path) {
+            Ok(map) => map,
+            Err(error) => {
+                let error = AssetError::new(AssetKind::Map, &job.path, error.to_string());
+                log::error(format!("Can't load {error}"));
+                self.failed_assets.push(error);
+                return;
+            }
+        };
 
-        // Using map file path as scene name
-        let scene_id = self.register_scene(path);
+        self.scene_grid[job.scene_id] = Some(MapGrid::from_tiled_map(&map));
+        self.scene_properties[job.scene_id] = Some(map.properties.clone());
+
+        job.progress.total_files += map.tilesets().len();
+        job.progress.loaded_files = job.progress.total_files;
+
+        // Tilesets are shared between scenes, so only the ones not loaded yet are queued
+        for tileset in map.tilesets() {
+            if tile_mgr.tileset_map.contains_key(&tileset.name) {
+                continue;
+            }
+
+            let tileset_id = match tile_mgr.register_tiled_tileset(tileset) {
+                Ok(id) => id,
+                Err(error) => {
+                    self.failed_assets.push(error);
+                    continue;
+                }
+            };
+
+            for (tile_id, _) in tileset.tiles() {
+                job.steps.push_back(SceneLoadStep::Texture {
+                    tileset: tileset.clone(),
+                    tileset_id,
+                    tile_id,
+                });
+                job.progress.total_textures += 1;
+            }
+        }
 
         // Layers are loaded sequentially, so rendering is a matter of rendering tiles in the order they
         // had been loaded.
-        for layer in map.layers() {
+        for (layer_index, layer) in map.layers().enumerate() {
             match layer.layer_type() {
-                tiled::LayerType::Tiles(_) => {
-                    log::debug(format!("Loading tile layer \"{}\"", layer.name));
-                    let tile_layer = layer.as_tile_layer().unwrap();
-                    self.load_map_tile_layer(
-                        &tile_layer,
-                        scene_id,
-                        layer.id(),
-                        tile_mgr,
-                        texture_mgr,
-                    )
-                    .await;
+                tiled::LayerType::Tiles(tile_layer) => {
+                    let width = tile_layer.width().unwrap_or_default() as usize;
+                    let height = tile_layer.height().unwrap_or_default() as usize;
+                    job.progress.total_tiles += width * height;
+
+                    self.scene_tile_layer[job.scene_id].push((layer.id(), layer.name.clone()));
+
+                    job.steps.push_back(SceneLoadStep::TileLayer {
+                        layer_index,
+                        start: 0,
+                    });
                 }
                 tiled::LayerType::Objects(_) => {
-                    log::debug(format!("Loading object layer \"{}\"", layer.name));
-                    let object_layer = layer.as_object_layer().unwrap();
-                    self.load_map_object_layer(&object_layer, scene_id, layer.id());
+                    job.steps
+                        .push_back(SceneLoadStep::ObjectLayer { layer_index });
                 }
                 tiled::LayerType::Image(_) | tiled::LayerType::Group(_) => log::warning(format!(
                     "Skipping layer \"{}\". Layer type not supported",
@@ -239,23 +450,42 @@ impl SceneMgr {
             }
         }
 
-        scene_id
+        job.map = Some(map);
     }
 
-    fn load_map_object_layer<'a>(
-        &mut self,
-        layer: &tiled::ObjectLayer<'a>,
-        scene_id: usize,
-        layer_id: u32,
-    ) {
+    fn load_map_object_layer(&mut self, job: &mut SceneLoadJob, layer_index: usize) {
+        let map = job.map.as_ref().unwrap();
+        let layer = map.get_layer(layer_index).unwrap();
+        log::debug(format!("Loading object layer \"{}\"", layer.name));
+        job.progress.current_asset = layer.name.clone();
+
+        let layer_id = layer.id();
+        let layer = layer.as_object_layer().unwrap();
+        let grid = self.scene_grid[job.scene_id].unwrap();
+
         for object in layer.objects() {
+            let object_id = object.id();
             let object_name = &object.name;
             let object_class = &object.user_type;
-            let object_position = f32::Vec2::new(object.x, object.y);
+            let mut object_position = grid.object_to_world(f32::Vec2::new(object.x, object.y));
 
             // For sizes, only rectangle object shapes are supported
             let object_size = match &object.shape {
-                tiled::ObjectShape::Rect { width, height } => Some(f32::Vec2::new(*width, *height)),
+                tiled::ObjectShape::Rect { width, height } => {
+                    // Rectangles are projected in isometric maps. Keeping their bounding box.
+                    let corners = [
+                        f32::Vec2::new(object.x, object.y),
+                        f32::Vec2::new(object.x + width, object.y),
+                        f32::Vec2::new(object.x, object.y + height),
+                        f32::Vec2::new(object.x + width, object.y + height),
+                    ]
+                    .map(|corner| grid.object_to_world(corner));
+                    let min = corners.iter().fold(corners[0], |acc, c| acc.min(*c));
+                    let max = corners.iter().fold(corners[0], |acc, c| acc.max(*c));
+
+                    object_position = min;
+                    Some(max - min)
+                }
                 tiled::ObjectShape::Point(_, _) => None,
                 _ => {
                     log::error(format!(
@@ -268,57 +498,91 @@ impl SceneMgr {
 
             let object_properties = object.properties.clone();
 
-            self.add_object(
-                scene_id,
-                layer_id,
-                object_name.to_string(),
-                object_class.to_string(),
-                object_position,
-                object_size,
-                object_properties,
-            );
+            let object = SceneObject {
+                id: object_id,
+                name: object_name.to_string(),
+                class: object_class.to_string(),
+                position: object_position,
+                size: object_size,
+                properties: object_properties,
+            };
+            self.add_object(job.scene_id, layer_id, object);
         }
     }
 
-    async fn load_map_tile_layer<'a>(
+    /// Loads a batch of tiles from a tile layer, starting at the `start` cell (in row-major order).
+    /// Queues the next batch if the layer has cells left.
+    fn load_map_tile_layer(
         &mut self,
-        layer: &tiled::TileLayer<'a>,
-        scene_id: usize,
-        layer_id: u32,
-        tile_mgr: &mut TileMgr,
-        texture_mgr: &mut Texture2dMgr,
+        job: &mut SceneLoadJob,
+        layer_index: usize,
+        start: usize,
+        tile_mgr: &TileMgr,
     ) {
-        for j in 0..layer.height().unwrap_or_default() {
-            for i in 0..layer.width().unwrap_or_default() {
-                match layer.get_tile(i as i32, j as i32) {
-                    Some(tile) => {
-                        let tileset = tile.get_tileset();
-
-                        let tileset_id = match tile_mgr.tileset_map.get(&tileset.name) {
-                            Some(id) => *id,
-                            None => {
-                                // Load tileset and return the new id
-                                tile_mgr.load_tileset(&tileset, texture_mgr).await.unwrap()
-                            }
-                        };
-
-                        let tile_id = tile.id();
-                        let tile_position = IVec2::new(i as i32, j as i32);
-                        let tile_size =
-                            f32::Vec2::new(tileset.tile_width as f32, tileset.tile_height as f32);
-
-                        self.add_tile(
-                            scene_id,
-                            layer_id,
-                            tile_position,
-                            tile_size,
-                            tile_id,
-                            tileset_id,
-                        );
-                    }
-                    None => {}
+        let map = job.map.as_ref().unwrap();
+        let layer = map.get_layer(layer_index).unwrap();
+        if start == 0 {
+            log::debug(format!("Loading tile layer \"{}\"", layer.name));
+        }
+        job.progress.current_asset = layer.name.clone();
+
+        let layer_id = layer.id();
+        let layer = layer.as_tile_layer().unwrap();
+
+        let width = layer.width().unwrap_or_default() as usize;
+        let height = layer.height().unwrap_or_default() as usize;
+        let end = usize::min(start + TILE_LOAD_BATCH_SIZE, width * height);
+
+        for cell in start..end {
+            let i = (cell % width) as i32;
+            let j = (cell / width) as i32;
+
+            let tile = match layer.get_tile(i, j) {
+                Some(tile) => tile,
+                None => continue,
+            };
+
+            let tileset = tile.get_tileset();
+
+            let tileset_id = match tile_mgr.tileset_map.get(&tileset.name) {
+                Some(id) => *id,
+                None => {
+                    log::error(format!(
+                        "Skipping tile at ({i}, {j}). Tileset \"{}\" is not loaded",
+                        tileset.name
+                    ));
+                    continue;
                 }
-            }
+            };
+
+            let tile_id = tile.id();
+            let tile_position = IVec2::new(i, j);
+            let tile_size = f32::Vec2::new(tileset.tile_width as f32, tileset.tile_height as f32);
+            let tile_is_solid = tile.get_tile().is_some_and(|tile_data| {
+                matches!(
+                    tile_data.properties.get(SOLID_TILE_PROPERTY),
+                    Some(tiled::PropertyValue::BoolValue(true))
+                )
+            });
+
+            self.add_tile(
+                job.scene_id,
+                layer_id,
+                tile_position,
+                tile_size,
+                tile_id,
+                tileset_id,
+                tile_is_solid,
+            );
+        }
+
+        job.progress.loaded_tiles += end - start;
+
+        if end < width * height {
+            job.steps.push_front(SceneLoadStep::TileLayer {
+                layer_index,
+                start: end,
+            });
         }
     }
 
@@ -327,8 +591,10 @@ impl SceneMgr {
     /// The active scene will automatically be rendered by the `SceneManager`.
     ///
     /// - Loads the `active_objects` array with the object indices from the selected scene.
+    /// - Indexes the active objects by class and name for the `active_objects_*` queries. The
+    ///   class index of the previous scene is kept for `objects_to_despawn_of_class`.
     /// - Loads the `tile_renderer_cache` with the tiles from selected scene in the order they
-    ///   should be rendered at.
+    ///   should be rendered at, grouped by tile layer.
     pub fn set_active_scene(&mut self, scene_id: Option<usize>, tile_mgr: &TileMgr) {
         log::debug(format!(
             "Activating scene with id `{:?}`",
@@ -342,10 +608,17 @@ impl SceneMgr {
         };
 
         self.objects_to_despawn = self.active_objects.to_vec();
+        std::mem::swap(&mut self.despawn_class_map, &mut self.active_class_map);
         self.has_pending_despawn = true;
 
         self.tile_renderer_cache.clear();
+        self.active_solid_tiles.clear();
         self.active_objects.clear();
+        self.active_class_map.clear();
+        self.active_name_map.clear();
+
+        let grid = self.scene_grid[scene_id].unwrap();
+        let tile_layers = &self.scene_tile_layer[scene_id];
 
         for i in 0..self.len() {
             if self.scene_id[i] != scene_id {
@@ -361,28 +634,68 @@ impl SceneMgr {
                     let tile_i = tile_mgr.get_tile_index(tilemap_id, tile_id);
                     let texture_i = tile_mgr.texture_i[tile_i];
 
+                    let layer_order = tile_layers
+                        .iter()
+                        .position(|(layer_id, _)| *layer_id == self.layer_id[i])
+                        .unwrap();
+
+                    // Tile images are aligned to the bottom left corner of the grid cell, as in Tiled
                     let tile_position = self.tile_position[i].unwrap();
                     let tile_size = self.tile_size[i].unwrap();
-                    let render_position = f32::Vec2::new(
-                        tile_position.x as f32 * tile_size.x,
-                        tile_position.y as f32 * tile_size.y,
-                    );
+                    let render_position = grid.tile_to_world(tile_position)
+                        + f32::Vec2::new(0.0, grid.tile_size.y - tile_size.y);
 
                     self.tile_renderer_cache.push(CachedTile {
                         texture_i,
                         position: render_position,
+                        render_order: (layer_order, grid.render_order(tile_position)),
                     });
+
+                    if self.tile_is_solid[i].unwrap() {
+                        self.active_solid_tiles.push(tile_position);
+                    }
                 }
 
                 LayerTag::Objects => {
                     // Load objects into cache `active_objects`
                     self.active_objects.push(i);
+
+                    if let Some(object_class) = self.object_class[i].as_ref() {
+                        match self.active_class_map.get_mut(object_class) {
+                            Some(indices) => indices.push(i),
+                            None => {
+                                self.active_class_map.insert(object_class.clone(), vec![i]);
+                            }
+                        }
+                    }
+
+                    if let Some(object_name) = self.object_name[i].as_ref() {
+                        match self.active_name_map.get_mut(object_name) {
+                            Some(indices) => indices.push(i),
+                            None => {
+                                self.active_name_map.insert(object_name.clone(), vec![i]);
+                            }
+                        }
+                    }
                 }
 
                 _ => continue,
             }
         }
 
+        self.tile_renderer_cache
+            .sort_by_key(|cached_tile| cached_tile.render_order);
+
+        self.tile_layer_range.clear();
+        let mut start = 0;
+        for layer_order in 0..tile_layers.len() {
+            let end = start
+                + self.tile_renderer_cache[start..]
+                    .partition_point(|cached_tile| cached_tile.render_order.0 == layer_order);
+            self.tile_layer_range.push(start..end);
+            start = end;
+        }
+
         self.has_pending_spawn = true;
     }
 
@@ -396,6 +709,7 @@ impl SceneMgr {
         }
 
         self.objects_to_despawn.clear();
+        self.despawn_class_map.clear();
         self.has_pending_despawn = false;
     }
 
@@ -407,12 +721,133 @@ impl SceneMgr {
         self.has_pending_despawn
     }
 
-    pub fn render(&self, texture_mgr: &Texture2dMgr) {
-        for cached_tile in self.tile_renderer_cache.iter() {
+    /// Renders a tile layer of the active scene. Layers are counted from the bottom one, see
+    /// `tile_layer_count`.
+    pub fn render_tile_layer(&self, layer_order: usize, texture_mgr: &Texture2dMgr) {
+        let range = match self.tile_layer_range.get(layer_order) {
+            Some(range) => range.clone(),
+            None => return,
+        };
+
+        for cached_tile in self.tile_renderer_cache[range].iter() {
             texture_mgr.render_texture_unscaled(cached_tile.texture_i, cached_tile.position);
         }
     }
 
+    /// Positions of the tiles of the active scene with the `solid` property.
+    pub fn active_solid_tiles(&self) -> &[IVec2] {
+        &self.active_solid_tiles
+    }
+
+    /// Amount of tile layers in the active scene.
+    pub fn tile_layer_count(&self) -> usize {
+        self.tile_layer_range.len()
+    }
+
+    /// Returns the render order of the active scene tile layer with the given name, which can be
+    /// used to place sprites above it with `RenderLayer::AboveTileLayer`.
+    pub fn tile_layer_index(&self, layer_name: &str) -> Option<usize> {
+        let scene_id = self.active_scene_id?;
+
+        self.scene_tile_layer[scene_id]
+            .iter()
+            .position(|(_, name)| name == layer_name)
+    }
+
+    /// Tile grid of the active scene.
+    pub fn active_grid(&self) -> Option<&MapGrid> {
+        self.scene_grid[self.active_scene_id?].as_ref()
+    }
+
+    /// Returns a string property of the active scene map. Map properties are optional, so a
+    /// missing one is not an error.
+    pub fn get_active_scene_property_string(&self, property_name: &str) -> Option<String> {
+        let properties = self.scene_properties[self.active_scene_id?].as_ref()?;
+
+        match properties.get(property_name)? {
+            tiled::PropertyValue::StringValue(value) => Some(value.clone()),
+            _ => {
+                log::error(format!(
+                    "Map property `{:?}` was requested as a string and has another type",
+                    &property_name
+                ));
+                None
+            }
+        }
+    }
+
+    /// Converts a world position to the tile of the active scene containing it.
+    /// Returns `None` if there's no active scene or the tile is outside the map.
+    pub fn world_to_tile(&self, position: f32::Vec2) -> Option<IVec2> {
+        let grid = self.active_grid()?;
+        let tile = grid.world_to_tile(position);
+
+        match grid.contains_tile(tile) {
+            true => Some(tile),
+            false => None,
+        }
+    }
+
+    /// Returns the indices of the active objects with the given class.
+    pub fn active_objects_of_class(&self, object_class: &str) -> &[usize] {
+        match self.active_class_map.get(object_class) {
+            Some(indices) => indices,
+            None => &[],
+        }
+    }
+
+    /// Returns the indices of the objects pending despawn with the given class.
+    pub fn objects_to_despawn_of_class(&self, object_class: &str) -> &[usize] {
+        match self.despawn_class_map.get(object_class) {
+            Some(indices) => indices,
+            None => &[],
+        }
+    }
+
+    /// Returns the indices of the active objects with the given name.
+    /// Object names are not required to be unique in a scene.
+    // Game systems don't look objects up by name or area yet, only the tests query them
+    #[cfg_attr(not(test), allow(dead_code))]
+    pub fn active_objects_with_name(&self, object_name: &str) -> &[usize] {
+        match self.active_name_map.get(object_name) {
+            Some(indices) => indices,
+            None => &[],
+        }
+    }
+
+    /// Returns the first active object with the given name, if any.
+    #[cfg_attr(not(test), allow(dead_code))]
+    pub fn find_active_object(&self, object_name: &str) -> Option<usize> {
+        self.active_objects_with_name(object_name).first().copied()
+    }
+
+    /// Returns the indices of the active objects inside `rect`.
+    ///
+    /// Objects with a size are included when their bounds overlap `rect`, point objects when their
+    /// position is contained in it.
+    #[cfg_attr(not(test), allow(dead_code))]
+    pub fn active_objects_in_rect<'a>(
+        &'a self,
+        rect: &'a Rect,
+    ) -> impl Iterator<Item = usize> + 'a {
+        self.active_objects.iter().copied().filter(move |i| {
+            let position = match self.object_position[*i] {
+                Some(position) => position,
+                None => return false,
+            };
+
+            match self.object_size[*i] {
+                Some(size) => rect.overlaps(&Rect::new(position.x, position.y, size.x, size.y)),
+                None => rect.contains(position),
+            }
+        })
+    }
+
+    /// Returns the scene manager index of the object with the Tiled `object_id` in the given scene.
+    pub fn get_object_index(&self, scene_id: usize, object_id: u32) -> Option<usize> {
+        self.object_id_map.get(&(scene_id, object_id)).copied()
+    }
+
     // TODO: generalize get_object_property methods
     pub fn get_object_property_string(&self, index: usize, property_name: &str) -> Option<String> {
         let value: Option<String> = match self.object_properties[index].as_ref() {
@@ -515,6 +950,66 @@ impl SceneMgr {
 
         value
     }
+
+    /// Resolves an object reference property to the scene manager index of the referenced object.
+    ///
+    /// Tiled stores object references as the referenced object id, which is only unique within
+    /// the scene, so the lookup is done in the scene of the object at `index`.
+    // No map links objects yet, only the tests resolve references until one does
+    #[cfg_attr(not(test), allow(dead_code))]
+    pub fn get_object_property_object(&self, index: usize, property_name: &str) -> Option<usize> {
+        let object_id: Option<u32> = match self.object_properties[index].as_ref() {
+            Some(property) => match property.get(property_name) {
+                Some(property_value) => match property_value {
+                    tiled::PropertyValue::ObjectValue(value) => Some(*value),
+                    _ => {
+                        log::error(format!(
+                            "Property `{:?}` of object `{:?}` was requested as an object and has another type",
+                            &property_name,
+                            &self.object_name[index]
+                        ));
+                        None
+                    }
+                },
+                None => {
+                    log::error(format!(
+                        "Property `{:?}` not found for object `{:?}`",
+                        &property_name, &self.object_name[index]
+                    ));
+                    None
+                }
+            },
+            None => {
+                log::error(format!(
+                    "Object `{:?}` has no properties",
+                    &self.object_name[index]
+                ));
+                None
+            }
+        };
+
+        let object_id = object_id?;
+
+        // Tiled uses 0 for object properties that don't point to any object
+        if object_id == 0 {
+            log::error(format!(
+                "Property `{:?}` of object `{:?}` doesn't reference any object",
+                &property_name, &self.object_name[index]
+            ));
+            return None;
+        }
+
+        let scene_id = self.scene_id[index];
+        let value = self.get_object_index(scene_id, object_id);
+        if value.is_none() {
+            log::error(format!(
+                "Property `{:?}` of object `{:?}` references object with id `{}`, which doesn't exist in scene `{}`",
+                &property_name, &self.object_name[index], object_id, scene_id
+            ));
+        }
+
+        value
+    }
 }
 
 fn create_tiled_cursor_loader(
@@ -528,11 +1023,107 @@ fn create_tiled_cursor_loader(
     loader
 }
 
+/// Loading progress of a single scene, counted as files, tile layer cells and tile textures.
+///
+/// Totals grow once the map file is parsed, since tilesets and layers are only known after that.
+#[derive(Clone, Default)]
+pub struct SceneLoadProgress {
+    pub loaded_files: usize,
+    pub total_files: usize,
+    pub loaded_tiles: usize,
+    pub total_tiles: usize,
+    pub loaded_textures: usize,
+    pub total_textures: usize,
+    /// Name of the asset loaded in the last step.
+    pub current_asset: String,
+}
+
+impl SceneLoadProgress {
+    pub fn loaded(&self) -> usize {
+        self.loaded_files + self.loaded_tiles + self.loaded_textures
+    }
+
+    pub fn total(&self) -> usize {
+        self.total_files + self.total_tiles + self.total_textures
+    }
+
+    /// Loaded fraction, from 0.0 to 1.0.
+    pub fn fraction(&self) -> f32 {
+        let total = self.total();
+        if total == 0 {
+            return 0.0;
+        }
+
+        self.loaded() as f32 / total as f32
+    }
+}
+
+struct SceneLoadJob {
+    scene_id: usize,
+    path: String,
+    /// Parsed map. Available after the `SceneLoadStep::Map` step.
+    map: Option<tiled::Map>,
+    steps: VecDeque<SceneLoadStep>,
+    progress: SceneLoadProgress,
+}
+
+impl SceneLoadJob {
+    fn new(scene_id: usize, path: String) -> Self {
+        let mut steps = VecDeque::new();
+        steps.push_back(SceneLoadStep::Map);
+
+        let progress = SceneLoadProgress {
+            total_files: 1,
+            current_asset: path.clone(),
+            ..Default::default()
+        };
+
+        Self {
+            scene_id,
+            path,
+            map: None,
+            steps,
+            progress,
+        }
+    }
+}
+
+enum SceneLoadStep {
+    Map,
+    Texture {
+        tileset: Arc<tiled::Tileset>,
+        tileset_id: usize,
+        tile_id: u32,
+    },
+    TileLayer {
+        layer_index: usize,
+        /// First cell to load, in row-major order.
+        start: usize,
+    },
+    ObjectLayer {
+        layer_index: usize,
+    },
+}
+
 struct CachedTile {
     /// Texture index in `Texture2dMgr`
     texture_i: usize,
     /// Render position
     position: f32::Vec2,
+    /// Tile layer order and position in the layer render order.
+    render_order: (usize, (i32, bool, i32)),
+}
+
+/// Object of an object layer, as added to the `SceneMgr`.
+struct SceneObject {
+    id: u32,
+    name: String,
+    class: String,
+    /// World position of the bounding box top left corner.
+    position: f32::Vec2,
+    /// Bounding box size. `None` for points and unsupported shapes.
+    size: Option<f32::Vec2>,
+    properties: tiled::Properties,
 }
 
 /// General reader for tiled maps and tilesets. Can be used in WebGL.
@@ -571,3 +1162,160 @@ pub(crate) enum LayerTag {
     Image,
     Group,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::grid::MapOrientation;
+
+    const OBJECT_LAYER_ID: u32 = 2;
+
+    /// Two scenes with objects sharing Tiled ids, the first one active.
+    struct TestScenes {
+        scene_mgr: SceneMgr,
+        scene_ids: [usize; 2],
+    }
+
+    impl TestScenes {
+        fn new() -> Self {
+            let mut scene_mgr = SceneMgr::new();
+            let scene_ids = [
+                scene_mgr.queue_scene("maps/test01.tmx"),
+                scene_mgr.queue_scene("maps/test02.tmx"),
+            ];
+            for scene_id in scene_ids {
+                scene_mgr.scene_grid[scene_id] = Some(MapGrid {
+                    orientation: MapOrientation::Orthogonal,
+                    size: IVec2::new(16, 16),
+                    tile_size: f32::Vec2::splat(16.0),
+                });
+            }
+
+            Self {
+                scene_mgr,
+                scene_ids,
+            }
+        }
+
+        fn add(
+            &mut self,
+            scene: usize,
+            object_id: u32,
+            name: &str,
+            position: (f32, f32),
+            size: Option<(f32, f32)>,
+            properties: tiled::Properties,
+        ) -> usize {
+            let object = SceneObject {
+                id: object_id,
+                name: String::from(name),
+                class: String::from("Test"),
+                position: f32::Vec2::new(position.0, position.1),
+                size: size.map(|(w, h)| f32::Vec2::new(w, h)),
+                properties,
+            };
+            self.scene_mgr
+                .add_object(self.scene_ids[scene], OBJECT_LAYER_ID, object)
+        }
+
+        fn activate(&mut self, scene: usize) {
+            self.scene_mgr
+                .set_active_scene(Some(self.scene_ids[scene]), &TileMgr::new());
+        }
+    }
+
+    #[test]
+    fn active_objects_with_name_only_finds_active_scene() {
+        let mut scenes = TestScenes::new();
+        let door_a = scenes.add(0, 1, "Door", (0.0, 0.0), None, HashMap::new());
+        let door_b = scenes.add(0, 2, "Door", (32.0, 0.0), None, HashMap::new());
+        scenes.add(0, 3, "Exit", (64.0, 0.0), None, HashMap::new());
+        let other_door = scenes.add(1, 1, "Door", (0.0, 0.0), None, HashMap::new());
+        scenes.activate(0);
+
+        let scene_mgr = &scenes.scene_mgr;
+        assert_eq!(scene_mgr.active_objects_with_name("Door"), [door_a, door_b]);
+        assert_eq!(scene_mgr.find_active_object("Door"), Some(door_a));
+        assert!(scene_mgr.active_objects_with_name("Missing").is_empty());
+        assert_eq!(scene_mgr.find_active_object("Missing"), None);
+
+        scenes.activate(1);
+        assert_eq!(
+            scenes.scene_mgr.find_active_object("Door"),
+            Some(other_door)
+        );
+        assert_eq!(scenes.scene_mgr.find_active_object("Exit"), None);
+    }
+
+    #[test]
+    fn active_objects_in_rect_tests_bounds_and_points() {
+        let mut scenes = TestScenes::new();
+        let point_inside = scenes.add(0, 1, "Point", (10.0, 10.0), None, HashMap::new());
+        scenes.add(0, 2, "Point", (40.0, 10.0), None, HashMap::new());
+        let area_overlapping = scenes.add(
+            0,
+            3,
+            "Area",
+            (28.0, 28.0),
+            Some((16.0, 16.0)),
+            HashMap::new(),
+        );
+        scenes.add(0, 4, "Area", (40.0, 40.0), Some((8.0, 8.0)), HashMap::new());
+        scenes.add(1, 5, "Point", (12.0, 12.0), None, HashMap::new());
+        scenes.activate(0);
+
+        let rect = Rect::new(0.0, 0.0, 32.0, 32.0);
+        let found: Vec<usize> = scenes.scene_mgr.active_objects_in_rect(&rect).collect();
+        assert_eq!(found, [point_inside, area_overlapping]);
+    }
+
+    #[test]
+    fn get_object_index_is_per_scene() {
+        let mut scenes = TestScenes::new();
+        let first = scenes.add(0, 7, "Circle", (0.0, 0.0), None, HashMap::new());
+        let second = scenes.add(1, 7, "Circle", (0.0, 0.0), None, HashMap::new());
+
+        let scene_mgr = &scenes.scene_mgr;
+        assert_eq!(
+            scene_mgr.get_object_index(scenes.scene_ids[0], 7),
+            Some(first)
+        );
+        assert_eq!(
+            scene_mgr.get_object_index(scenes.scene_ids[1], 7),
+            Some(second)
+        );
+        assert_eq!(scene_mgr.get_object_index(scenes.scene_ids[0], 8), None);
+    }
+
+    #[test]
+    fn get_object_property_object_resolves_references_in_same_scene() {
+        let mut scenes = TestScenes::new();
+        let door = scenes.add(0, 1, "Door", (0.0, 0.0), None, HashMap::new());
+        scenes.add(1, 1, "Door", (0.0, 0.0), None, HashMap::new());
+
+        let properties = HashMap::from([
+            (String::from("door"), tiled::PropertyValue::ObjectValue(1)),
+            (String::from("exit"), tiled::PropertyValue::ObjectValue(9)),
+            (String::from("empty"), tiled::PropertyValue::ObjectValue(0)),
+            (
+                String::from("name"),
+                tiled::PropertyValue::StringValue(String::from("door")),
+            ),
+        ]);
+        let circle = scenes.add(0, 2, "Circle", (0.0, 0.0), None, properties);
+
+        let scene_mgr = &scenes.scene_mgr;
+        assert_eq!(
+            scene_mgr.get_object_property_object(circle, "door"),
+            Some(door)
+        );
+        // Missing objects, empty references, other types and missing properties
+        assert_eq!(scene_mgr.get_object_property_object(circle, "exit"), None);
+        assert_eq!(scene_mgr.get_object_property_object(circle, "empty"), None);
+        assert_eq!(scene_mgr.get_object_property_object(circle, "name"), None);
+        assert_eq!(
+            scene_mgr.get_object_property_object(circle, "missing"),
+            None
+        );
+    }
+}
@@ -1,47 +1,143 @@
+use std::collections::HashMap;
+
 use macroquad::{
-    color,
-    math::f32,
-    texture::{draw_texture_ex, load_texture, DrawTextureParams, Texture2D},
+    color::{self, Color},
+    math::{f32, Rect},
+    texture::{draw_texture_ex, load_image, DrawTextureParams, Image, Texture2D},
 };
 
-use super::logging::log;
+use super::{
+    animation::{AnimationClip, AnimationEvent},
+    asset::{self, AssetError, AssetKind},
+    atlas::AtlasPacker,
+    file,
+    handle::{insert_at, Handle, HandleAllocator},
+    logging::log,
+};
 
 const MAX_SPRITE_COUNT: usize = 1024;
+const MAX_CLIP_COUNT: usize = 256;
 const MAX_TEXTURE_COUNT: usize = 256;
+const ATLAS_PAGE_SIZE: u32 = 2048;
+const ATLAS_PADDING: u32 = 1;
+/// Cache key of the generated texture that replaces missing ones.
+const MISSING_TEXTURE_PATH: &str = "<missing texture>";
 
 /// Holds references to sprite instances in the game.
+///
+/// Sprites can play sprite-sheet animation clips registered with `add_clip`. A playing clip sets
+/// the `source` rectangle of the sprite's texture that is rendered, and the sprite `size` to the
+/// size of the current frame.
+///
+/// The sprite `position` is the world position of its `pivot`, which also is the center of
+/// rotation. The pivot is relative to the sprite size, from (0, 0) at the top left corner to
+/// (1, 1) at the bottom right one. It's the top left corner by default.
+///
+/// Sprites are referenced with generational `SpriteHandle`s. Removed sprites free their slot for
+/// new ones.
+///
+/// Sprites are rendered by `RenderLayer`, and inside a layer by their `z` value (lower first).
+/// Y-sorted sprites with the same `z` are also sorted by their position `y`, so sprites lower on
+/// the screen are drawn in front. The render order is computed by `update_render_order`.
 pub struct SpriteMgr {
+    handles: HandleAllocator<SpriteMgr>,
+
     position: Vec<f32::Vec2>,
     size: Vec<f32::Vec2>,
     scale: Vec<f32::Vec2>,
+    /// Rectangle of the texture to render, the whole texture if `None`.
+    source: Vec<Option<Rect>>,
+    /// Rotation around the pivot, in radians.
+    rotation: Vec<f32>,
+    flip_x: Vec<bool>,
+    flip_y: Vec<bool>,
+    pivot: Vec<f32::Vec2>,
+    tint: Vec<Color>,
     is_active: Vec<bool>,
 
+    // Render order
+    layer: Vec<RenderLayer>,
+    z: Vec<f32>,
+    is_y_sorted: Vec<bool>,
+    /// Active sprite indices in the order they are rendered at.
+    render_order: Vec<usize>,
+
     // References
-    pub texture_i: Vec<usize>,
+    texture_i: Vec<usize>,
 
     scaled_size_cache: Vec<f32::Vec2>,
+
+    // Animation
+    clip: Vec<AnimationClip>,
+    /// Clip being played by each sprite.
+    clip_i: Vec<Option<usize>>,
+    frame_i: Vec<usize>,
+    /// Time elapsed in the current frame.
+    frame_time: Vec<f32>,
+    is_playing: Vec<bool>,
+    animation_events: Vec<AnimationEvent>,
 }
 
 impl SpriteMgr {
     pub fn new() -> Self {
+        let handles = HandleAllocator::with_capacity(MAX_SPRITE_COUNT);
+
         let position = Vec::with_capacity(MAX_SPRITE_COUNT);
         let size = Vec::with_capacity(MAX_SPRITE_COUNT);
         let scale = Vec::with_capacity(MAX_SPRITE_COUNT);
+        let source = Vec::with_capacity(MAX_SPRITE_COUNT);
+        let rotation = Vec::with_capacity(MAX_SPRITE_COUNT);
+        let flip_x = Vec::with_capacity(MAX_SPRITE_COUNT);
+        let flip_y = Vec::with_capacity(MAX_SPRITE_COUNT);
+        let pivot = Vec::with_capacity(MAX_SPRITE_COUNT);
+        let tint = Vec::with_capacity(MAX_SPRITE_COUNT);
         let is_active = Vec::with_capacity(MAX_SPRITE_COUNT);
 
+        let layer = Vec::with_capacity(MAX_SPRITE_COUNT);
+        let z = Vec::with_capacity(MAX_SPRITE_COUNT);
+        let is_y_sorted = Vec::with_capacity(MAX_SPRITE_COUNT);
+        let render_order = Vec::with_capacity(MAX_SPRITE_COUNT);
+
         let texture_i = Vec::with_capacity(MAX_SPRITE_COUNT);
 
         let scaled_size_cache = Vec::with_capacity(MAX_SPRITE_COUNT);
 
+        let clip = Vec::with_capacity(MAX_CLIP_COUNT);
+        let clip_i = Vec::with_capacity(MAX_SPRITE_COUNT);
+        let frame_i = Vec::with_capacity(MAX_SPRITE_COUNT);
+        let frame_time = Vec::with_capacity(MAX_SPRITE_COUNT);
+        let is_playing = Vec::with_capacity(MAX_SPRITE_COUNT);
+        let animation_events = Vec::with_capacity(MAX_SPRITE_COUNT);
+
         Self {
+            handles,
+
             position,
             size,
             scale,
+            source,
+            rotation,
+            flip_x,
+            flip_y,
+            pivot,
+            tint,
             is_active,
 
+            layer,
+            z,
+            is_y_sorted,
+            render_order,
+
             texture_i,
 
             scaled_size_cache,
+
+            clip,
+            clip_i,
+            frame_i,
+            frame_time,
+            is_playing,
+            animation_events,
         }
     }
 
@@ -52,16 +148,34 @@ impl SpriteMgr {
         position: f32::Vec2,
         size: f32::Vec2,
         scale: f32::Vec2,
-    ) -> usize {
-        self.position.push(position);
-        self.size.push(size);
-        self.scale.push(scale);
-        self.scaled_size_cache.push(size * scale);
-        self.is_active.push(true);
-
-        self.texture_i.push(texture_i);
-
-        self.len() - 1
+    ) -> SpriteHandle {
+        let handle = self.handles.allocate();
+        let i = handle.index();
+
+        insert_at(&mut self.position, i, position);
+        insert_at(&mut self.size, i, size);
+        insert_at(&mut self.scale, i, scale);
+        insert_at(&mut self.scaled_size_cache, i, size * scale);
+        insert_at(&mut self.source, i, None);
+        insert_at(&mut self.rotation, i, 0.0);
+        insert_at(&mut self.flip_x, i, false);
+        insert_at(&mut self.flip_y, i, false);
+        insert_at(&mut self.pivot, i, f32::Vec2::ZERO);
+        insert_at(&mut self.tint, i, color::WHITE);
+        insert_at(&mut self.is_active, i, true);
+
+        insert_at(&mut self.layer, i, RenderLayer::Foreground);
+        insert_at(&mut self.z, i, 0.0);
+        insert_at(&mut self.is_y_sorted, i, false);
+
+        insert_at(&mut self.texture_i, i, texture_i);
+
+        insert_at(&mut self.clip_i, i, None);
+        insert_at(&mut self.frame_i, i, 0);
+        insert_at(&mut self.frame_time, i, 0.0);
+        insert_at(&mut self.is_playing, i, false);
+
+        handle
     }
 
     /// Adds a sprite instance and adds a new texture from the provided file path.
@@ -72,113 +186,556 @@ impl SpriteMgr {
         scale: f32::Vec2,
 
         texture_mgr: &mut Texture2dMgr,
-    ) -> usize {
+    ) -> SpriteHandle {
         let texture_i = texture_mgr.add_from_file(file_path).await;
         let size = texture_mgr.texture[texture_i].size();
 
         self.add(texture_i, position, size, scale)
     }
 
+    /// Removes a sprite, releasing its texture. Its slot will be reused by new sprites.
+    pub fn remove(&mut self, sprite: SpriteHandle, texture_mgr: &mut Texture2dMgr) {
+        let index = self.handles.index(sprite);
+
+        self.is_active[index] = false;
+        self.is_playing[index] = false;
+        texture_mgr.release(self.texture_i[index]);
+
+        self.handles.free(sprite);
+    }
+
+    pub fn is_valid(&self, sprite: SpriteHandle) -> bool {
+        self.handles.is_valid(sprite)
+    }
+
+    /// Amount of sprite slots, including removed ones.
     pub fn len(&self) -> usize {
         self.position.len()
     }
 
-    pub fn size(&self, index: usize) -> &f32::Vec2 {
-        &self.size[index]
+    pub fn size(&self, sprite: SpriteHandle) -> &f32::Vec2 {
+        &self.size[self.handles.index(sprite)]
     }
 
-    pub fn set_scale(&mut self, index: usize, scale: f32::Vec2) {
+    pub fn set_scale(&mut self, sprite: SpriteHandle, scale: f32::Vec2) {
+        let index = self.handles.index(sprite);
         self.scale[index].x = scale.x;
         self.scale[index].y = scale.y;
 
         self.scaled_size_cache[index] = self.size[index] * scale;
     }
 
-    pub fn scale(&self, index: usize) -> &f32::Vec2 {
-        &self.scale[index]
+    pub fn scale(&self, sprite: SpriteHandle) -> &f32::Vec2 {
+        &self.scale[self.handles.index(sprite)]
     }
 
-    pub fn scaled_size(&self, index: usize) -> &f32::Vec2 {
-        &self.scaled_size_cache[index]
+    pub fn scaled_size(&self, sprite: SpriteHandle) -> &f32::Vec2 {
+        &self.scaled_size_cache[self.handles.index(sprite)]
     }
 
-    /// Render every active sprite
-    pub fn render(&self, texture_mgr: &Texture2dMgr) {
-        for i in 0..self.len() {
+    /// Sorts the active sprites in the order they should be rendered at. Should run after every
+    /// system has updated the sprite positions for the frame.
+    pub fn update_render_order(&mut self) {
+        self.render_order.clear();
+        self.render_order
+            .extend((0..self.len()).filter(|i| self.is_active[*i]));
+
+        // Stable sort, so sprites with the same key keep their insertion order
+        self.render_order.sort_by(|a, b| {
+            let a_y = match self.is_y_sorted[*a] {
+                true => self.position[*a].y,
+                false => f32::NEG_INFINITY,
+            };
+            let b_y = match self.is_y_sorted[*b] {
+                true => self.position[*b].y,
+                false => f32::NEG_INFINITY,
+            };
+
+            self.layer[*a]
+                .cmp(&self.layer[*b])
+                .then(self.z[*a].total_cmp(&self.z[*b]))
+                .then(a_y.total_cmp(&b_y))
+        });
+    }
+
+    /// Renders the active sprites of a layer, using the order from the last
+    /// `update_render_order`.
+    pub fn render_layer(&self, layer: RenderLayer, texture_mgr: &Texture2dMgr) {
+        let start = self
+            .render_order
+            .partition_point(|i| self.layer[*i] < layer);
+
+        for i in self.render_order[start..].iter().copied() {
+            if self.layer[i] != layer {
+                break;
+            }
+            // Sprites removed or deactivated since the last sort
             if !self.is_active[i] {
                 continue;
             }
 
-            texture_mgr.render_texture(self.texture_i[i], self.position[i], self.scale[i]);
+            let params = DrawTextureParams {
+                dest_size: Some(self.scaled_size_cache[i]),
+                source: self.source[i],
+                rotation: self.rotation[i],
+                flip_x: self.flip_x[i],
+                flip_y: self.flip_y[i],
+                pivot: Some(self.position[i]),
+            };
+
+            texture_mgr.render_texture_ex(
+                self.texture_i[i],
+                self.top_left_at(i),
+                self.tint[i],
+                params,
+            );
+        }
+    }
+
+    /// Registers an animation clip that can be played by any sprite. Returns the clip index.
+    pub fn add_clip(&mut self, clip: AnimationClip) -> usize {
+        self.clip.push(clip);
+
+        self.clip.len() - 1
+    }
+
+    /// Plays a clip from its first frame. Does nothing if the sprite is already playing it.
+    pub fn play(&mut self, sprite: SpriteHandle, clip_i: usize) {
+        let index = self.handles.index(sprite);
+        if self.clip_i[index] == Some(clip_i) && self.is_playing[index] {
+            return;
+        }
+
+        self.clip_i[index] = Some(clip_i);
+        self.is_playing[index] = true;
+        self.set_frame(index, 0);
+    }
+
+    /// Stops the current clip, keeping its current frame.
+    pub fn stop(&mut self, sprite: SpriteHandle) {
+        let index = self.handles.index(sprite);
+        self.is_playing[index] = false;
+    }
+
+    fn set_frame(&mut self, index: usize, frame_i: usize) {
+        let clip_i = self.clip_i[index].unwrap();
+        let frame = self.clip[clip_i].frame[frame_i];
+
+        self.frame_i[index] = frame_i;
+        self.frame_time[index] = 0.0;
+        self.source[index] = Some(frame);
+
+        self.size[index] = f32::Vec2::new(frame.w, frame.h);
+        self.scaled_size_cache[index] = self.size[index] * self.scale[index];
+    }
+
+    /// Advances the animation of every active sprite playing a clip.
+    ///
+    /// Clears the events from the last update, so events must be read before the next one.
+    pub fn update(&mut self, dt: f32) {
+        self.animation_events.clear();
+
+        for i in 0..self.len() {
+            if !self.is_active[i] || !self.is_playing[i] {
+                continue;
+            }
+
+            let clip_i = self.clip_i[i].unwrap();
+            self.frame_time[i] += dt;
+
+            // Long frame times can skip several frames
+            loop {
+                let clip = &self.clip[clip_i];
+                let frame_i = self.frame_i[i];
+                let frame_duration = clip.frame_duration[frame_i];
+                if self.frame_time[i] < frame_duration {
+                    break;
+                }
+
+                let remaining_time = self.frame_time[i] - frame_duration;
+
+                if frame_i + 1 < clip.len() {
+                    self.set_frame(i, frame_i + 1);
+                } else if clip.is_looping {
+                    self.set_frame(i, 0);
+                } else {
+                    self.is_playing[i] = false;
+                    self.animation_events.push(AnimationEvent::Finished {
+                        sprite: self.handles.handle(i),
+                        clip_i,
+                    });
+                    break;
+                }
+
+                self.frame_time[i] = remaining_time;
+            }
         }
     }
 
-    pub fn position(&self, index: usize) -> f32::Vec2 {
-        self.position[index]
+    /// Events emitted by the last `update`.
+    pub fn animation_events(&self) -> &[AnimationEvent] {
+        &self.animation_events
+    }
+
+    pub fn position(&self, sprite: SpriteHandle) -> f32::Vec2 {
+        self.position[self.handles.index(sprite)]
     }
 
-    pub fn set_position(&mut self, index: usize, position: f32::Vec2) {
+    pub fn set_position(&mut self, sprite: SpriteHandle, position: f32::Vec2) {
+        let index = self.handles.index(sprite);
         self.position[index] = position;
     }
 
-    pub fn translate(&mut self, index: usize, delta: f32::Vec2) {
-        self.set_position(index, self.position[index] + delta);
+    /// World position of the top left corner of the (unrotated) sprite.
+    pub fn top_left(&self, sprite: SpriteHandle) -> f32::Vec2 {
+        self.top_left_at(self.handles.index(sprite))
     }
 
-    pub fn set_active(&mut self, index: usize, is_active: bool) {
+    fn top_left_at(&self, index: usize) -> f32::Vec2 {
+        self.position[index] - self.pivot[index] * self.scaled_size_cache[index]
+    }
+
+    /// Axis aligned bounds of the (unrotated) sprite.
+    pub fn bounds(&self, sprite: SpriteHandle) -> Rect {
+        let index = self.handles.index(sprite);
+        let top_left = self.top_left_at(index);
+        let size = self.scaled_size_cache[index];
+
+        Rect::new(top_left.x, top_left.y, size.x, size.y)
+    }
+
+    pub fn set_rotation(&mut self, sprite: SpriteHandle, rotation: f32) {
+        let index = self.handles.index(sprite);
+        self.rotation[index] = rotation;
+    }
+
+    pub fn set_flip(&mut self, sprite: SpriteHandle, flip_x: bool, flip_y: bool) {
+        let index = self.handles.index(sprite);
+        self.flip_x[index] = flip_x;
+        self.flip_y[index] = flip_y;
+    }
+
+    /// Sets the pivot, relative to the sprite size. The sprite keeps its `position`, so it's
+    /// rendered around the new pivot.
+    pub fn set_pivot(&mut self, sprite: SpriteHandle, pivot: f32::Vec2) {
+        let index = self.handles.index(sprite);
+        self.pivot[index] = pivot;
+    }
+
+    pub fn set_tint(&mut self, sprite: SpriteHandle, tint: Color) {
+        let index = self.handles.index(sprite);
+        self.tint[index] = tint;
+    }
+
+    pub fn translate(&mut self, sprite: SpriteHandle, delta: f32::Vec2) {
+        self.set_position(sprite, self.position(sprite) + delta);
+    }
+
+    pub fn set_active(&mut self, sprite: SpriteHandle, is_active: bool) {
+        let index = self.handles.index(sprite);
         self.is_active[index] = is_active
     }
 
-    pub fn is_active(&self, index: usize) -> bool {
-        self.is_active[index]
+    pub fn is_active(&self, sprite: SpriteHandle) -> bool {
+        self.is_active[self.handles.index(sprite)]
     }
+
+    pub fn set_layer(&mut self, sprite: SpriteHandle, layer: RenderLayer) {
+        let index = self.handles.index(sprite);
+        self.layer[index] = layer;
+    }
+
+    /// Sets the render order of a sprite inside its layer. Lower values are rendered first.
+    pub fn set_z(&mut self, sprite: SpriteHandle, z: f32) {
+        let index = self.handles.index(sprite);
+        self.z[index] = z;
+    }
+
+    /// Y-sorted sprites are rendered by their position `y` after sorting by `z`. Non y-sorted
+    /// sprites are rendered before the y-sorted ones with the same `z`.
+    pub fn set_y_sorted(&mut self, sprite: SpriteHandle, is_y_sorted: bool) {
+        let index = self.handles.index(sprite);
+        self.is_y_sorted[index] = is_y_sorted;
+    }
+
+    /// Texture index of a sprite in `Texture2dMgr`.
+    pub fn texture_i(&self, sprite: SpriteHandle) -> usize {
+        self.texture_i[self.handles.index(sprite)]
+    }
+}
+
+pub type SpriteHandle = Handle<SpriteMgr>;
+
+/// Sprite render layers, in render order. Layers are interleaved with the tile layers of the active
+/// scene, which are counted from the bottom one in the Tiled map.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub enum RenderLayer {
+    /// Below every tile layer.
+    Background,
+    /// Above the tile layer with this index and below the next one.
+    AboveTileLayer(usize),
+    /// Above every tile layer. Default layer of new sprites.
+    Foreground,
+    /// Above everything else in the world, including debug shapes like collider boxes.
+    Overlay,
 }
 
 /// Holds reference to all textures used by the game.
+///
+/// Textures loaded from files are cached by their normalised path, so loading the same file again
+/// returns the existing texture. Every load counts as a user of the texture and should be paired
+/// with a `release` once the texture isn't needed anymore.
+///
+/// Loaded textures are packed into atlas pages, so that consecutive draws of different textures
+/// can share the same page texture and be batched. Textures that don't fit in a page are drawn
+/// on their own.
+///
+/// Textures that fail to load are replaced by a generated checkerboard texture, so the game keeps
+/// running. The failures are kept in `failed_assets` for the asset report.
 pub struct Texture2dMgr {
     pub texture: Vec<Texture2D>,
-
+    /// CPU side copy of each texture, used to build the atlas.
+    image: Vec<Image>,
+    /// Normalised file path of each texture, `None` for released textures.
+    path: Vec<Option<String>>,
+    /// Amount of users of each texture.
+    ref_count: Vec<usize>,
+
+    /// Maps normalised file paths to texture indices.
+    path_map: HashMap<String, usize>,
+    /// Released texture indices that can be reused.
+    free_i: Vec<usize>,
+
+    // Atlas
+    atlas_packer: AtlasPacker,
+    atlas_page: Vec<Texture2D>,
+    /// Atlas page index and source rectangle of each texture, if packed.
+    atlas_region: Vec<Option<(usize, Rect)>>,
     pub is_atlas_outdated: bool,
+
+    failed_assets: Vec<AssetError>,
 }
 
 impl Texture2dMgr {
     pub fn new() -> Self {
         let texture = Vec::with_capacity(MAX_TEXTURE_COUNT);
+        let image = Vec::with_capacity(MAX_TEXTURE_COUNT);
+        let path = Vec::with_capacity(MAX_TEXTURE_COUNT);
+        let ref_count = Vec::with_capacity(MAX_TEXTURE_COUNT);
+
+        let path_map = HashMap::with_capacity(MAX_TEXTURE_COUNT);
+        let free_i = Vec::with_capacity(MAX_TEXTURE_COUNT);
 
+        let atlas_packer = AtlasPacker::new(ATLAS_PAGE_SIZE, ATLAS_PADDING);
+        let atlas_page = Vec::new();
+        let atlas_region = Vec::with_capacity(MAX_TEXTURE_COUNT);
         let is_atlas_outdated = false;
 
+        let failed_assets = Vec::new();
+
         Self {
             texture,
+            image,
+            path,
+            ref_count,
+
+            path_map,
+            free_i,
 
+            atlas_packer,
+            atlas_page,
+            atlas_region,
             is_atlas_outdated,
+
+            failed_assets,
         }
     }
 
+    /// Returns the index of the texture loaded from `file_path`, loading it if it's not cached.
+    /// Increases the texture reference count.
+    ///
+    /// Returns the missing texture if the file can't be loaded, see `try_add_from_file`.
     pub async fn add_from_file(&mut self, file_path: &str) -> usize {
-        log::debug(format!("Loading texture: {file_path}"));
-        let texture = load_texture(file_path).await.unwrap();
+        match self.try_add_from_file(file_path).await {
+            Ok(index) => index,
+            Err(error) => self.add_missing(error),
+        }
+    }
+
+    /// Same as `add_from_file`, but returns an error if the file can't be loaded.
+    pub async fn try_add_from_file(&mut self, file_path: &str) -> Result<usize, AssetError> {
+        let normalized_path = file::normalize_path(file_path);
+
+        if let Some(index) = self.get_index(&normalized_path) {
+            self.ref_count[index] += 1;
+            return Ok(index);
+        }
+
+        log::debug(format!("Loading texture: {normalized_path}"));
+        let image = load_image(&normalized_path).await.map_err(|error| {
+            AssetError::new(AssetKind::Texture, &normalized_path, error.to_string())
+        })?;
+
+        Ok(self.add_image(normalized_path, image))
+    }
+
+    /// Records a failed asset and returns the missing texture in its place. Increases the
+    /// missing texture reference count.
+    pub fn add_missing(&mut self, error: AssetError) -> usize {
+        log::error(format!("Can't load {error}. Using missing texture"));
+        if !self
+            .failed_assets
+            .iter()
+            .any(|failed| failed.kind == error.kind && failed.path == error.path)
+        {
+            self.failed_assets.push(error);
+        }
+
+        if let Some(index) = self.path_map.get(MISSING_TEXTURE_PATH).copied() {
+            self.ref_count[index] += 1;
+            return index;
+        }
+
+        self.add_image(
+            String::from(MISSING_TEXTURE_PATH),
+            asset::missing_texture_image(),
+        )
+    }
+
+    /// Assets that failed to load since the game started.
+    pub fn failed_assets(&self) -> &[AssetError] {
+        &self.failed_assets
+    }
+
+    /// Uploads an image as a new texture cached by `normalized_path`, with one user.
+    fn add_image(&mut self, normalized_path: String, image: Image) -> usize {
+        let texture = Texture2D::from_image(&image);
         texture.set_filter(macroquad::texture::FilterMode::Nearest);
 
-        self.texture.push(texture);
+        let index = match self.free_i.pop() {
+            Some(index) => {
+                self.texture[index] = texture;
+                self.image[index] = image;
+                self.atlas_region[index] = None;
+                self.path[index] = Some(normalized_path.clone());
+                self.ref_count[index] = 1;
+                index
+            }
+            None => {
+                self.texture.push(texture);
+                self.image.push(image);
+                self.atlas_region.push(None);
+                self.path.push(Some(normalized_path.clone()));
+                self.ref_count.push(1);
+                self.len() - 1
+            }
+        };
+
+        self.path_map.insert(normalized_path, index);
 
         self.is_atlas_outdated = true;
 
-        self.len() - 1
+        index
+    }
+
+    /// Returns the index of the texture loaded from `file_path`, if it's loaded.
+    pub fn get_index(&self, file_path: &str) -> Option<usize> {
+        self.path_map.get(&file::normalize_path(file_path)).copied()
+    }
+
+    /// Decreases the reference count of a texture. Once it has no users, the texture is freed and
+    /// its index can be reused by a new texture.
+    pub fn release(&mut self, index: usize) {
+        if self.ref_count[index] == 0 {
+            log::error(format!("Texture #{index} released more times than loaded"));
+            return;
+        }
+
+        self.ref_count[index] -= 1;
+        if self.ref_count[index] > 0 {
+            return;
+        }
+
+        if let Some(path) = self.path[index].take() {
+            log::debug(format!("Releasing texture: {path}"));
+            self.path_map.remove(&path);
+        }
+
+        self.texture[index] = Texture2D::empty();
+        self.image[index] = Image::empty();
+        self.atlas_region[index] = None;
+        self.free_i.push(index);
+
+        self.is_atlas_outdated = true;
     }
 
     pub fn len(&self) -> usize {
         self.texture.len()
     }
 
-    pub fn update(&mut self) {
-        if self.is_atlas_outdated {
+    /// Rebuilds the atlas if textures were added or released, once `is_loading` is over. Loading
+    /// a scene adds textures over many frames, and each rebuild repacks and uploads every page.
+    /// Textures that aren't packed yet are drawn on their own meanwhile.
+    pub fn update(&mut self, is_loading: bool) {
+        if self.is_atlas_outdated && !is_loading {
             self.rebuild_texture_atlas();
         }
     }
 
-    pub fn rebuild_texture_atlas(&self) {
-        // TODO: update macroquad when the fix is released and uncomment the code below.
-        // build_textures_atlas();
+    /// Packs every loaded texture into atlas pages and uploads them.
+    pub fn rebuild_texture_atlas(&mut self) {
+        let texture_i: Vec<usize> = (0..self.len())
+            .filter(|i| self.path[*i].is_some())
+            .collect();
+        let images: Vec<&Image> = texture_i.iter().map(|i| &self.image[*i]).collect();
+
+        let (pages, placements) = self.atlas_packer.build_pages(&images);
+
+        self.atlas_page.clear();
+        for page in pages.iter() {
+            let page_texture = Texture2D::from_image(page);
+            page_texture.set_filter(macroquad::texture::FilterMode::Nearest);
+            self.atlas_page.push(page_texture);
+        }
+
+        for (index, placement) in texture_i.iter().zip(placements.iter()) {
+            self.atlas_region[*index] = placement.map(|placement| {
+                let rect = Rect::new(
+                    placement.x as f32,
+                    placement.y as f32,
+                    placement.width as f32,
+                    placement.height as f32,
+                );
+                (placement.page, rect)
+            });
+
+            if placement.is_none() {
+                log::warning(format!(
+                    "Texture {:?} doesn't fit in an atlas page",
+                    self.path[*index]
+                ));
+            }
+        }
+
+        log::debug(format!(
+            "Texture atlas rebuilt: {} textures in {} pages",
+            texture_i.len(),
+            self.atlas_page.len()
+        ));
+
+        self.is_atlas_outdated = false;
+    }
+
+    /// Renders a texture with the full set of draw parameters. `params.source` is relative to the
+    /// texture.
+    pub fn render_texture_ex(
+        &self,
+        index: usize,
+        position: f32::Vec2,
+        color: Color,
+        params: DrawTextureParams,
+    ) {
+        self.draw(index, position, color, params);
     }
 
     pub fn render_texture(&self, index: usize, position: f32::Vec2, scale: f32::Vec2) {
@@ -190,38 +747,35 @@ impl Texture2dMgr {
 
         let params = DrawTextureParams {
             dest_size: Some(scaled_size),
-            source: None,
-            rotation: 0.0,
-            flip_x: false,
-            flip_y: false,
-            pivot: None,
+            ..Default::default()
         };
 
-        draw_texture_ex(
-            &self.texture[index],
-            position.x,
-            position.y,
-            color::WHITE,
-            params,
-        );
+        self.draw(index, position, color::WHITE, params);
     }
 
     pub fn render_texture_unscaled(&self, index: usize, position: f32::Vec2) {
-        let params = DrawTextureParams {
-            dest_size: None,
-            source: None,
-            rotation: 0.0,
-            flip_x: false,
-            flip_y: false,
-            pivot: None,
+        self.draw(index, position, color::WHITE, DrawTextureParams::default());
+    }
+
+    /// Draws a texture from its atlas page if it's packed, or from the texture itself if not.
+    /// `params.source` is relative to the texture.
+    fn draw(&self, index: usize, position: f32::Vec2, color: Color, params: DrawTextureParams) {
+        let (texture, source) = match (self.atlas_region[index], params.source) {
+            (Some((page_i, region)), Some(source)) => (
+                &self.atlas_page[page_i],
+                Some(Rect::new(
+                    region.x + source.x,
+                    region.y + source.y,
+                    source.w,
+                    source.h,
+                )),
+            ),
+            (Some((page_i, region)), None) => (&self.atlas_page[page_i], Some(region)),
+            (None, source) => (&self.texture[index], source),
         };
 
-        draw_texture_ex(
-            &self.texture[index],
-            position.x,
-            position.y,
-            color::WHITE,
-            params,
-        );
+        let params = DrawTextureParams { source, ..params };
+
+        draw_texture_ex(texture, position.x, position.y, color, params);
     }
 }
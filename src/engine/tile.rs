@@ -1,6 +1,10 @@
 use std::collections::HashMap;
 
-use super::{logging::log, scene::TiledCursorReader, sprite::Texture2dMgr};
+use super::{
+    asset::{AssetError, AssetKind},
+    logging::log,
+    sprite::Texture2dMgr,
+};
 
 const MAX_TILE_COUNT: usize = 1024;
 const MAX_TILESET_COUNT: usize = 128;
@@ -70,54 +74,66 @@ impl TileMgr {
         *tile_i
     }
 
-    /// Loads every tile from a Tiled tileset. Also registers the tileset.
-    /// Returns the loaded tileset id.
-    pub async fn load_tileset(
+    /// Registers a Tiled tileset and returns the `tileset_id`. Its tiles still have to be loaded
+    /// with `load_tile`.
+    pub fn register_tiled_tileset(
         &mut self,
         tileset: &tiled::Tileset,
-        texture_mgr: &mut Texture2dMgr,
-    ) -> Result<usize, std::io::Error> {
+    ) -> Result<usize, AssetError> {
         log::debug(format!("Loading tileset: {}", &tileset.name));
 
-        let error_str = format!("Can't load tileset {}: it contains no tiles", tileset.name);
         if tileset.tilecount <= 0 {
-            log::error(&error_str);
-            return Err(std::io::Error::new(std::io::ErrorKind::Other, error_str));
+            let error = AssetError::new(AssetKind::Tileset, &tileset.name, "it contains no tiles");
+            log::error(format!("Can't load {error}"));
+            return Err(error);
         }
 
-        let tileset_id = self.register_tileset(&tileset.name);
-
-        for tile in tileset.tiles() {
-            let tile_id = tile.0;
-            let image_path = &tile
-                .1
-                .image
-                .as_ref()
-                .unwrap()
-                .source
-                .as_os_str()
-                .to_str()
-                .unwrap();
-
-            log::debug(format!("Loading tile #{tile_id} from {image_path:?}"));
-
-            let texture_i = texture_mgr.add_from_file(&image_path).await;
-
-            self.add(tile_id, tileset_id, texture_i);
-        }
+        Ok(self.register_tileset(&tileset.name))
+    }
 
-        Ok(tileset_id)
+    /// Returns the image path of a tile from a Tiled tileset.
+    ///
+    /// Only image collection tilesets, with an image for each tile, are supported.
+    pub fn tile_image_path(
+        &self,
+        tileset: &tiled::Tileset,
+        tile_id: u32,
+    ) -> Result<String, AssetError> {
+        let tile_name = format!("{}#{tile_id}", tileset.name);
+
+        let tile = tileset
+            .get_tile(tile_id)
+            .ok_or_else(|| AssetError::new(AssetKind::Tile, &tile_name, "tile not found"))?;
+        let image = tile
+            .image
+            .as_ref()
+            .ok_or_else(|| AssetError::new(AssetKind::Tile, &tile_name, "tile has no image"))?;
+        let image_path = image.source.as_os_str().to_str().ok_or_else(|| {
+            AssetError::new(AssetKind::Tile, &tile_name, "image path is not valid UTF-8")
+        })?;
+
+        Ok(String::from(image_path))
     }
 
-    /// Loads every tile from a file path. Also registers the tileset.
-    /// Returns the loaded tileset id.
-    pub async fn load_tileset_from_path(
+    /// Loads the texture of a single tile from a registered Tiled tileset and adds the tile.
+    /// Returns the tile index.
+    ///
+    /// Tiles whose image can't be loaded use the missing texture.
+    pub async fn load_tile(
         &mut self,
-        path: &str,
-        loader: &mut tiled::Loader<tiled::DefaultResourceCache, TiledCursorReader>,
+        tileset: &tiled::Tileset,
+        tileset_id: usize,
+        tile_id: u32,
         texture_mgr: &mut Texture2dMgr,
-    ) -> Result<usize, std::io::Error> {
-        let tileset = loader.load_tsx_tileset(path).unwrap();
-        self.load_tileset(&tileset, texture_mgr).await
+    ) -> usize {
+        let texture_i = match self.tile_image_path(tileset, tile_id) {
+            Ok(image_path) => {
+                log::debug(format!("Loading tile #{tile_id} from {image_path:?}"));
+                texture_mgr.add_from_file(&image_path).await
+            }
+            Err(error) => texture_mgr.add_missing(error),
+        };
+
+        self.add(tile_id, tileset_id, texture_i)
     }
 }
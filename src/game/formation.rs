@@ -0,0 +1,342 @@
+use macroquad::math::{f32, IVec2};
+
+use crate::engine::navigation::NavGrid;
+
+/// Expected maximum amount of units ordered together.
+const MAX_UNIT_COUNT: usize = 1024;
+/// Distance between neighbouring slots, in unit sizes.
+const SLOT_SPACING: f32 = 1.5;
+/// Furthest distance, in cells, a slot inside a wall is moved to find a free cell.
+const MAX_FREE_CELL_DISTANCE: i32 = 8;
+
+/// Arrangement of the slots of a group move order.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum FormationLayout {
+    /// Rows as wide as the formation is deep.
+    Grid,
+    /// A single row facing the move direction.
+    Line,
+    /// Rows one slot wider each, with the tip towards the move direction.
+    Wedge,
+}
+
+impl FormationLayout {
+    /// Layout after this one, to cycle through them.
+    pub fn next(self) -> Self {
+        match self {
+            Self::Grid => Self::Line,
+            Self::Line => Self::Wedge,
+            Self::Wedge => Self::Grid,
+        }
+    }
+
+    /// Slot count of each row, front to back.
+    fn row_len(self, row: usize, unit_count: usize) -> usize {
+        match self {
+            Self::Grid => (unit_count as f32).sqrt().ceil() as usize,
+            Self::Line => unit_count,
+            Self::Wedge => row + 1,
+        }
+    }
+}
+
+/// Spreads a group of units in slots around their move target, facing the move direction.
+///
+/// Units are matched to slots by their position relative to the group, so the units in front stay
+/// in front and the ones on the left stay on the left.
+pub struct Formation {
+    pub layout: FormationLayout,
+
+    /// Slot of each unit, in the order the units were given.
+    slots: Vec<f32::Vec2>,
+    /// Slot positions relative to the target, as (lateral, depth) offsets.
+    local_slots: Vec<f32::Vec2>,
+    /// Slot count of each row, front to back.
+    row_len: Vec<usize>,
+    /// Unit indices sorted front to back, then left to right within each row.
+    unit_order: Vec<usize>,
+    /// Cells taken by slots, so slots moved out of walls don't land on them.
+    taken_cells: Vec<IVec2>,
+}
+
+impl Formation {
+    pub fn new() -> Self {
+        Self {
+            layout: FormationLayout::Grid,
+
+            slots: Vec::with_capacity(MAX_UNIT_COUNT),
+            local_slots: Vec::with_capacity(MAX_UNIT_COUNT),
+            row_len: Vec::with_capacity(MAX_UNIT_COUNT),
+            unit_order: Vec::with_capacity(MAX_UNIT_COUNT),
+            taken_cells: Vec::with_capacity(MAX_UNIT_COUNT),
+        }
+    }
+
+    /// Computes a slot around `target` for each unit in `positions`, see `slots`. `unit_size` is
+    /// the size of the largest unit of the group.
+    ///
+    /// Slots blocked in `nav_grid` are moved to the nearest free cell. Without a grid, slots are
+    /// left where the layout puts them.
+    pub fn assign(
+        &mut self,
+        positions: &[f32::Vec2],
+        target: f32::Vec2,
+        unit_size: f32,
+        nav_grid: Option<&NavGrid>,
+    ) {
+        self.slots.clear();
+        if positions.is_empty() {
+            return;
+        }
+
+        // Face from the group towards the target, screen up if they are on top of each other
+        let center = positions.iter().sum::<f32::Vec2>() / positions.len() as f32;
+        let forward = match (target - center).normalize_or_zero() {
+            f32::Vec2::ZERO => f32::Vec2::NEG_Y,
+            forward => forward,
+        };
+        let lateral = -forward.perp();
+
+        self.update_local_slots(positions.len(), unit_size * SLOT_SPACING);
+        self.update_unit_order(positions, center, forward, lateral);
+
+        self.slots.resize(positions.len(), target);
+        for (slot_i, unit_i) in self.unit_order.iter().enumerate() {
+            let local = self.local_slots[slot_i];
+            self.slots[*unit_i] = target + lateral * local.x - forward * local.y;
+        }
+
+        if let Some(nav_grid) = nav_grid {
+            self.move_slots_out_of_walls(nav_grid);
+        }
+    }
+
+    /// Slots of the last `assign`, in the same order as its unit positions.
+    pub fn slots(&self) -> &[f32::Vec2] {
+        &self.slots
+    }
+
+    /// Lays out the slots row by row, front to back and left to right, centered on the target.
+    fn update_local_slots(&mut self, unit_count: usize, spacing: f32) {
+        self.local_slots.clear();
+        self.row_len.clear();
+
+        let mut row = 0;
+        while self.local_slots.len() < unit_count {
+            let row_len = self
+                .layout
+                .row_len(row, unit_count)
+                .min(unit_count - self.local_slots.len());
+            for column in 0..row_len {
+                let lateral = (column as f32 - (row_len - 1) as f32 / 2.0) * spacing;
+                self.local_slots
+                    .push(f32::Vec2::new(lateral, row as f32 * spacing));
+            }
+            self.row_len.push(row_len);
+            row += 1;
+        }
+
+        let center = self.local_slots.iter().sum::<f32::Vec2>() / unit_count as f32;
+        for slot in self.local_slots.iter_mut() {
+            *slot -= center;
+        }
+    }
+
+    /// Sorts the units like the slots, so each unit takes the slot matching its place in the group.
+    fn update_unit_order(
+        &mut self,
+        positions: &[f32::Vec2],
+        center: f32::Vec2,
+        forward: f32::Vec2,
+        lateral: f32::Vec2,
+    ) {
+        let depth = |i: &usize| -(positions[*i] - center).dot(forward);
+        let side = |i: &usize| (positions[*i] - center).dot(lateral);
+
+        self.unit_order.clear();
+        self.unit_order.extend(0..positions.len());
+        self.unit_order
+            .sort_by(|a, b| depth(a).total_cmp(&depth(b)));
+
+        let mut row_start = 0;
+        for row_len in &self.row_len {
+            self.unit_order[row_start..row_start + row_len]
+                .sort_by(|a, b| side(a).total_cmp(&side(b)));
+            row_start += row_len;
+        }
+    }
+
+    fn move_slots_out_of_walls(&mut self, nav_grid: &NavGrid) {
+        // Slots outside walls stay, so their cells are taken from the start
+        self.taken_cells.clear();
+        self.taken_cells.extend(
+            self.slots
+                .iter()
+                .map(|slot| nav_grid.world_to_cell(*slot))
+                .filter(|cell| !nav_grid.is_blocked(*cell)),
+        );
+
+        for slot in self.slots.iter_mut() {
+            let cell = nav_grid.world_to_cell(*slot);
+            if !nav_grid.is_blocked(cell) {
+                continue;
+            }
+
+            // Slots moved out of walls don't share cells, so the units don't end up stacked
+            let taken_cells = &self.taken_cells;
+            let free_cell = nav_grid.nearest_walkable_cell(*slot, MAX_FREE_CELL_DISTANCE, |cell| {
+                !taken_cells.contains(&cell)
+            });
+            if let Some(free_cell) = free_cell {
+                *slot = nav_grid.cell_center(free_cell);
+                self.taken_cells.push(free_cell);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const UNIT_SIZE: f32 = 16.0;
+
+    /// Units spread left to right and front to back below `target`, so they move up.
+    fn group(columns: usize, rows: usize) -> Vec<f32::Vec2> {
+        let mut positions = Vec::new();
+        for row in 0..rows {
+            for column in 0..columns {
+                positions.push(f32::Vec2::new(
+                    column as f32 * 20.0,
+                    500.0 + row as f32 * 20.0 + column as f32,
+                ));
+            }
+        }
+        positions
+    }
+
+    /// Slot count of each row of the last formation, front to back.
+    fn row_lens(formation: &Formation, target: f32::Vec2, forward: f32::Vec2) -> Vec<usize> {
+        let mut depths: Vec<f32> = formation
+            .slots()
+            .iter()
+            .map(|slot| -(*slot - target).dot(forward))
+            .collect();
+        depths.sort_by(f32::total_cmp);
+
+        let mut row_lens: Vec<usize> = Vec::new();
+        let mut last_depth = f32::NEG_INFINITY;
+        for depth in depths {
+            if depth - last_depth > 1.0 {
+                row_lens.push(0);
+            }
+            *row_lens.last_mut().unwrap() += 1;
+            last_depth = depth;
+        }
+        row_lens
+    }
+
+    #[test]
+    fn assign_lays_out_rows_of_each_layout() {
+        let target = f32::Vec2::new(20.0, 0.0);
+        let positions = group(3, 3);
+        let mut formation = Formation::new();
+
+        for (layout, expected) in [
+            (FormationLayout::Grid, vec![3, 3, 3]),
+            (FormationLayout::Line, vec![9]),
+            (FormationLayout::Wedge, vec![1, 2, 3, 3]),
+        ] {
+            formation.layout = layout;
+            formation.assign(&positions, target, UNIT_SIZE, None);
+
+            assert_eq!(formation.slots().len(), positions.len());
+            let forward = (target - positions.iter().sum::<f32::Vec2>() / 9.0).normalize();
+            assert_eq!(
+                row_lens(&formation, target, forward),
+                expected,
+                "{layout:?}"
+            );
+        }
+
+        formation.assign(&[], target, UNIT_SIZE, None);
+        assert!(formation.slots().is_empty());
+    }
+
+    #[test]
+    fn wedge_tip_faces_target() {
+        let target = f32::Vec2::new(20.0, 0.0);
+        let positions = group(3, 2);
+        let mut formation = Formation::new();
+        formation.layout = FormationLayout::Wedge;
+        formation.assign(&positions, target, UNIT_SIZE, None);
+
+        // The tip is the only slot of the front row, taken by the front unit
+        let slots = formation.slots();
+        let tip_y = slots
+            .iter()
+            .map(|slot| slot.y)
+            .fold(f32::INFINITY, f32::min);
+        assert_eq!(slots[0].y, tip_y);
+        assert_eq!(slots.iter().filter(|slot| slot.y < tip_y + 1.0).count(), 1);
+    }
+
+    #[test]
+    fn assign_keeps_relative_unit_order() {
+        let target = f32::Vec2::new(20.0, 0.0);
+        let positions = group(3, 3);
+        let mut formation = Formation::new();
+        formation.layout = FormationLayout::Grid;
+        formation.assign(&positions, target, UNIT_SIZE, None);
+
+        // Units of a row stay left to right, rows stay front to back
+        let slots = formation.slots();
+        for row in 0..3 {
+            let row_slots = &slots[row * 3..row * 3 + 3];
+            assert!(row_slots[0].x < row_slots[1].x && row_slots[1].x < row_slots[2].x);
+        }
+        for column in 0..3 {
+            assert!(slots[column].y < slots[3 + column].y);
+            assert!(slots[3 + column].y < slots[6 + column].y);
+        }
+
+        // Moving down mirrors the formation, units on the left stay on the left
+        let target = f32::Vec2::new(20.0, 1000.0);
+        formation.layout = FormationLayout::Line;
+        formation.assign(&positions[..3], target, UNIT_SIZE, None);
+        let slots = formation.slots();
+        assert!(slots[0].x < slots[1].x && slots[1].x < slots[2].x);
+    }
+
+    #[test]
+    fn blocked_slots_move_to_distinct_free_cells() {
+        let mut nav_grid = NavGrid::new(
+            f32::Vec2::ZERO,
+            f32::Vec2::splat(UNIT_SIZE),
+            IVec2::new(16, 16),
+        );
+        for y in 6..10 {
+            for x in 3..13 {
+                nav_grid.set_blocked(IVec2::new(x, y), true);
+            }
+        }
+
+        let target = f32::Vec2::new(128.0, 128.0);
+        let positions: Vec<_> = (0..6)
+            .map(|i| f32::Vec2::new(80.0 + i as f32 * 20.0, 240.0))
+            .collect();
+        let mut formation = Formation::new();
+        formation.layout = FormationLayout::Line;
+        formation.assign(&positions, target, UNIT_SIZE, Some(&nav_grid));
+
+        let cells: Vec<_> = formation
+            .slots()
+            .iter()
+            .map(|slot| nav_grid.world_to_cell(*slot))
+            .collect();
+        for (i, cell) in cells.iter().enumerate() {
+            assert!(!nav_grid.is_blocked(*cell), "{cell} is blocked");
+            assert!(!cells[..i].contains(cell), "{cell} is taken twice");
+        }
+    }
+}
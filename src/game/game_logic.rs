@@ -1,8 +1,11 @@
 use std::collections::HashMap;
 
-use macroquad::input::is_key_pressed;
-
-use crate::engine::{scene::SceneMgr, sprite::Texture2dMgr, tile::TileMgr};
+use crate::engine::{
+    input::{InputAction, InputMgr},
+    logging::log,
+    scene::SceneMgr,
+    tile::TileMgr,
+};
 
 const LEVEL_COUNT: usize = 32;
 
@@ -37,39 +40,72 @@ impl GameLogic {
         }
     }
 
-    pub async fn init(
+    /// Queues every level for loading. Gameplay starts in `update` once the first level is loaded,
+    /// while the rest keep loading in the background. Levels that fail to load are skipped.
+    pub fn init(&mut self, scene_mgr: &mut SceneMgr) {
+        self.level_scene_i
+            .insert(0, scene_mgr.queue_scene("maps/world01.tmx"));
+        self.level_scene_i
+            .insert(1, scene_mgr.queue_scene("maps/world02.tmx"));
+    }
+
+    pub fn update(&mut self, scene_mgr: &mut SceneMgr, tile_mgr: &TileMgr) {
+        if self.state != GameState::Initialization {
+            return;
+        }
+
+        // Start with the first level that didn't fail, once it's loaded
+        for level in 0..self.level_scene_i.len() {
+            let scene_i = self.get_level_scene_i(&level);
+            if scene_mgr.is_scene_failed(scene_i) {
+                continue;
+            }
+
+            if scene_mgr.is_scene_loaded(scene_i) {
+                if level != 0 {
+                    log::warning(format!("Level 0 failed to load, starting at level {level}"));
+                }
+                self.load_level(level, scene_mgr, tile_mgr);
+                self.set_state(GameState::IngameGameplay);
+            }
+            return;
+        }
+
+        log::error("Every level failed to load");
+        self.set_state(GameState::LoadingFailed);
+    }
+
+    pub fn input(
         &mut self,
+        input_mgr: &InputMgr,
         scene_mgr: &mut SceneMgr,
         tile_mgr: &mut TileMgr,
-        texture_mgr: &mut Texture2dMgr,
     ) {
-        self.level_scene_i.insert(
-            0,
-            scene_mgr
-                .load_scene("maps/world01.tmx", tile_mgr, texture_mgr)
-                .await,
-        );
-
-        self.level_scene_i.insert(
-            1,
-            scene_mgr
-                .load_scene("maps/world02.tmx", tile_mgr, texture_mgr)
-                .await,
-        );
-
-        self.load_level(0, scene_mgr, tile_mgr);
-        self.set_state(GameState::IngameGameplay);
-    }
+        if matches!(
+            self.state,
+            GameState::Initialization | GameState::LoadingFailed
+        ) {
+            return;
+        }
 
-    pub fn input(&mut self, scene_mgr: &mut SceneMgr, tile_mgr: &mut TileMgr) {
-        if is_key_pressed(macroquad::input::KeyCode::Key1) {
+        if input_mgr.is_action_pressed(InputAction::LoadLevel1) {
             self.load_level(0, scene_mgr, tile_mgr);
-        } else if is_key_pressed(macroquad::input::KeyCode::Key2) {
+        } else if input_mgr.is_action_pressed(InputAction::LoadLevel2) {
             self.load_level(1, scene_mgr, tile_mgr);
         }
     }
 
-    pub fn load_level(&mut self, level: usize, scene_mgr: &mut SceneMgr, tile_mgr: &mut TileMgr) {
+    pub fn load_level(&mut self, level: usize, scene_mgr: &mut SceneMgr, tile_mgr: &TileMgr) {
+        let scene_i = self.get_level_scene_i(&level);
+        if scene_mgr.is_scene_failed(scene_i) {
+            log::error(format!("Level {level} failed to load"));
+            return;
+        }
+        if !scene_mgr.is_scene_loaded(scene_i) {
+            log::warning(format!("Level {level} is still loading"));
+            return;
+        }
+
         self.current_level = level;
         scene_mgr.set_active_scene(Some(self.current_scene_i()), &tile_mgr);
     }
@@ -94,6 +130,8 @@ impl GameLogic {
 #[derive(Clone, Copy, PartialEq, Eq)]
 pub enum GameState {
     Initialization,
+    /// No level could be loaded, so there is nothing to play.
+    LoadingFailed,
     MainMenu,
     IngameGameplay,
     IngamePause,
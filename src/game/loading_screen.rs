@@ -1,11 +1,15 @@
 use macroquad::{
     color,
+    shapes::{draw_rectangle, draw_rectangle_lines},
     text::{draw_text, get_text_center},
     window::{clear_background, screen_height, screen_width},
 };
 
 use crate::engine::{camera::camera::CameraMgr, scene::SceneMgr};
 
+const PROGRESS_BAR_WIDTH: f32 = 480.0;
+const PROGRESS_BAR_HEIGHT: f32 = 24.0;
+
 pub struct LoadingScreen;
 
 /// Shows a loading screen while the SceneManager is loading or unloading a scene.
@@ -15,26 +19,71 @@ impl LoadingScreen {
     }
 
     pub fn render(&self, scene_mgr: &SceneMgr, camera_mgr: &CameraMgr) {
-        if scene_mgr.has_pending_spawn() || scene_mgr.has_pending_despawn() {
-            clear_background(color::RED);
-
-            camera_mgr.push_active_camera();
-
-            let loading_text = "Loading...";
-            let text_size = 48;
-            let text_center = get_text_center(loading_text, Option::None, text_size, 1.0, 0.0);
-            let text_x = screen_width() / 2.0 - text_center.x;
-            let text_y = screen_height() / 2.0 - text_center.y;
-
-            draw_text(
-                loading_text,
-                text_x.round(),
-                text_y.round(),
-                text_size as f32,
-                color::WHITE,
-            );
-
-            camera_mgr.pop_active_camera();
+        if scene_mgr.active_scene_id.is_some()
+            && !scene_mgr.has_pending_spawn()
+            && !scene_mgr.has_pending_despawn()
+        {
+            return;
         }
+
+        clear_background(color::RED);
+
+        camera_mgr.push_active_camera();
+
+        let loading_text = "Loading...";
+        let text_size = 48;
+        let text_center = get_text_center(loading_text, Option::None, text_size, 1.0, 0.0);
+        let text_x = screen_width() / 2.0 - text_center.x;
+        let text_y = screen_height() / 2.0 - text_center.y - PROGRESS_BAR_HEIGHT * 2.0;
+
+        draw_text(
+            loading_text,
+            text_x.round(),
+            text_y.round(),
+            text_size as f32,
+            color::WHITE,
+        );
+
+        // Progress bar
+        let (fraction, current_asset) = match scene_mgr.load_progress() {
+            Some(progress) => (progress.fraction(), progress.current_asset.as_str()),
+            None => (1.0, ""),
+        };
+
+        let bar_x = (screen_width() / 2.0 - PROGRESS_BAR_WIDTH / 2.0).round();
+        let bar_y = (screen_height() / 2.0 - PROGRESS_BAR_HEIGHT / 2.0).round();
+
+        draw_rectangle(
+            bar_x,
+            bar_y,
+            PROGRESS_BAR_WIDTH * fraction,
+            PROGRESS_BAR_HEIGHT,
+            color::WHITE,
+        );
+        draw_rectangle_lines(
+            bar_x,
+            bar_y,
+            PROGRESS_BAR_WIDTH,
+            PROGRESS_BAR_HEIGHT,
+            4.0,
+            color::WHITE,
+        );
+
+        // Current asset
+        let asset_text_size = 24;
+        let asset_text_center =
+            get_text_center(current_asset, Option::None, asset_text_size, 1.0, 0.0);
+        let asset_text_x = screen_width() / 2.0 - asset_text_center.x;
+        let asset_text_y = bar_y + PROGRESS_BAR_HEIGHT * 2.0 - asset_text_center.y;
+
+        draw_text(
+            current_asset,
+            asset_text_x.round(),
+            asset_text_y.round(),
+            asset_text_size as f32,
+            color::WHITE,
+        );
+
+        camera_mgr.pop_active_camera();
     }
 }
@@ -1,8 +1,10 @@
 //! Game specific logic and systems.
 
+pub mod formation;
 pub mod game_logic;
 pub mod loading_screen;
 pub mod player;
 pub mod selector_box;
 pub mod summoning_circle;
+pub mod tile_occupancy;
 pub mod wall;
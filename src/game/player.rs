@@ -1,87 +1,206 @@
 use crate::{
-    engine::collision::collider::{self, ColliderMgr, Hit},
-    engine::sprite::{SpriteMgr, Texture2dMgr},
+    engine::animation::{AnimationClip, AnimationEvent},
+    engine::collision::collider::{
+        ColliderHandle, ColliderMgr, CollisionLayer, CollisionMask, HitList, TriggerEvent,
+    },
+    engine::grid::MapGrid,
+    engine::handle::insert_at,
+    engine::input::{InputAction, InputMgr},
+    engine::logging::log,
+    engine::navigation::{NavGrid, NavMgr},
+    engine::sprite::{SpriteHandle, SpriteMgr, Texture2dMgr},
     engine::{camera::camera::CameraMgr, scene::SceneMgr},
 };
-use macroquad::math::f32;
-use macroquad::{
-    color,
-    input::{is_key_down, is_mouse_button_pressed, KeyCode, MouseButton},
-};
+use macroquad::color::{self, Color};
+use macroquad::math::{f32, IVec2, Rect};
+use std::collections::{HashMap, VecDeque};
 
-use super::{selector_box::SelectorBox, summoning_circle::SummoningCircleMgr};
+use super::{formation::Formation, selector_box::SelectorBox, tile_occupancy::TileOccupancy};
 
 const MAX_UNIT_COUNT: usize = 1024;
+/// Commands a unit can hold, counting the current one. Orders queued past it are ignored.
+const MAX_COMMAND_COUNT: usize = 16;
+/// Map property with the `MovementMode` of the scene: `free` (default) or `tile`.
+const MOVEMENT_MODE_PROPERTY: &str = "movement_mode";
 const MOVE_DISTANCE_TOLERANCE: f32 = 1.0;
-const COLLISION_IGNORE_LIST_SIZE: usize = 1024;
+/// Distance at which a path waypoint counts as reached. Looser than the move target, as crowded
+/// units can't all walk over the same point.
+const WAYPOINT_DISTANCE_TOLERANCE: f32 = 8.0;
+/// Units closer than their combined half widths times this push each other apart.
+const SEPARATION_RANGE: f32 = 1.2;
+/// Strength of the separation push against the move direction.
+const SEPARATION_WEIGHT: f32 = 1.5;
+/// Units inside their arrival radius that made less than this fraction of their expected progress
+/// towards the target in a frame are settled, and stop.
+const SETTLED_PROGRESS_RATIO: f32 = 0.25;
+/// Surfaces a unit can slide along in a single movement step, like the two walls of a corner.
+const MAX_SLIDE_COUNT: usize = 3;
+/// Distance from the pivot (the unit's feet) to the bottom of the sprite.
+const PIVOT_BOTTOM_OFFSET: f32 = 5.0;
+const SELECTED_TINT: Color = Color::new(1.0, 1.0, 0.6, 1.0);
+/// Color of the queued path of selected units.
+const COMMAND_PATH_COLOR: Color = Color::new(1.0, 1.0, 0.6, 0.8);
+const COMMAND_TARGET_RADIUS: f32 = 4.0;
+/// Units are blocked by other units and walls.
+const COLLISION_MASK: CollisionMask =
+    CollisionMask::from_layers(&[CollisionLayer::Unit, CollisionLayer::Wall]);
+/// Units keep their distance from other units only.
+const UNIT_MASK: CollisionMask = CollisionMask::from_layers(&[CollisionLayer::Unit]);
+
+/// Unit sprite-sheet grid as (columns, rows). `player01.png` currently holds a single frame, so
+/// every clip uses it until the sheet gets more frames.
+const UNIT_SHEET_GRID: (u32, u32) = (1, 1);
+/// Sheet row, frame count, frame duration and looping of the clip of each `UnitState`.
+const UNIT_CLIPS: [(u32, u32, f32, bool); UnitState::COUNT] = [
+    (0, 1, 0.2, true),  // Idle
+    (0, 1, 0.1, true),  // Walking
+    (0, 1, 0.4, false), // Attacking
+    (0, 1, 0.6, false), // Summoning
+];
 
 pub struct PlayerUnitMgr {
     move_speed: Vec<f32>,
     /// Current movement input
     input_move: Vec<f32::Vec2>,
-    anchor_offset: Vec<f32::Vec2>,
     is_selected: Vec<bool>,
+    /// Whether the sprite has the selection tint, so it's only set when the selection changes.
+    is_tinted: Vec<bool>,
     is_active: Vec<bool>,
     team: Vec<PlayerTeam>,
+    state: Vec<UnitState>,
 
-    collider_i: Vec<Option<usize>>,
-    sprite_i: Vec<Option<usize>>,
+    collider_i: Vec<Option<ColliderHandle>>,
+    sprite_i: Vec<Option<SpriteHandle>>,
     scene_object_i: Vec<usize>,
+    /// Maps unit colliders to unit indices, to find the units hit by collision queries.
+    collider_unit_map: HashMap<ColliderHandle, usize>,
 
-    /// Collision hit buffers
-    movement_hit_buffer: Vec<Vec<Hit>>,
-    selection_hit: Vec<Hit>,
-
-    move_target: Vec<Option<f32::Vec2>>,
+    /// Slots of despawned units, reused by new ones.
+    free_i: Vec<usize>,
 
+    /// Collision hit buffers
+    movement_hits: Vec<HitList>,
+
+    /// Commands of each unit in order. The front one is being carried out.
+    commands: Vec<VecDeque<UnitCommand>>,
+    /// Path waypoints left before the current move target, in reverse order so the next one is
+    /// last.
+    waypoints: Vec<Vec<f32::Vec2>>,
+    /// Distance to the move target at which a settled unit counts as arrived, as other units may
+    /// stand in the way.
+    arrival_radius: Vec<f32>,
+    /// Push away from nearby units, updated every frame.
+    separation: Vec<f32::Vec2>,
+
+    /// Slots of the units ordered together.
+    formation: Formation,
+    /// Units of the current move order and their positions, reused between orders.
+    order_unit_i: Vec<usize>,
+    order_positions: Vec<f32::Vec2>,
+
+    movement_mode: MovementMode,
+    /// Tile grid of the active scene, set on spawn.
+    map_grid: Option<MapGrid>,
+    /// Tiles units end their moves on, in the tile movement mode.
+    occupancy: TileOccupancy,
+    /// Path cells buffer, reused between tile paths.
+    path_cells: Vec<IVec2>,
+    /// `NavMgr::version` the paths of the units were found on.
+    nav_version: u32,
+
+    /// Whether box selections add to the current selection, read on input.
+    is_adding_to_selection: bool,
+    /// Whether the selected units attack this frame, read on input.
+    is_attack_pressed: bool,
     mouse_pos: f32::Vec2,
+    /// Scene tile under the mouse. `None` when outside of the map.
+    mouse_tile: Option<IVec2>,
 
-    collision_ignored_i: Vec<usize>,
+    /// Animation clip of each `UnitState`. Registered when the first unit is added.
+    clip_i: Option<[usize; UnitState::COUNT]>,
 }
 
 impl PlayerUnitMgr {
     pub fn new() -> Self {
         let move_speed = Vec::with_capacity(MAX_UNIT_COUNT);
         let input_move = Vec::with_capacity(MAX_UNIT_COUNT);
-        let anchor_offset = Vec::with_capacity(MAX_UNIT_COUNT);
         let is_selected = Vec::with_capacity(MAX_UNIT_COUNT);
+        let is_tinted = Vec::with_capacity(MAX_UNIT_COUNT);
         let is_active = Vec::with_capacity(MAX_UNIT_COUNT);
 
         let team = Vec::with_capacity(MAX_UNIT_COUNT);
+        let state = Vec::with_capacity(MAX_UNIT_COUNT);
 
         let collider_i = Vec::with_capacity(MAX_UNIT_COUNT);
         let sprite_i = Vec::with_capacity(MAX_UNIT_COUNT);
         let scene_object_i = Vec::with_capacity(MAX_UNIT_COUNT);
+        let collider_unit_map = HashMap::with_capacity(MAX_UNIT_COUNT);
+
+        let free_i = Vec::with_capacity(MAX_UNIT_COUNT);
+
+        let movement_hits = Vec::with_capacity(MAX_UNIT_COUNT);
+
+        let commands = Vec::with_capacity(MAX_UNIT_COUNT);
+        let waypoints = Vec::with_capacity(MAX_UNIT_COUNT);
+        let arrival_radius = Vec::with_capacity(MAX_UNIT_COUNT);
+        let separation = Vec::with_capacity(MAX_UNIT_COUNT);
 
-        let movement_hit_buffer = Vec::with_capacity(MAX_UNIT_COUNT);
-        let selection_hit = Vec::with_capacity(MAX_UNIT_COUNT);
+        let formation = Formation::new();
+        let order_unit_i = Vec::with_capacity(MAX_UNIT_COUNT);
+        let order_positions = Vec::with_capacity(MAX_UNIT_COUNT);
 
-        let move_target = Vec::with_capacity(MAX_UNIT_COUNT);
+        let movement_mode = MovementMode::Free;
+        let map_grid = None;
+        let occupancy = TileOccupancy::new(MAX_UNIT_COUNT);
+        let path_cells = Vec::new();
+        let nav_version = 0;
 
+        let is_adding_to_selection = false;
+        let is_attack_pressed = false;
         let mouse_pos = f32::Vec2::ZERO;
+        let mouse_tile = None;
 
-        let collision_ignored_i = Vec::with_capacity(COLLISION_IGNORE_LIST_SIZE);
+        let clip_i = None;
 
         Self {
             move_speed,
             input_move,
-            anchor_offset,
             is_selected,
+            is_tinted,
             is_active,
             team,
+            state,
 
             collider_i,
             sprite_i,
             scene_object_i,
+            collider_unit_map,
 
-            movement_hit_buffer,
-            selection_hit,
+            free_i,
 
-            move_target,
+            movement_hits,
 
+            commands,
+            waypoints,
+            arrival_radius,
+            separation,
+
+            formation,
+            order_unit_i,
+            order_positions,
+
+            movement_mode,
+            map_grid,
+            occupancy,
+            path_cells,
+            nav_version,
+
+            is_adding_to_selection,
+            is_attack_pressed,
             mouse_pos,
+            mouse_tile,
 
-            collision_ignored_i,
+            clip_i,
         }
     }
 
@@ -94,35 +213,36 @@ impl PlayerUnitMgr {
         collider_mgr: &mut ColliderMgr,
         texture_mgr: &mut Texture2dMgr,
     ) -> usize {
-        self.move_speed.push(move_speed);
-        self.input_move.push(f32::Vec2::ZERO);
-        self.is_selected.push(false);
-        self.is_active.push(false);
-        self.move_target.push(None);
-
-        let index = self.len() - 1;
+        // Reuse the slot of a despawned unit if possible
+        let index = self.free_i.pop().unwrap_or(self.len());
 
-        if index == 0 {
+        if self.len() == 0 {
             assert!(
                 team == PlayerTeam::Player,
                 "First unit added should have 'Player' team."
             );
         }
 
-        self.team.push(team);
-
-        // Create hit buffers
-        self.movement_hit_buffer
-            .push(ColliderMgr::create_hit_buffer(
-                collider::MAX_COLLISION_COUNT,
-            ));
-        self.selection_hit.push(Hit {
-            is_colliding: false,
-            collider_i: usize::MAX,
-            position: f32::Vec2::ZERO,
-            delta: f32::Vec2::ZERO,
-            normal: f32::Vec2::ZERO,
-        });
+        insert_at(&mut self.move_speed, index, move_speed);
+        insert_at(&mut self.input_move, index, f32::Vec2::ZERO);
+        insert_at(&mut self.is_selected, index, false);
+        insert_at(&mut self.is_tinted, index, false);
+        insert_at(&mut self.is_active, index, false);
+        insert_at(&mut self.arrival_radius, index, 0.0);
+        insert_at(&mut self.separation, index, f32::Vec2::ZERO);
+
+        insert_at(&mut self.team, index, team);
+        insert_at(&mut self.state, index, UnitState::Idle);
+
+        // Create hit lists, command and waypoint buffers, reused slots keep theirs
+        if index == self.movement_hits.len() {
+            self.movement_hits.push(HitList::new());
+            self.commands
+                .push(VecDeque::with_capacity(MAX_COMMAND_COUNT));
+            self.waypoints.push(Vec::new());
+        }
+        self.commands[index].clear();
+        self.waypoints[index].clear();
 
         // Create sprite
         let sprite_i = sprite_mgr
@@ -133,18 +253,49 @@ impl PlayerUnitMgr {
                 texture_mgr,
             )
             .await;
-        self.sprite_i.push(Some(sprite_i));
+        insert_at(&mut self.sprite_i, index, Some(sprite_i));
+
+        // Animations
+        if self.clip_i.is_none() {
+            let sheet_size = texture_mgr.texture[sprite_mgr.texture_i(sprite_i)].size();
+            let (columns, rows) = UNIT_SHEET_GRID;
+            self.clip_i = Some(
+                UNIT_CLIPS.map(|(row, frame_count, frame_duration, is_looping)| {
+                    sprite_mgr.add_clip(AnimationClip::from_sheet_row(
+                        sheet_size,
+                        columns,
+                        rows,
+                        row,
+                        frame_count,
+                        frame_duration,
+                        is_looping,
+                    ))
+                }),
+            );
+        }
+        sprite_mgr.play(sprite_i, self.clip_i.unwrap()[UnitState::Idle as usize]);
 
+        // Pivot at the unit's feet, so its position is where it stands
         let scaled_size = sprite_mgr.scaled_size(sprite_i);
-        let anchor_offset = f32::Vec2::new(0.0, scaled_size.y / 2.0 - 5.0);
-        self.anchor_offset.push(anchor_offset);
+        let pivot = f32::Vec2::new(0.5, 1.0 - PIVOT_BOTTOM_OFFSET / scaled_size.y);
+        sprite_mgr.set_pivot(sprite_i, pivot);
+
+        // Units lower on the screen are drawn in front
+        sprite_mgr.set_y_sorted(sprite_i, true);
 
         // Create collider
-        let collider_i = collider_mgr.add_from_sprite(sprite_i, None, sprite_mgr);
-        self.collider_i.push(Some(collider_i));
-        collider_mgr.render_bbox[collider_i] = false;
+        let collider_i = collider_mgr.add_from_sprite(
+            sprite_i,
+            None,
+            CollisionLayer::Unit,
+            COLLISION_MASK,
+            sprite_mgr,
+        );
+        insert_at(&mut self.collider_i, index, Some(collider_i));
+        self.collider_unit_map.insert(collider_i, index);
+        collider_mgr.set_render_bbox(collider_i, false);
 
-        self.scene_object_i.push(scene_object_i);
+        insert_at(&mut self.scene_object_i, index, scene_object_i);
 
         // Set not active
         self.set_active(index, false, sprite_mgr, collider_mgr);
@@ -162,31 +313,28 @@ impl PlayerUnitMgr {
         sprite_mgr: &mut SpriteMgr,
         collider_mgr: &mut ColliderMgr,
         texture_mgr: &mut Texture2dMgr,
-        selector_box: &SelectorBox,
-        summoning_circle_mgr: &SummoningCircleMgr,
     ) {
         if scene_mgr.active_scene_id == None || scene_mgr.active_objects.len() == 0 {
             return;
         }
 
-        // Regenerate collision ignored list
-        self.collision_ignored_i.clear();
-        self.collision_ignored_i.push(selector_box.collider_i());
-
-        for circle_i in 0..summoning_circle_mgr.len() {
-            if !summoning_circle_mgr.is_active(circle_i) {
-                continue;
-            }
-            self.collision_ignored_i
-                .push(summoning_circle_mgr.collider_i(circle_i));
-        }
+        self.movement_mode =
+            match scene_mgr.get_active_scene_property_string(MOVEMENT_MODE_PROPERTY) {
+                Some(mode_str) => match mode_str.as_str() {
+                    "free" => MovementMode::Free,
+                    "tile" => MovementMode::Tile,
+                    _ => panic!(
+                        "Invalid value for map property `{MOVEMENT_MODE_PROPERTY}`: `{:?}`",
+                        mode_str
+                    ),
+                },
+                None => MovementMode::Free,
+            };
+        self.map_grid = scene_mgr.active_grid().copied();
+        self.occupancy.clear();
 
         // Spawn from scene
-        for scene_object_i in &scene_mgr.active_objects {
-            let object_class = scene_mgr.object_class[*scene_object_i].as_ref();
-            if object_class.unwrap() != "PlayerUnit" {
-                continue;
-            }
+        for scene_object_i in scene_mgr.active_objects_of_class("PlayerUnit") {
             let name = scene_mgr.object_name[*scene_object_i].as_ref().unwrap();
 
             let team = match scene_mgr.get_object_property_string(*scene_object_i, "team") {
@@ -234,28 +382,73 @@ impl PlayerUnitMgr {
 
             sprite_mgr.set_position(self.sprite_i[new_index].unwrap(), position);
 
+            // Stand on a tile no other unit took
+            if let (MovementMode::Tile, Some(map_grid), true) =
+                (self.movement_mode, self.map_grid, start_active)
+            {
+                let tile = map_grid.world_to_tile(position);
+                match self
+                    .occupancy
+                    .nearest_free_tile(tile, new_index, &map_grid, None)
+                {
+                    Some(free_tile) => {
+                        self.occupancy.reserve(new_index, free_tile);
+                        sprite_mgr.set_position(
+                            self.sprite_i[new_index].unwrap(),
+                            map_grid.tile_center(free_tile),
+                        );
+                    }
+                    None => log::warning(format!("No free tile for PlayerUnit `{:?}`", name)),
+                }
+            }
+
             self.set_active(new_index, start_active, sprite_mgr, collider_mgr);
         }
     }
 
+    /// Removes the units of despawned scene objects, along with their sprites and colliders. Their
+    /// slots are reused by the next spawned units.
     pub fn despawn(
         &mut self,
         scene_mgr: &SceneMgr,
         collider_mgr: &mut ColliderMgr,
         sprite_mgr: &mut SpriteMgr,
+        texture_mgr: &mut Texture2dMgr,
     ) {
-        'scene_iter: for scene_object_i in &scene_mgr.objects_to_despawn {
-            if scene_mgr.object_class[*scene_object_i].as_ref().unwrap() == "PlayerUnit" {
-                for index in 0..self.len() {
-                    if self.scene_object_i[index] == *scene_object_i && self.is_active(index) {
-                        self.set_active(index, false, sprite_mgr, collider_mgr);
-                        continue 'scene_iter;
-                    }
+        'scene_iter: for scene_object_i in scene_mgr.objects_to_despawn_of_class("PlayerUnit") {
+            for index in 0..self.len() {
+                if self.scene_object_i[index] == *scene_object_i && self.sprite_i[index].is_some() {
+                    self.remove(index, sprite_mgr, collider_mgr, texture_mgr);
+                    continue 'scene_iter;
                 }
             }
         }
     }
 
+    fn remove(
+        &mut self,
+        index: usize,
+        sprite_mgr: &mut SpriteMgr,
+        collider_mgr: &mut ColliderMgr,
+        texture_mgr: &mut Texture2dMgr,
+    ) {
+        self.is_active[index] = false;
+        self.is_selected[index] = false;
+        self.commands[index].clear();
+        self.waypoints[index].clear();
+        self.occupancy.release(index);
+
+        if let Some(sprite_i) = self.sprite_i[index].take() {
+            sprite_mgr.remove(sprite_i, texture_mgr);
+        }
+        if let Some(collider_i) = self.collider_i[index].take() {
+            self.collider_unit_map.remove(&collider_i);
+            collider_mgr.remove(collider_i);
+        }
+
+        self.free_i.push(index);
+    }
+
     fn set_active(
         &mut self,
         index: usize,
@@ -269,49 +462,253 @@ impl PlayerUnitMgr {
         let collider_i = self.collider_i[index].unwrap();
         let sprite_i = self.sprite_i[index].unwrap();
 
-        let position = sprite_mgr.position(sprite_i);
-        collider_mgr.set_position(collider_i, position.x, position.y);
+        let top_left = sprite_mgr.top_left(sprite_i);
+        collider_mgr.set_position(collider_i, top_left.x, top_left.y);
         collider_mgr.set_active(collider_i, is_active);
     }
 
-    pub fn input(&mut self, collider_mgr: &ColliderMgr, camera_mgr: &CameraMgr) {
+    pub fn input(
+        &mut self,
+        scene_mgr: &SceneMgr,
+        sprite_mgr: &SpriteMgr,
+        collider_mgr: &ColliderMgr,
+        camera_mgr: &CameraMgr,
+        nav_mgr: &NavMgr,
+        input_mgr: &InputMgr,
+    ) {
         self.mouse_pos = camera_mgr.get_mouse_world_position();
+        self.mouse_tile = scene_mgr.world_to_tile(self.mouse_pos);
+
+        let is_select_pressed = input_mgr.is_action_pressed(InputAction::Select);
+        let is_command_pressed = input_mgr.is_action_pressed(InputAction::Command);
+        self.is_adding_to_selection = input_mgr.is_action_down(InputAction::AddToSelection);
+        self.is_attack_pressed = input_mgr.is_action_pressed(InputAction::Attack);
 
-        let is_mouse_r_pressed = is_mouse_button_pressed(MouseButton::Right);
-        let is_mouse_l_pressed = is_mouse_button_pressed(MouseButton::Left);
+        if input_mgr.is_action_pressed(InputAction::CycleFormation) {
+            self.formation.layout = self.formation.layout.next();
+            log::debug(format!("Formation layout: {:?}", self.formation.layout));
+        }
 
+        // Selection
         for i in 0..self.len() {
-            // Selection
-            if !self.is_active(i) {
+            if !self.is_active(i) || !is_select_pressed {
                 continue;
             }
 
-            if is_mouse_l_pressed {
-                let mut selection_hit = &mut self.selection_hit[i];
-                let collider_i = self.collider_i[i].unwrap();
-                let bbox = collider_mgr.bbox[collider_i];
-                let bbox_center = bbox.center();
+            let collider_i = self.collider_i[i].unwrap();
+            let bbox = collider_mgr.bbox(collider_i);
+            let bbox_center = bbox.center();
+
+            self.is_selected[i] = ColliderMgr::intersect_point_single(
+                &self.mouse_pos,
+                &bbox,
+                &bbox_center,
+                collider_i,
+            )
+            .is_some()
+                || (self.is_adding_to_selection && self.is_selected[i]);
+        }
+
+        // Movement, orders outside of the map are ignored
+        if is_command_pressed && self.mouse_tile.is_some() {
+            let is_queued = input_mgr.is_action_down(InputAction::QueueCommand);
+            self.order_move(self.mouse_pos, is_queued, sprite_mgr, collider_mgr, nav_mgr);
+        }
 
-                self.is_selected[i] = ColliderMgr::intersect_point_single(
-                    &self.mouse_pos,
-                    &bbox,
-                    &bbox_center,
-                    collider_i,
-                    &mut selection_hit,
-                ) || (is_key_down(KeyCode::LeftShift) && self.is_selected[i]);
+        // Command queue editing
+        let is_pop_pressed = input_mgr.is_action_pressed(InputAction::PopCommand);
+        let is_stop_pressed = input_mgr.is_action_pressed(InputAction::StopCommands);
+        for i in 0..self.len() {
+            if !self.is_active(i) || !self.is_selected[i] {
+                continue;
             }
 
-            // Movement
-            if !self.is_selected[i] {
+            if is_stop_pressed {
+                self.clear_commands(i);
+            } else if is_pop_pressed {
+                self.pop_command(i);
+            }
+        }
+    }
+
+    /// Orders the selected units to move to `target`, each to its own formation slot. Queued
+    /// orders are carried out after the current ones, otherwise they replace them.
+    fn order_move(
+        &mut self,
+        target: f32::Vec2,
+        is_queued: bool,
+        sprite_mgr: &SpriteMgr,
+        collider_mgr: &ColliderMgr,
+        nav_mgr: &NavMgr,
+    ) {
+        // Queued formations are laid out from where the units will be by then, paths start at the
+        // feet of the units
+        self.order_unit_i.clear();
+        self.order_positions.clear();
+        let mut unit_size: f32 = 0.0;
+        for i in 0..self.len() {
+            if !self.is_active(i) || !self.is_selected[i] {
                 continue;
             }
 
-            if is_mouse_r_pressed {
-                self.move_target[i] = Some(self.mouse_pos);
+            let bbox = collider_mgr.bbox(self.collider_i[i].unwrap());
+            let position = match self.commands[i].back() {
+                Some(UnitCommand::Move(last_target)) if is_queued => *last_target,
+                _ => sprite_mgr.position(self.sprite_i[i].unwrap()),
+            };
+            self.order_unit_i.push(i);
+            self.order_positions.push(position);
+            unit_size = unit_size.max(bbox.w.abs()).max(bbox.h.abs());
+        }
+
+        self.formation
+            .assign(&self.order_positions, target, unit_size, nav_mgr.grid());
+
+        for order_i in 0..self.order_unit_i.len() {
+            let i = self.order_unit_i[order_i];
+            if !is_queued {
+                self.clear_commands(i);
+            }
+            if self.commands[i].len() >= MAX_COMMAND_COUNT {
+                continue;
+            }
+
+            // Idle units in the tile movement mode stand on their reserved tile
+            let start = match (self.movement_mode, self.map_grid, self.occupancy.tile(i)) {
+                (MovementMode::Tile, Some(map_grid), Some(tile)) => map_grid.tile_center(tile),
+                _ => self.order_positions[order_i],
+            };
+
+            let slot = self.formation.slots()[order_i];
+            self.commands[i].push_back(UnitCommand::Move(slot));
+            self.arrival_radius[i] = unit_size;
+            self.reserve_last_command_tile(i, nav_mgr.grid());
+
+            if self.commands[i].len() == 1 {
+                self.start_command(i, start, nav_mgr);
+            }
+        }
+    }
+
+    /// Starts the current command of the unit, finding the path to its target from `position`.
+    fn start_command(&mut self, index: usize, position: f32::Vec2, nav_mgr: &NavMgr) {
+        let waypoints = &mut self.waypoints[index];
+        waypoints.clear();
+
+        let target = match self.commands[index].front() {
+            Some(UnitCommand::Move(target)) => *target,
+            None => return,
+        };
+
+        if let (MovementMode::Tile, Some(map_grid), Some(nav_grid)) =
+            (self.movement_mode, self.map_grid, nav_mgr.grid())
+        {
+            // Units can't walk straight through walls between tiles, so unreachable targets are
+            // dropped. Once out of commands the unit keeps the tile it stands on.
+            let start_cell = nav_grid.world_to_cell(position);
+            let target_cell = nav_grid.world_to_cell(target);
+            if !nav_grid.find_path(start_cell, target_cell, &mut self.path_cells) {
+                self.commands[index].pop_front();
+                if self.commands[index].is_empty() {
+                    self.occupancy.release(index);
+                    self.occupancy
+                        .reserve(index, map_grid.world_to_tile(position));
+                }
+                self.start_command(index, position, nav_mgr);
+                return;
+            }
+
+            // Step through the center of every tile of the path
+            let mut last_tile = map_grid.world_to_tile(position);
+            for cell in &self.path_cells {
+                let tile = map_grid.world_to_tile(nav_grid.cell_center(*cell));
+                if tile != last_tile {
+                    waypoints.push(map_grid.tile_center(tile));
+                    last_tile = tile;
+                }
+            }
+        } else if !nav_mgr.find_path(position, target, waypoints) {
+            waypoints.clear();
+        }
+
+        // Walk around obstacles, or straight to the target if there is no path in the free
+        // movement mode. The last waypoint is the target itself.
+        if waypoints.last() == Some(&target) {
+            waypoints.pop();
+        }
+        waypoints.reverse();
+    }
+
+    /// Moves the tile reservation of the unit to the tile its last command ends on, in the tile
+    /// movement mode. The command target is moved to the nearest free tile, or the command is
+    /// dropped if there is none.
+    fn reserve_last_command_tile(&mut self, index: usize, nav_grid: Option<&NavGrid>) {
+        let map_grid = match (self.movement_mode, self.map_grid) {
+            (MovementMode::Tile, Some(map_grid)) => map_grid,
+            _ => return,
+        };
+        let target = match self.commands[index].back_mut() {
+            Some(UnitCommand::Move(target)) => target,
+            None => return,
+        };
+
+        let tile = map_grid.world_to_tile(*target);
+        match self
+            .occupancy
+            .nearest_free_tile(tile, index, &map_grid, nav_grid)
+        {
+            Some(free_tile) => {
+                self.occupancy.reserve(index, free_tile);
+                *target = map_grid.tile_center(free_tile);
+            }
+            None => {
+                self.commands[index].pop_back();
             }
         }
     }
 
+    /// Finishes the current command of the unit and starts the next one.
+    fn complete_command(&mut self, index: usize, position: f32::Vec2, nav_mgr: &NavMgr) {
+        self.commands[index].pop_front();
+        self.start_command(index, position, nav_mgr);
+    }
+
+    /// Removes every command of the unit, stopping it. In the tile movement mode the unit still
+    /// finishes its current step, so it stops on a tile.
+    pub fn clear_commands(&mut self, index: usize) {
+        let step_target = match self.movement_mode {
+            MovementMode::Tile => self.waypoints[index].last().copied().or_else(|| {
+                self.commands[index]
+                    .front()
+                    .map(|UnitCommand::Move(target)| *target)
+            }),
+            MovementMode::Free => None,
+        };
+
+        self.commands[index].clear();
+        self.waypoints[index].clear();
+
+        if let Some(step_target) = step_target {
+            self.commands[index].push_back(UnitCommand::Move(step_target));
+            self.reserve_last_command_tile(index, None);
+        }
+    }
+
+    /// Removes the last queued command of the unit. Removing the current one stops the unit, like
+    /// `clear_commands`.
+    pub fn pop_command(&mut self, index: usize) -> Option<UnitCommand> {
+        if self.commands[index].len() <= 1 {
+            let command = self.commands[index].front().copied();
+            self.clear_commands(index);
+            return command;
+        }
+
+        let command = self.commands[index].pop_back();
+        self.reserve_last_command_tile(index, None);
+
+        command
+    }
+
     fn clear_input(&mut self, index: usize) {
         self.input_move[index].x = 0.0;
         self.input_move[index].y = 0.0;
@@ -323,7 +720,56 @@ impl PlayerUnitMgr {
         selector_box: &SelectorBox,
         sprite_mgr: &mut SpriteMgr,
         collider_mgr: &mut ColliderMgr,
+        nav_mgr: &NavMgr,
     ) {
+        // One-shot animations go back to idle once finished
+        for event in sprite_mgr.animation_events().to_vec() {
+            let AnimationEvent::Finished { sprite, .. } = event;
+            for i in 0..self.len() {
+                if self.sprite_i[i] != Some(sprite) {
+                    continue;
+                }
+                if matches!(self.state[i], UnitState::Attacking | UnitState::Summoning) {
+                    self.set_state(i, UnitState::Idle, sprite_mgr);
+                }
+            }
+        }
+
+        // Units summon when they step into a summoning circle, as found by the last trigger update
+        for event in collider_mgr.trigger_events() {
+            let (trigger, other) = match *event {
+                TriggerEvent::Enter { trigger, other } => (trigger, other),
+                _ => continue,
+            };
+            if !collider_mgr.is_valid(trigger)
+                || collider_mgr.layer(trigger) != CollisionLayer::Trigger
+            {
+                continue;
+            }
+
+            let unit_i = self.collider_i.iter().position(|c| *c == Some(other));
+            if let Some(i) = unit_i.filter(|i| self.is_active(*i)) {
+                self.set_state(i, UnitState::Summoning, sprite_mgr);
+            }
+        }
+
+        // Re-plan the paths when the navigation grid changed, like when a door toggled
+        if self.nav_version != nav_mgr.version() {
+            self.nav_version = nav_mgr.version();
+            for i in 0..self.len() {
+                if !self.is_active(i) || self.commands[i].is_empty() {
+                    continue;
+                }
+
+                let position = sprite_mgr.position(self.sprite_i[i].unwrap());
+                self.start_command(i, position, nav_mgr);
+            }
+        }
+
+        if self.movement_mode == MovementMode::Free {
+            self.update_separation(collider_mgr);
+        }
+
         for i in 0..self.len() {
             if !self.is_active(i) {
                 continue;
@@ -332,7 +778,7 @@ impl PlayerUnitMgr {
             // Selection (with box)
             let collider_i = self.collider_i[i].unwrap();
             if selector_box.is_dragging() {
-                if !is_key_down(KeyCode::LeftShift) {
+                if !self.is_adding_to_selection {
                     self.is_selected[i] = false;
                 }
                 if !self.is_selected[i] && selector_box.selected_collider_i().contains(&collider_i)
@@ -341,25 +787,71 @@ impl PlayerUnitMgr {
                 }
             }
 
+            if self.is_tinted[i] != self.is_selected[i] {
+                self.is_tinted[i] = self.is_selected[i];
+                let tint = match self.is_selected[i] {
+                    true => SELECTED_TINT,
+                    false => color::WHITE,
+                };
+                sprite_mgr.set_tint(self.sprite_i[i].unwrap(), tint);
+            }
+
+            // Animation state, attacks are placeholders without targets for now
+            if self.is_attack_pressed && self.is_selected[i] {
+                self.set_state(i, UnitState::Attacking, sprite_mgr);
+            }
+            if matches!(self.state[i], UnitState::Idle | UnitState::Walking) {
+                let state = match self.commands[i].is_empty() {
+                    false => UnitState::Walking,
+                    true => UnitState::Idle,
+                };
+                self.set_state(i, state, sprite_mgr);
+            }
+
             // Movement
-            if self.move_target[i] == None {
+            if self.movement_mode == MovementMode::Tile {
+                self.update_tile_movement(i, dt, sprite_mgr, collider_mgr, nav_mgr);
                 continue;
             }
 
             let sprite_i = self.sprite_i[i].unwrap();
             let position = sprite_mgr.position(sprite_i);
-            let size = sprite_mgr.scaled_size(sprite_i);
+            let separation = (self.separation[i] * SEPARATION_WEIGHT).clamp_length_max(1.0);
+
+            // Idle units only make room for others
+            let move_target = match self.commands[i].front() {
+                Some(UnitCommand::Move(move_target)) => *move_target,
+                None => {
+                    if separation != f32::Vec2::ZERO {
+                        let translation = separation * self.move_speed[i] * dt;
+                        self.update_movement(i, &translation, sprite_mgr, collider_mgr);
+                    }
+                    continue;
+                }
+            };
 
-            // Get movement vector
-            let move_target = self.move_target[i].unwrap() - *size / 2.0 - self.anchor_offset[i];
+            // Skip reached waypoints
+            while let Some(waypoint) = self.waypoints[i].last() {
+                if position.distance(*waypoint) >= WAYPOINT_DISTANCE_TOLERANCE {
+                    break;
+                }
+                self.waypoints[i].pop();
+            }
+            let next_waypoint = self.waypoints[i].last().copied();
 
-            let distance = move_target - position;
-            if distance.length_squared() < MOVE_DISTANCE_TOLERANCE {
-                self.move_target[i] = None;
+            let distance = next_waypoint.unwrap_or(move_target) - position;
+            if next_waypoint.is_none() && distance.length_squared() < MOVE_DISTANCE_TOLERANCE {
+                self.complete_command(i, position, nav_mgr);
                 continue;
             }
 
-            self.input_move[i] = distance.normalize_or_zero();
+            // Get movement vector, steering away from nearby units
+            self.input_move[i] = (distance.normalize_or_zero() + separation).normalize_or_zero();
+
+            // Face movement direction
+            if self.input_move[i].x != 0.0 {
+                sprite_mgr.set_flip(sprite_i, self.input_move[i].x < 0.0, false);
+            }
 
             let translation_x = self.input_move[i].x * self.move_speed[i] * dt;
             let translation_y = self.input_move[i].y * self.move_speed[i] * dt;
@@ -369,11 +861,117 @@ impl PlayerUnitMgr {
                 self.update_movement(i, &translation, sprite_mgr, collider_mgr);
             }
 
+            // Crowded units near the target stop once they can't get any closer, so groups
+            // ordered to the same point don't jitter forever
+            let new_position = sprite_mgr.position(sprite_i);
+            let new_distance = move_target.distance(new_position);
+            let progress = distance.length() - new_distance;
+            let expected_progress = self.move_speed[i] * dt;
+            if next_waypoint.is_none()
+                && new_distance < self.arrival_radius[i]
+                && progress < expected_progress * SETTLED_PROGRESS_RATIO
+            {
+                self.complete_command(i, new_position, nav_mgr);
+            }
+
             // Cleanup
             self.clear_input(i);
         }
     }
 
+    /// Pushes apart the units closer than their separation range.
+    ///
+    /// Neighbours are found with the collider broadphase, in a box covering the range to the
+    /// widest unit.
+    fn update_separation(&mut self, collider_mgr: &ColliderMgr) {
+        let max_half_width = (0..self.len())
+            .filter(|i| self.is_active(*i))
+            .map(|i| collider_mgr.bbox(self.collider_i[i].unwrap()).w.abs() / 2.0)
+            .fold(0.0, f32::max);
+
+        for i in 0..self.len() {
+            self.separation[i] = f32::Vec2::ZERO;
+            if !self.is_active(i) {
+                continue;
+            }
+
+            let collider_i = self.collider_i[i].unwrap();
+            let bbox = collider_mgr.bbox(collider_i);
+            let center = bbox.center();
+            let half_width = bbox.w.abs() / 2.0;
+
+            let max_range = (half_width + max_half_width) * SEPARATION_RANGE;
+            let query_rect = Rect::new(
+                center.x - max_range,
+                center.y - max_range,
+                max_range * 2.0,
+                max_range * 2.0,
+            );
+
+            let mut separation = f32::Vec2::ZERO;
+            collider_mgr.for_each_in_rect(&query_rect, UNIT_MASK, |other_collider_i| {
+                if other_collider_i == collider_i
+                    || !self.collider_unit_map.contains_key(&other_collider_i)
+                {
+                    return;
+                }
+
+                let other_bbox = collider_mgr.bbox(other_collider_i);
+                let range = (half_width + other_bbox.w.abs() / 2.0) * SEPARATION_RANGE;
+                let offset = center - other_bbox.center();
+                let distance = offset.length();
+                if distance >= range {
+                    return;
+                }
+
+                // Units on the exact same spot split along their slot order
+                let direction = match distance > 0.0 {
+                    true => offset / distance,
+                    false => f32::Vec2::from_angle(i as f32),
+                };
+                separation += direction * (1.0 - distance / range);
+            });
+            self.separation[i] = separation;
+        }
+    }
+
+    /// Steps the unit towards the center of the next tile of its path, stopping exactly on it.
+    fn update_tile_movement(
+        &mut self,
+        index: usize,
+        dt: f32,
+        sprite_mgr: &mut SpriteMgr,
+        collider_mgr: &mut ColliderMgr,
+        nav_mgr: &NavMgr,
+    ) {
+        let target = match self.commands[index].front() {
+            Some(UnitCommand::Move(target)) => *target,
+            None => return,
+        };
+
+        let sprite_i = self.sprite_i[index].unwrap();
+        let collider_i = self.collider_i[index].unwrap();
+        let position = sprite_mgr.position(sprite_i);
+        let next_tile = self.waypoints[index].last().copied().unwrap_or(target);
+
+        let offset = next_tile - position;
+        if offset.x != 0.0 {
+            sprite_mgr.set_flip(sprite_i, offset.x < 0.0, false);
+        }
+
+        let step = self.move_speed[index] * dt;
+        if offset.length() > step {
+            let position = position + offset.normalize() * step;
+            Self::set_position(sprite_i, collider_i, position, sprite_mgr, collider_mgr);
+            return;
+        }
+
+        Self::set_position(sprite_i, collider_i, next_tile, sprite_mgr, collider_mgr);
+        if self.waypoints[index].pop().is_none() {
+            self.complete_command(index, next_tile, nav_mgr);
+        }
+    }
+
     fn update_movement(
         &mut self,
         index: usize,
@@ -384,37 +982,67 @@ impl PlayerUnitMgr {
         let sprite_i = self.sprite_i[index].unwrap();
         let collider_i = self.collider_i[index].unwrap();
 
-        let current_position = sprite_mgr.position(sprite_i);
-        let mut new_position = current_position + *translation;
+        // Move up to the first surface hit and slide along it with the rest of the motion, so
+        // fast units can't tunnel through thin walls
+        let mut motion = *translation;
+        for _ in 0..MAX_SLIDE_COUNT {
+            let hit = collider_mgr.sweep_bbox(collider_i, &motion);
+            let time = hit.map_or(1.0, |hit| hit.time);
 
-        let mov_hits = collider_mgr.intersect_bbox(
-            collider_i,
-            &translation,
-            &mut self.movement_hit_buffer[index],
-            Some(&self.collision_ignored_i),
-        );
-        if mov_hits > 0 {
-            for i in 0..mov_hits {
-                let hit = &self.movement_hit_buffer[index][i];
-                if hit.is_colliding {
-                    new_position -= hit.delta;
-                }
+            let position = sprite_mgr.position(sprite_i) + motion * time;
+            Self::set_position(sprite_i, collider_i, position, sprite_mgr, collider_mgr);
+
+            let hit = match hit {
+                Some(hit) => hit,
+                None => break,
+            };
+
+            motion *= 1.0 - time;
+            motion -= hit.normal * motion.dot(hit.normal);
+            if motion.length_squared() <= 0.0 {
+                break;
             }
         }
 
-        sprite_mgr.set_position(sprite_i, new_position);
-        collider_mgr.set_position(collider_i, new_position.x, new_position.y);
+        // Push out of overlaps the sweep ignores, like units spawned on top of each other
+        let hits = &mut self.movement_hits[index];
+        collider_mgr.intersect_bbox(collider_i, &f32::Vec2::ZERO, hits);
+        if !hits.is_empty() {
+            let mut position = sprite_mgr.position(sprite_i);
+            for hit in hits.iter() {
+                position -= hit.delta;
+            }
+            Self::set_position(sprite_i, collider_i, position, sprite_mgr, collider_mgr);
+        }
+    }
+
+    fn set_position(
+        sprite_i: SpriteHandle,
+        collider_i: ColliderHandle,
+        position: f32::Vec2,
+        sprite_mgr: &mut SpriteMgr,
+        collider_mgr: &mut ColliderMgr,
+    ) {
+        sprite_mgr.set_position(sprite_i, position);
+
+        let top_left = sprite_mgr.top_left(sprite_i);
+        collider_mgr.set_position(collider_i, top_left.x, top_left.y);
     }
 
-    pub fn render(&self, collider_mgr: &ColliderMgr) {
+    pub fn render(&self, scene_mgr: &SceneMgr, sprite_mgr: &SpriteMgr, collider_mgr: &ColliderMgr) {
+        let mut is_any_selected = false;
+
         for i in 0..self.len() {
             if !self.is_selected[i] || !self.is_active(i) {
                 continue;
             }
 
+            is_any_selected = true;
+
             // Render selection box
             let collider_i = self.collider_i[i].unwrap();
-            let bbox = collider_mgr.bbox[collider_i];
+            let sprite_i = self.sprite_i[i].unwrap();
+            let bbox = collider_mgr.bbox(collider_i);
             macroquad::shapes::draw_rectangle_lines(
                 bbox.x,
                 bbox.y,
@@ -423,12 +1051,96 @@ impl PlayerUnitMgr {
                 4.0,
                 color::YELLOW,
             );
+
+            // Paths start at the feet of the units, like when they are found
+            self.render_commands(i, sprite_mgr.position(sprite_i));
+        }
+
+        // Render move target tile
+        if !is_any_selected {
+            return;
+        }
+
+        let (grid, mouse_tile) = match (scene_mgr.active_grid(), self.mouse_tile) {
+            (Some(grid), Some(mouse_tile)) => (grid, mouse_tile),
+            _ => return,
+        };
+
+        let corners = grid.tile_corners(mouse_tile);
+        for corner_i in 0..corners.len() {
+            let start = corners[corner_i];
+            let end = corners[(corner_i + 1) % corners.len()];
+            macroquad::shapes::draw_line(start.x, start.y, end.x, end.y, 2.0, color::YELLOW);
+        }
+    }
+
+    /// Draws the path of the unit through its current and queued commands. Only the current
+    /// command follows its path around obstacles, the queued ones are drawn as straight lines.
+    fn render_commands(&self, index: usize, position: f32::Vec2) {
+        let mut start = position;
+        let mut draw_segment = |end: f32::Vec2| {
+            macroquad::shapes::draw_line(start.x, start.y, end.x, end.y, 2.0, COMMAND_PATH_COLOR);
+            start = end;
+        };
+
+        for waypoint in self.waypoints[index].iter().rev() {
+            draw_segment(*waypoint);
+        }
+        for command in &self.commands[index] {
+            let UnitCommand::Move(target) = command;
+            draw_segment(*target);
+            macroquad::shapes::draw_circle_lines(
+                target.x,
+                target.y,
+                COMMAND_TARGET_RADIUS,
+                2.0,
+                COMMAND_PATH_COLOR,
+            );
         }
     }
 
     pub fn is_active(&self, index: usize) -> bool {
         self.is_active[index]
     }
+
+    /// Sets the unit state and plays its animation.
+    pub fn set_state(&mut self, index: usize, state: UnitState, sprite_mgr: &mut SpriteMgr) {
+        self.state[index] = state;
+
+        let sprite_i = self.sprite_i[index].unwrap();
+        sprite_mgr.play(sprite_i, self.clip_i.unwrap()[state as usize]);
+    }
+}
+
+/// How units move, chosen per map with the `movement_mode` property.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum MovementMode {
+    /// Units move on continuous positions and stop anywhere close to their target.
+    Free,
+    /// Units stand on tile centers and step from tile to tile. No two units end on the same tile.
+    Tile,
+}
+
+/// Order given to a unit. Units carry out their commands one after the other.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum UnitCommand {
+    /// Walk to a position.
+    Move(f32::Vec2),
+}
+
+/// Unit action state. Each state has its own animation.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum UnitState {
+    Idle,
+    Walking,
+    /// Ordered to attack. One-shot, goes back to `Idle` when the animation finishes.
+    Attacking,
+    /// Entered a summoning circle. One-shot, goes back to `Idle` when the animation finishes.
+    Summoning,
+}
+
+impl UnitState {
+    pub const COUNT: usize = 4;
 }
 
 #[derive(Eq, PartialEq)]
@@ -1,26 +1,30 @@
 use crate::engine::{
     camera::camera::CameraMgr,
-    collision::collider::{self, ColliderMgr, Hit},
+    collision::collider::{
+        ColliderHandle, ColliderMgr, CollisionLayer, CollisionMask, Hit, HitList,
+    },
+    input::{InputAction, InputMgr},
 };
 
 use macroquad::{
     color,
-    input::{is_mouse_button_down, is_mouse_button_pressed, is_mouse_button_released, MouseButton},
     math::{f32, Rect},
 };
 
 const MAX_SELECTED_COUNT: usize = 64;
+/// Only units can be selected.
+const COLLISION_MASK: CollisionMask = CollisionMask::from_layers(&[CollisionLayer::Unit]);
 
 /// Selects colliders with an RTS style selection box.
 pub struct SelectorBox {
-    collider_i: Option<usize>,
+    collider_i: Option<ColliderHandle>,
 
     is_dragging: bool,
     is_active: bool,
     drag_start_position: f32::Vec2,
 
-    selected_collider_i: Vec<usize>,
-    selection_hit_buffer: Vec<Hit>,
+    selected_collider_i: Vec<ColliderHandle>,
+    selection_hits: HitList<Hit, MAX_SELECTED_COUNT>,
 }
 
 impl SelectorBox {
@@ -32,7 +36,7 @@ impl SelectorBox {
         let drag_start_position = f32::Vec2::ZERO;
 
         let selected_collider_i = Vec::with_capacity(MAX_SELECTED_COUNT);
-        let selection_hit_buffer = Vec::with_capacity(collider::MAX_COLLISION_COUNT);
+        let selection_hits = HitList::new();
 
         Self {
             collider_i,
@@ -42,22 +46,26 @@ impl SelectorBox {
             drag_start_position,
 
             selected_collider_i,
-            selection_hit_buffer,
+            selection_hits,
         }
     }
 
     pub fn init(&mut self, collider_mgr: &mut ColliderMgr) {
         let bbox = Rect::new(0.0, 0.0, 0.0, 0.0);
-        self.collider_i = Some(collider_mgr.add(bbox));
-        self.selection_hit_buffer = ColliderMgr::create_hit_buffer(collider::MAX_COLLISION_COUNT);
+        self.collider_i = Some(collider_mgr.add(bbox, CollisionLayer::Selector, COLLISION_MASK));
     }
 
-    pub fn input(&mut self, camera_mgr: &CameraMgr, collider_mgr: &mut ColliderMgr) {
-        if is_mouse_button_pressed(MouseButton::Left) {
+    pub fn input(
+        &mut self,
+        input_mgr: &InputMgr,
+        camera_mgr: &CameraMgr,
+        collider_mgr: &mut ColliderMgr,
+    ) {
+        if input_mgr.is_action_pressed(InputAction::Select) {
             self.start_dragging(camera_mgr, collider_mgr);
-        } else if is_mouse_button_down(MouseButton::Left) {
+        } else if input_mgr.is_action_down(InputAction::Select) {
             self.update_dragging(camera_mgr, collider_mgr);
-        } else if is_mouse_button_released(MouseButton::Left) {
+        } else if input_mgr.is_action_released(InputAction::Select) {
             self.stop_dragging(collider_mgr);
         }
     }
@@ -65,20 +73,14 @@ impl SelectorBox {
     pub fn update(&mut self, collider_mgr: &ColliderMgr) {
         self.clear_selected_collider_i();
 
-        let hit_count = collider_mgr.intersect_bbox(
+        collider_mgr.intersect_bbox(
             self.collider_i.unwrap(),
             &f32::Vec2::ZERO,
-            &mut self.selection_hit_buffer,
-            None,
+            &mut self.selection_hits,
         );
 
-        for hit_i in 0..hit_count {
-            let hit = &self.selection_hit_buffer[hit_i];
-            if !hit.is_colliding {
-                continue;
-            }
-
-            self.selected_collider_i.push(hit.collider_i);
+        for hit in &self.selection_hits {
+            self.selected_collider_i.push(hit.collider);
         }
     }
 
@@ -87,7 +89,7 @@ impl SelectorBox {
             return;
         }
 
-        let bbox = &collider_mgr.bbox[self.collider_i.unwrap()];
+        let bbox = collider_mgr.bbox(self.collider_i.unwrap());
 
         if f32::abs(bbox.w) < 1.0 || f32::abs(bbox.h) < 1.0 {
             return;
@@ -110,25 +112,19 @@ impl SelectorBox {
     }
 
     fn reset_collider(&self, collider_mgr: &mut ColliderMgr) {
-        let bbox = &mut collider_mgr.bbox[self.collider_i.unwrap()];
-        bbox.x = 0.0;
-        bbox.y = 0.0;
-        bbox.w = 0.0;
-        bbox.h = 0.0;
+        collider_mgr.set_bbox(self.collider_i.unwrap(), Rect::new(0.0, 0.0, 0.0, 0.0));
     }
 
     fn start_dragging(&mut self, camera_mgr: &CameraMgr, collider_mgr: &mut ColliderMgr) {
         self.is_dragging = true;
         let mouse_pos = camera_mgr.get_mouse_world_position();
-        let bbox = &mut collider_mgr.bbox[self.collider_i.unwrap()];
         self.drag_start_position = mouse_pos;
-        bbox.x = self.drag_start_position.x;
-        bbox.y = self.drag_start_position.y;
+        collider_mgr.set_position(self.collider_i.unwrap(), mouse_pos.x, mouse_pos.y);
     }
 
     fn update_dragging(&mut self, camera_mgr: &CameraMgr, collider_mgr: &mut ColliderMgr) {
         let mouse_pos = camera_mgr.get_mouse_world_position();
-        let bbox = &mut collider_mgr.bbox[self.collider_i.unwrap()];
+        let mut bbox = collider_mgr.bbox(self.collider_i.unwrap());
 
         if mouse_pos.x >= self.drag_start_position.x {
             bbox.x = self.drag_start_position.x;
@@ -145,6 +141,8 @@ impl SelectorBox {
             bbox.y = mouse_pos.y;
             bbox.h = self.drag_start_position.y - mouse_pos.y;
         }
+
+        collider_mgr.set_bbox(self.collider_i.unwrap(), bbox);
     }
 
     fn stop_dragging(&mut self, collider_mgr: &mut ColliderMgr) {
@@ -158,11 +156,7 @@ impl SelectorBox {
         }
     }
 
-    pub fn collider_i(&self) -> usize {
-        self.collider_i.unwrap()
-    }
-
-    pub fn selected_collider_i(&self) -> &Vec<usize> {
+    pub fn selected_collider_i(&self) -> &Vec<ColliderHandle> {
         &self.selected_collider_i
     }
 
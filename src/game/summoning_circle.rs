@@ -1,19 +1,69 @@
 use crate::engine::{
-    collision::collider::ColliderMgr,
+    collision::{
+        collider::{ColliderHandle, ColliderMgr, CollisionLayer, CollisionMask, TriggerEvent},
+        shape::ColliderShape,
+    },
+    particle::{EmitterConfig, EmitterHandle, ParticleMgr},
     scene::SceneMgr,
-    sprite::{SpriteMgr, Texture2dMgr},
+    sprite::{RenderLayer, SpriteHandle, SpriteMgr, Texture2dMgr},
 };
 
-use macroquad::math::f32;
+use macroquad::{
+    color::{self, Color},
+    math::f32,
+};
 
 const MAX_OBJECTS: usize = 512;
+/// Circles detect the units standing on them.
+const COLLISION_MASK: CollisionMask = CollisionMask::from_layers(&[CollisionLayer::Unit]);
+/// Circles are drawn right above this scene tile layer, so they stay below units.
+const GROUND_TILE_LAYER: &str = "Ground";
+/// Render order used instead when the scene has no ground layer.
+const FALLBACK_Z: f32 = -1.0;
+/// Tint of circles with units standing on them.
+const OCCUPIED_TINT: Color = Color::new(1.0, 0.8, 1.0, 1.0);
+
+/// Sparks emitted once when a circle activates.
+const ACTIVATION_BURST: EmitterConfig = EmitterConfig {
+    burst_count: 48,
+    spawn_rate: 0.0,
+    duration: Some(0.0),
+    lifetime: (0.4, 0.9),
+    velocity: (f32::Vec2::new(-120.0, -160.0), f32::Vec2::new(120.0, 40.0)),
+    acceleration: f32::Vec2::new(0.0, 200.0),
+    color: (
+        Color::new(1.0, 0.9, 0.5, 1.0),
+        Color::new(0.8, 0.2, 1.0, 0.0),
+    ),
+    size: (6.0, 2.0),
+    texture_i: None,
+};
+/// Glow rising from active circles.
+const ACTIVE_GLOW: EmitterConfig = EmitterConfig {
+    burst_count: 0,
+    spawn_rate: 12.0,
+    duration: None,
+    lifetime: (0.8, 1.6),
+    velocity: (f32::Vec2::new(-10.0, -40.0), f32::Vec2::new(10.0, -20.0)),
+    acceleration: f32::Vec2::ZERO,
+    color: (
+        Color::new(0.7, 0.3, 1.0, 0.8),
+        Color::new(0.7, 0.3, 1.0, 0.0),
+    ),
+    size: (4.0, 1.0),
+    texture_i: None,
+};
 
 pub struct SummoningCircleMgr {
     is_active: Vec<bool>,
+    /// Amount of units standing on each circle.
+    occupant_count: Vec<u32>,
 
     scene_object_i: Vec<usize>,
-    collider_i: Vec<usize>,
-    sprite_i: Vec<usize>,
+    collider_i: Vec<ColliderHandle>,
+    sprite_i: Vec<SpriteHandle>,
+    /// Glow emitter while active.
+    emitter: Vec<Option<EmitterHandle>>,
 }
 
 // TODO: make generic object manager for scenes instead of duplicating most of this code
@@ -21,19 +71,28 @@ impl SummoningCircleMgr {
     pub fn new() -> Self {
         Self {
             is_active: Vec::with_capacity(MAX_OBJECTS),
+            occupant_count: Vec::with_capacity(MAX_OBJECTS),
 
             scene_object_i: Vec::with_capacity(MAX_OBJECTS),
             collider_i: Vec::with_capacity(MAX_OBJECTS),
             sprite_i: Vec::with_capacity(MAX_OBJECTS),
+            emitter: Vec::with_capacity(MAX_OBJECTS),
         }
     }
 
-    pub fn add(&mut self, sprite_i: usize, collider_i: usize, scene_object_i: usize) -> usize {
+    pub fn add(
+        &mut self,
+        sprite_i: SpriteHandle,
+        collider_i: ColliderHandle,
+        scene_object_i: usize,
+    ) -> usize {
         self.is_active.push(false);
+        self.occupant_count.push(0);
 
         self.scene_object_i.push(scene_object_i);
         self.collider_i.push(collider_i);
         self.sprite_i.push(sprite_i);
+        self.emitter.push(None);
 
         self.len() - 1
     }
@@ -56,9 +115,19 @@ impl SummoningCircleMgr {
             )
             .await;
 
+        sprite_mgr.set_z(sprite_i, FALLBACK_Z);
+
         // Create collider
-        let collider_i = collider_mgr.add_from_sprite(sprite_i, None, sprite_mgr);
-        collider_mgr.render_bbox[collider_i] = false;
+        let collider_i = collider_mgr.add_from_sprite(
+            sprite_i,
+            None,
+            CollisionLayer::Trigger,
+            COLLISION_MASK,
+            sprite_mgr,
+        );
+        collider_mgr.set_render_bbox(collider_i, false);
+        collider_mgr.set_trigger(collider_i, true);
+        collider_mgr.set_shape(collider_i, ColliderShape::Circle);
 
         self.add(sprite_i, collider_i, scene_object_i)
     }
@@ -73,10 +142,30 @@ impl SummoningCircleMgr {
         is_active: bool,
         collider_mgr: &mut ColliderMgr,
         sprite_mgr: &mut SpriteMgr,
+        particle_mgr: &mut ParticleMgr,
     ) {
         self.is_active[index] = is_active;
         collider_mgr.set_active(self.collider_i[index], is_active);
         sprite_mgr.set_active(self.sprite_i[index], is_active);
+
+        // Particle effects. The glow disappears along with the circle, or fades out when the
+        // circle activates again and gets a new one.
+        if let Some(emitter) = self.emitter[index].take() {
+            match is_active {
+                true => particle_mgr.stop(emitter),
+                false if particle_mgr.is_valid(emitter) => particle_mgr.remove(emitter),
+                false => {}
+            }
+        }
+        if is_active {
+            let sprite_i = self.sprite_i[index];
+            let center = sprite_mgr.bounds(sprite_i).center();
+            particle_mgr.add_emitter(ACTIVATION_BURST, center);
+
+            let offset = center - sprite_mgr.position(sprite_i);
+            self.emitter[index] =
+                Some(particle_mgr.add_emitter_attached(ACTIVE_GLOW, sprite_i, offset, sprite_mgr));
+        }
     }
 
     pub async fn spawn(
@@ -85,34 +174,40 @@ impl SummoningCircleMgr {
         collider_mgr: &mut ColliderMgr,
         sprite_mgr: &mut SpriteMgr,
         texture_mgr: &mut Texture2dMgr,
+        particle_mgr: &mut ParticleMgr,
     ) {
         if scene_mgr.active_scene_id == None || scene_mgr.active_objects.len() == 0 {
             return;
         }
 
-        'scene_iter: for scene_object_i in &scene_mgr.active_objects {
-            if scene_mgr.object_class[*scene_object_i].as_ref().unwrap() == "SummoningCircle" {
-                // Existing in manager, activate it
-                for index in 0..self.len() {
-                    if self.scene_object_i[index] == *scene_object_i {
-                        self.set_active(index, true, collider_mgr, sprite_mgr);
-                        continue 'scene_iter;
-                    }
-                }
+        let layer = match scene_mgr.tile_layer_index(GROUND_TILE_LAYER) {
+            Some(layer_order) => RenderLayer::AboveTileLayer(layer_order),
+            None => RenderLayer::Foreground,
+        };
 
-                // New object, create it
-                let position = scene_mgr.object_position[*scene_object_i].unwrap();
-                let new_index = self
-                    .add_from_scene_object(
-                        position,
-                        *scene_object_i,
-                        collider_mgr,
-                        sprite_mgr,
-                        texture_mgr,
-                    )
-                    .await;
-                self.set_active(new_index, true, collider_mgr, sprite_mgr);
+        'scene_iter: for scene_object_i in scene_mgr.active_objects_of_class("SummoningCircle") {
+            // Existing in manager, activate it
+            for index in 0..self.len() {
+                if self.scene_object_i[index] == *scene_object_i {
+                    self.set_active(index, true, collider_mgr, sprite_mgr, particle_mgr);
+                    sprite_mgr.set_layer(self.sprite_i[index], layer);
+                    continue 'scene_iter;
+                }
             }
+
+            // New object, create it
+            let position = scene_mgr.object_position[*scene_object_i].unwrap();
+            let new_index = self
+                .add_from_scene_object(
+                    position,
+                    *scene_object_i,
+                    collider_mgr,
+                    sprite_mgr,
+                    texture_mgr,
+                )
+                .await;
+            self.set_active(new_index, true, collider_mgr, sprite_mgr, particle_mgr);
+            sprite_mgr.set_layer(self.sprite_i[new_index], layer);
         }
     }
 
@@ -121,24 +216,43 @@ impl SummoningCircleMgr {
         scene_mgr: &SceneMgr,
         collider_mgr: &mut ColliderMgr,
         sprite_mgr: &mut SpriteMgr,
+        particle_mgr: &mut ParticleMgr,
     ) {
-        'scene_iter: for scene_object_i in &scene_mgr.objects_to_despawn {
-            if scene_mgr.object_class[*scene_object_i].as_ref().unwrap() == "SummoningCircle" {
-                for index in 0..self.len() {
-                    if self.scene_object_i[index] == *scene_object_i && self.is_active(index) {
-                        self.set_active(index, false, collider_mgr, sprite_mgr);
-                        continue 'scene_iter;
-                    }
+        'scene_iter: for scene_object_i in scene_mgr.objects_to_despawn_of_class("SummoningCircle")
+        {
+            for index in 0..self.len() {
+                if self.scene_object_i[index] == *scene_object_i && self.is_active(index) {
+                    self.set_active(index, false, collider_mgr, sprite_mgr, particle_mgr);
+                    continue 'scene_iter;
                 }
             }
         }
     }
 
-    pub fn is_active(&self, index: usize) -> bool {
-        self.is_active[index]
+    /// Tracks the units entering and leaving circles.
+    pub fn update(&mut self, collider_mgr: &ColliderMgr, sprite_mgr: &mut SpriteMgr) {
+        for event in collider_mgr.trigger_events() {
+            let (trigger, change) = match *event {
+                TriggerEvent::Enter { trigger, .. } => (trigger, 1),
+                TriggerEvent::Exit { trigger, .. } => (trigger, -1),
+                TriggerEvent::Stay { .. } => continue,
+            };
+
+            let index = match self.collider_i.iter().position(|c| *c == trigger) {
+                Some(index) => index,
+                None => continue,
+            };
+
+            self.occupant_count[index] = self.occupant_count[index].saturating_add_signed(change);
+            let tint = match self.occupant_count[index] > 0 {
+                true => OCCUPIED_TINT,
+                false => color::WHITE,
+            };
+            sprite_mgr.set_tint(self.sprite_i[index], tint);
+        }
     }
 
-    pub fn collider_i(&self, index: usize) -> usize {
-        self.collider_i[index]
+    pub fn is_active(&self, index: usize) -> bool {
+        self.is_active[index]
     }
 }
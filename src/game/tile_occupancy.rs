@@ -0,0 +1,227 @@
+use std::collections::HashMap;
+
+use macroquad::math::IVec2;
+
+use crate::engine::{grid::MapGrid, navigation::NavGrid};
+
+/// Furthest distance, in tiles, searched for a free tile.
+const MAX_FREE_TILE_DISTANCE: i32 = 8;
+
+/// Tiles reserved by units in the tile movement mode.
+///
+/// Each unit reserves a single tile: the one it stands on when idle, or the one its last command
+/// ends on when moving. Units pass through each other on the way, but never end on the same tile.
+pub struct TileOccupancy {
+    /// Maps reserved tiles to the unit that reserved them.
+    occupant: HashMap<IVec2, usize>,
+    /// Tile reserved by each unit, indexed by unit.
+    unit_tile: Vec<Option<IVec2>>,
+}
+
+impl TileOccupancy {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            occupant: HashMap::with_capacity(capacity),
+            unit_tile: Vec::with_capacity(capacity),
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.occupant.clear();
+        self.unit_tile.clear();
+    }
+
+    /// Tile reserved by the unit, if any.
+    pub fn tile(&self, unit: usize) -> Option<IVec2> {
+        self.unit_tile.get(unit).copied().flatten()
+    }
+
+    /// Unit that reserved the tile, if any.
+    pub fn occupant(&self, tile: IVec2) -> Option<usize> {
+        self.occupant.get(&tile).copied()
+    }
+
+    /// Whether the unit can reserve the tile: it's free or already reserved by the same unit.
+    pub fn is_free_for(&self, tile: IVec2, unit: usize) -> bool {
+        self.occupant(tile).is_none_or(|occupant| occupant == unit)
+    }
+
+    /// Moves the reservation of the unit to `tile`. Returns `false`, keeping the previous
+    /// reservation, if another unit already reserved it.
+    pub fn reserve(&mut self, unit: usize, tile: IVec2) -> bool {
+        if !self.is_free_for(tile, unit) {
+            return false;
+        }
+
+        self.release(unit);
+        if unit >= self.unit_tile.len() {
+            self.unit_tile.resize(unit + 1, None);
+        }
+        self.unit_tile[unit] = Some(tile);
+        self.occupant.insert(tile, unit);
+
+        true
+    }
+
+    /// Removes the reservation of the unit, if any.
+    pub fn release(&mut self, unit: usize) {
+        if let Some(tile) = self.unit_tile.get_mut(unit).and_then(Option::take) {
+            self.occupant.remove(&tile);
+        }
+    }
+
+    /// Tile closest to `tile` the unit can reserve, inside the map and not blocked in `nav_grid`.
+    /// Returns `None` if there is none in range.
+    pub fn nearest_free_tile(
+        &self,
+        tile: IVec2,
+        unit: usize,
+        map_grid: &MapGrid,
+        nav_grid: Option<&NavGrid>,
+    ) -> Option<IVec2> {
+        let is_walkable = |tile: IVec2| match nav_grid {
+            Some(nav_grid) => {
+                !nav_grid.is_blocked(nav_grid.world_to_cell(map_grid.tile_center(tile)))
+            }
+            None => true,
+        };
+
+        // Search square rings of growing size, the closest free tile of the first ring with any
+        for distance in 0..=MAX_FREE_TILE_DISTANCE {
+            let mut closest: Option<(i32, IVec2)> = None;
+            for y in -distance..=distance {
+                for x in -distance..=distance {
+                    if x.abs() != distance && y.abs() != distance {
+                        continue;
+                    }
+
+                    let candidate = tile + IVec2::new(x, y);
+                    if !map_grid.contains_tile(candidate)
+                        || !self.is_free_for(candidate, unit)
+                        || !is_walkable(candidate)
+                    {
+                        continue;
+                    }
+
+                    let candidate_distance = x * x + y * y;
+                    if closest.is_none_or(|(distance, _)| candidate_distance < distance) {
+                        closest = Some((candidate_distance, candidate));
+                    }
+                }
+            }
+
+            if let Some((_, tile)) = closest {
+                return Some(tile);
+            }
+        }
+
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use macroquad::math::f32;
+
+    use super::*;
+    use crate::engine::grid::MapOrientation;
+
+    const TILE_SIZE: f32 = 16.0;
+
+    fn map_grid(width: i32, height: i32) -> MapGrid {
+        MapGrid {
+            orientation: MapOrientation::Orthogonal,
+            size: IVec2::new(width, height),
+            tile_size: f32::Vec2::splat(TILE_SIZE),
+        }
+    }
+
+    #[test]
+    fn reserve_fails_for_tile_of_other_unit() {
+        let mut occupancy = TileOccupancy::new(2);
+        let tile = IVec2::new(1, 1);
+        assert!(occupancy.reserve(0, tile));
+
+        // Reserving again for the same unit is fine
+        assert!(occupancy.reserve(0, tile));
+        assert!(!occupancy.is_free_for(tile, 1));
+
+        // The other unit keeps its previous reservation
+        assert!(occupancy.reserve(1, IVec2::new(2, 2)));
+        assert!(!occupancy.reserve(1, tile));
+        assert_eq!(occupancy.occupant(tile), Some(0));
+        assert_eq!(occupancy.tile(1), Some(IVec2::new(2, 2)));
+    }
+
+    #[test]
+    fn reserve_moves_previous_reservation() {
+        let mut occupancy = TileOccupancy::new(1);
+        assert!(occupancy.reserve(0, IVec2::new(1, 1)));
+        assert!(occupancy.reserve(0, IVec2::new(3, 1)));
+
+        assert_eq!(occupancy.tile(0), Some(IVec2::new(3, 1)));
+        assert_eq!(occupancy.occupant(IVec2::new(1, 1)), None);
+        assert_eq!(occupancy.occupant(IVec2::new(3, 1)), Some(0));
+    }
+
+    #[test]
+    fn release_frees_tile() {
+        let mut occupancy = TileOccupancy::new(2);
+        let tile = IVec2::new(1, 1);
+        occupancy.reserve(0, tile);
+        occupancy.release(0);
+
+        assert_eq!(occupancy.tile(0), None);
+        assert_eq!(occupancy.occupant(tile), None);
+        assert!(occupancy.reserve(1, tile));
+
+        // Releasing units without reservations does nothing
+        occupancy.release(0);
+        occupancy.release(5);
+        assert_eq!(occupancy.occupant(tile), Some(1));
+    }
+
+    #[test]
+    fn nearest_free_tile_skips_reserved_and_blocked_tiles() {
+        let map_grid = map_grid(5, 5);
+        let mut occupancy = TileOccupancy::new(2);
+        let tile = IVec2::new(2, 2);
+        assert_eq!(
+            occupancy.nearest_free_tile(tile, 0, &map_grid, None),
+            Some(tile)
+        );
+
+        // The unit's own reservation is free for it
+        occupancy.reserve(1, tile);
+        assert_eq!(
+            occupancy.nearest_free_tile(tile, 1, &map_grid, None),
+            Some(tile)
+        );
+
+        // Neighbours in scan order, skipping the blocked one above
+        let mut nav_grid =
+            NavGrid::new(f32::Vec2::ZERO, f32::Vec2::splat(TILE_SIZE), map_grid.size);
+        nav_grid.set_blocked(IVec2::new(2, 1), true);
+        assert_eq!(
+            occupancy.nearest_free_tile(tile, 0, &map_grid, Some(&nav_grid)),
+            Some(IVec2::new(1, 2))
+        );
+    }
+
+    #[test]
+    fn nearest_free_tile_stays_inside_map() {
+        let map_grid = map_grid(2, 1);
+        let mut occupancy = TileOccupancy::new(3);
+        occupancy.reserve(1, IVec2::new(0, 0));
+        assert_eq!(
+            occupancy.nearest_free_tile(IVec2::new(0, 0), 0, &map_grid, None),
+            Some(IVec2::new(1, 0))
+        );
+
+        occupancy.reserve(2, IVec2::new(1, 0));
+        assert_eq!(
+            occupancy.nearest_free_tile(IVec2::new(0, 0), 0, &map_grid, None),
+            None
+        );
+    }
+}
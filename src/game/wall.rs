@@ -1,13 +1,18 @@
-use crate::engine::{collision::collider::ColliderMgr, scene::SceneMgr};
+use crate::engine::{
+    collision::collider::{ColliderHandle, ColliderMgr, CollisionLayer, CollisionMask},
+    scene::SceneMgr,
+};
 use macroquad::math::{f32, Rect};
 
 const MAX_OBJECTS: usize = 256;
+/// Walls don't move, so they never query other colliders.
+const COLLISION_MASK: CollisionMask = CollisionMask::NONE;
 
 pub struct WallMgr {
     is_active: Vec<bool>,
 
     scene_object_i: Vec<usize>,
-    collider_i: Vec<usize>,
+    collider_i: Vec<ColliderHandle>,
 }
 
 impl WallMgr {
@@ -20,7 +25,7 @@ impl WallMgr {
         }
     }
 
-    pub fn add(&mut self, collider_i: usize, scene_object_i: usize) -> usize {
+    pub fn add(&mut self, collider_i: ColliderHandle, scene_object_i: usize) -> usize {
         self.is_active.push(false);
 
         self.collider_i.push(collider_i);
@@ -37,7 +42,7 @@ impl WallMgr {
         collider_mgr: &mut ColliderMgr,
     ) -> usize {
         let bbox = Rect::new(position.x, position.y, size.x, size.y);
-        let collider_i = collider_mgr.add(bbox);
+        let collider_i = collider_mgr.add(bbox, CollisionLayer::Wall, COLLISION_MASK);
 
         self.add(collider_i, scene_object_i)
     }
@@ -49,7 +54,7 @@ impl WallMgr {
     fn set_active(&mut self, index: usize, is_active: bool, collider_mgr: &mut ColliderMgr) {
         self.is_active[index] = is_active;
         collider_mgr.set_active(self.collider_i[index], is_active);
-        collider_mgr.render_bbox[self.collider_i[index]] = true;
+        collider_mgr.set_render_bbox(self.collider_i[index], true);
     }
 
     /// Reads objects with class `Wall` from the scene and spawn them. If they are already loaded,
@@ -59,34 +64,29 @@ impl WallMgr {
             return;
         }
 
-        'scene_iter: for scene_object_i in &scene_mgr.active_objects {
-            if scene_mgr.object_class[*scene_object_i].as_ref().unwrap() == "Wall" {
-                for index in 0..self.len() {
-                    if self.scene_object_i[index] == *scene_object_i {
-                        self.set_active(index, true, collider_mgr);
-                        continue 'scene_iter;
-                    }
+        'scene_iter: for scene_object_i in scene_mgr.active_objects_of_class("Wall") {
+            for index in 0..self.len() {
+                if self.scene_object_i[index] == *scene_object_i {
+                    self.set_active(index, true, collider_mgr);
+                    continue 'scene_iter;
                 }
+            }
 
-                let position = scene_mgr.object_position[*scene_object_i].unwrap();
-                // Assuming the wall object is a valid rectangle here
-                let size = scene_mgr.object_size[*scene_object_i].unwrap();
+            let position = scene_mgr.object_position[*scene_object_i].unwrap();
+            // Assuming the wall object is a valid rectangle here
+            let size = scene_mgr.object_size[*scene_object_i].unwrap();
 
-                let wall_i =
-                    self.add_from_scene_object(position, size, *scene_object_i, collider_mgr);
-                self.set_active(wall_i, true, collider_mgr);
-            }
+            let wall_i = self.add_from_scene_object(position, size, *scene_object_i, collider_mgr);
+            self.set_active(wall_i, true, collider_mgr);
         }
     }
 
     pub fn despawn(&mut self, scene_mgr: &SceneMgr, collider_mgr: &mut ColliderMgr) {
-        'scene_iter: for scene_object_i in &scene_mgr.objects_to_despawn {
-            if scene_mgr.object_class[*scene_object_i].as_ref().unwrap() == "Wall" {
-                for index in 0..self.len() {
-                    if self.scene_object_i[index] == *scene_object_i && self.is_active[index] {
-                        self.set_active(index, false, collider_mgr);
-                        continue 'scene_iter;
-                    }
+        'scene_iter: for scene_object_i in scene_mgr.objects_to_despawn_of_class("Wall") {
+            for index in 0..self.len() {
+                if self.scene_object_i[index] == *scene_object_i && self.is_active[index] {
+                    self.set_active(index, false, collider_mgr);
+                    continue 'scene_iter;
                 }
             }
         }
@@ -1,10 +1,14 @@
 use crate::{
     engine::{
+        asset,
         camera::camera::CameraMgr,
         collision::collider::ColliderMgr,
         diagnostics::DiagnosticsMgr,
+        input::{InputAction, InputMgr},
+        navigation::NavMgr,
+        particle::ParticleMgr,
         scene::SceneMgr,
-        sprite::{SpriteMgr, Texture2dMgr},
+        sprite::{RenderLayer, SpriteMgr, Texture2dMgr},
         tile::TileMgr,
     },
     game::{
@@ -15,10 +19,9 @@ use crate::{
 
 use crate::game::{player::PlayerUnitMgr, wall::WallMgr};
 
-use macroquad::{
-    color, input::is_key_pressed, miniquad::window::quit, time::get_frame_time,
-    window::clear_background,
-};
+use macroquad::{color, miniquad::window::quit, time::get_frame_time, window::clear_background};
+
+const INPUT_BINDINGS_PATH: &str = "input/bindings.txt";
 
 /// Main game manager. Owns all individual system managers.
 pub struct GameMgr {
@@ -27,8 +30,11 @@ pub struct GameMgr {
     pub scene_mgr: SceneMgr,
     pub sprite_mgr: SpriteMgr,
     pub collider_mgr: ColliderMgr,
+    pub nav_mgr: NavMgr,
+    pub particle_mgr: ParticleMgr,
     pub camera_mgr: CameraMgr,
     pub diagnostics_mgr: DiagnosticsMgr,
+    pub input_mgr: InputMgr,
 
     pub player_unit_mgr: PlayerUnitMgr,
     pub wall_mgr: WallMgr,
@@ -41,6 +47,8 @@ pub struct GameMgr {
 
     // TODO: consider an alternative to passing around clones of the `pc_assets_folder`.
     pub pc_assets_folder: Option<String>,
+
+    is_asset_report_logged: bool,
 }
 
 impl GameMgr {
@@ -50,8 +58,11 @@ impl GameMgr {
         let scene_mgr = SceneMgr::new();
         let sprite_mgr = SpriteMgr::new();
         let collider_mgr = ColliderMgr::new();
+        let nav_mgr = NavMgr::new();
+        let particle_mgr = ParticleMgr::new();
         let camera_mgr = CameraMgr::new();
         let diagnostics_mgr = DiagnosticsMgr::new();
+        let input_mgr = InputMgr::new();
 
         let player_unit_mgr = PlayerUnitMgr::new();
         let wall_mgr = WallMgr::new();
@@ -67,9 +78,12 @@ impl GameMgr {
             tile_mgr,
             sprite_mgr,
             collider_mgr,
+            nav_mgr,
+            particle_mgr,
             scene_mgr,
             camera_mgr,
             diagnostics_mgr,
+            input_mgr,
 
             player_unit_mgr,
             wall_mgr,
@@ -81,23 +95,38 @@ impl GameMgr {
             game_logic,
 
             pc_assets_folder,
+
+            is_asset_report_logged: false,
         }
     }
 
     pub async fn init(&mut self) {
         self.diagnostics_mgr.init();
+        self.input_mgr.load_bindings(INPUT_BINDINGS_PATH).await;
         self.scene_mgr.init(self.pc_assets_folder.clone()).await;
         self.camera_mgr.init();
 
         self.selector_box.init(&mut self.collider_mgr);
 
-        self.game_logic
-            .init(
-                &mut self.scene_mgr,
-                &mut self.tile_mgr,
-                &mut self.texture2d_mgr,
-            )
+        self.game_logic.init(&mut self.scene_mgr);
+    }
+
+    pub async fn load(&mut self) {
+        self.scene_mgr
+            .load(&mut self.tile_mgr, &mut self.texture2d_mgr)
             .await;
+
+        // Report missing assets once the startup loading finishes
+        if !self.is_asset_report_logged && !self.scene_mgr.is_loading() {
+            asset::log_report(
+                self.scene_mgr
+                    .failed_assets()
+                    .iter()
+                    .chain(self.texture2d_mgr.failed_assets())
+                    .chain(self.input_mgr.failed_assets()),
+            );
+            self.is_asset_report_logged = true;
+        }
     }
 
     pub async fn spawn(&mut self) {
@@ -112,6 +141,7 @@ impl GameMgr {
                 &mut self.collider_mgr,
                 &mut self.sprite_mgr,
                 &mut self.texture2d_mgr,
+                &mut self.particle_mgr,
             )
             .await;
         self.wall_mgr.spawn(&self.scene_mgr, &mut self.collider_mgr);
@@ -121,8 +151,6 @@ impl GameMgr {
                 &mut self.sprite_mgr,
                 &mut self.collider_mgr,
                 &mut self.texture2d_mgr,
-                &self.selector_box,
-                &self.summoning_circle_mgr,
             )
             .await;
 
@@ -139,6 +167,7 @@ impl GameMgr {
             &self.scene_mgr,
             &mut self.collider_mgr,
             &mut self.sprite_mgr,
+            &mut self.particle_mgr,
         );
         self.wall_mgr
             .despawn(&self.scene_mgr, &mut self.collider_mgr);
@@ -146,30 +175,43 @@ impl GameMgr {
             &self.scene_mgr,
             &mut self.collider_mgr,
             &mut self.sprite_mgr,
+            &mut self.texture2d_mgr,
         );
 
         self.scene_mgr.despawn();
     }
 
     pub fn input(&mut self) {
-        if is_key_pressed(macroquad::input::KeyCode::Q) {
+        if self.input_mgr.is_action_pressed(InputAction::Quit) {
             quit();
         }
 
         self.selector_box
-            .input(&self.camera_mgr, &mut self.collider_mgr);
+            .input(&self.input_mgr, &self.camera_mgr, &mut self.collider_mgr);
 
-        self.player_unit_mgr
-            .input(&self.collider_mgr, &self.camera_mgr);
+        // After spawning and despawning changed the static colliders
+        self.nav_mgr.update(&self.scene_mgr, &self.collider_mgr);
+
+        self.player_unit_mgr.input(
+            &self.scene_mgr,
+            &self.sprite_mgr,
+            &self.collider_mgr,
+            &self.camera_mgr,
+            &self.nav_mgr,
+            &self.input_mgr,
+        );
 
         self.game_logic
-            .input(&mut self.scene_mgr, &mut self.tile_mgr);
+            .input(&self.input_mgr, &mut self.scene_mgr, &mut self.tile_mgr);
     }
 
     pub fn update(&mut self) {
         let dt = get_frame_time();
 
-        self.texture2d_mgr.update();
+        self.texture2d_mgr.update(self.scene_mgr.is_loading());
+        self.sprite_mgr.update(dt);
+
+        self.game_logic.update(&mut self.scene_mgr, &self.tile_mgr);
 
         self.selector_box.update(&self.collider_mgr);
         self.player_unit_mgr.update(
@@ -177,18 +219,44 @@ impl GameMgr {
             &self.selector_box,
             &mut self.sprite_mgr,
             &mut self.collider_mgr,
+            &self.nav_mgr,
         );
+
+        // After every system moved its colliders
+        self.collider_mgr.update_triggers();
+        self.summoning_circle_mgr
+            .update(&self.collider_mgr, &mut self.sprite_mgr);
+
+        // After every system moved its sprites
+        self.particle_mgr.update(dt, &self.sprite_mgr);
+        self.sprite_mgr.update_render_order();
     }
 
     pub fn render(&self) {
         clear_background(color::RED);
 
-        self.scene_mgr.render(&self.texture2d_mgr);
-        self.sprite_mgr.render(&self.texture2d_mgr);
+        // Sprite layers are interleaved with the scene tile layers
+        self.sprite_mgr
+            .render_layer(RenderLayer::Background, &self.texture2d_mgr);
+        for layer_order in 0..self.scene_mgr.tile_layer_count() {
+            self.scene_mgr
+                .render_tile_layer(layer_order, &self.texture2d_mgr);
+            self.sprite_mgr.render_layer(
+                RenderLayer::AboveTileLayer(layer_order),
+                &self.texture2d_mgr,
+            );
+        }
+        self.sprite_mgr
+            .render_layer(RenderLayer::Foreground, &self.texture2d_mgr);
+        self.particle_mgr.render(&self.texture2d_mgr);
+
         self.collider_mgr.render();
+        self.sprite_mgr
+            .render_layer(RenderLayer::Overlay, &self.texture2d_mgr);
 
         self.selector_box.render(&self.collider_mgr);
-        self.player_unit_mgr.render(&self.collider_mgr);
+        self.player_unit_mgr
+            .render(&self.scene_mgr, &self.sprite_mgr, &self.collider_mgr);
 
         self.loading_screen
             .render(&self.scene_mgr, &self.camera_mgr);
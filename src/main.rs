@@ -3,6 +3,7 @@ mod game;
 mod game_manager;
 
 use engine::{
+    collision::benchmark::run_broadphase_benchmark,
     file,
     logging::log::{set_active_log_level, LogLevel},
 };
@@ -13,6 +14,11 @@ use macroquad::window::next_frame;
 async fn main() {
     set_active_log_level(LogLevel::DEBUG);
 
+    if std::env::args().any(|arg| arg == "--collider-benchmark") {
+        run_broadphase_benchmark();
+        return;
+    }
+
     let pc_assets_folder = file::set_pc_assets_folder("assets");
 
     let mut game_mgr = GameMgr::new(pc_assets_folder);
@@ -20,6 +26,8 @@ async fn main() {
     game_mgr.init().await;
 
     loop {
+        game_mgr.load().await;
+
         game_mgr.despawn();
         game_mgr.spawn().await;
 